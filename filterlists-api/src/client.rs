@@ -0,0 +1,495 @@
+//! Client for the FilterLists API, caching responses on disk so that the directory stays usable
+//! when the API is slow or unreachable.
+use crate::{
+    Filter, FilterArgs, FilterDetails, FilterLanguage, FilterLicense, FilterListAPIError,
+    FilterListError, FilterListSyntax, FilterMaintainer, FilterSoftware, FilterTag,
+    FILTERLISTS_API_URL,
+};
+use serde::de::DeserializeOwned;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+use tokio::fs;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_RETRIES: u32 = 2;
+/// Delay before the first retry, doubled on every following one.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+/// The directory changes slowly, a day old data is good enough.
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone)]
+struct Cache {
+    directory: PathBuf,
+    ttl: Duration,
+}
+
+impl Cache {
+    fn get_path(&self, path: &str) -> PathBuf {
+        self.directory
+            .join(format!("{}.json", path.replace('/', "_")))
+    }
+
+    /// Returns the cached response to `path`, as long as it isn't older than `max_age`.
+    async fn read(&self, path: &str, max_age: Option<Duration>) -> Option<String> {
+        let cache_path = self.get_path(path);
+
+        if let Some(max_age) = max_age {
+            let modified = fs::metadata(&cache_path).await.ok()?.modified().ok()?;
+
+            if SystemTime::now().duration_since(modified).ok()? > max_age {
+                return None;
+            }
+        }
+
+        fs::read_to_string(cache_path).await.ok()
+    }
+
+    /// Caches `body` as the response to `path`. Each write gets its own temporary file, as
+    /// clients of this process or of others may fetch the same path at the same time.
+    async fn write(&self, path: &str, body: &str) {
+        static WRITE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let cache_path = self.get_path(path);
+        let temporary_path = cache_path.with_extension(format!(
+            "{}.{}.tmp",
+            std::process::id(),
+            WRITE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let result = async {
+            fs::create_dir_all(&self.directory).await?;
+            fs::write(&temporary_path, body).await?;
+            fs::rename(&temporary_path, &cache_path).await
+        }
+        .await;
+
+        if let Err(err) = result {
+            let _ = fs::remove_file(&temporary_path).await;
+            log::warn!("Unable to cache {}: {}", cache_path.display(), err);
+        }
+    }
+}
+
+/// Client for the FilterLists API.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), filterlists_api::FilterListError> {
+/// let client = filterlists_api::FilterListsClient::default()
+///     .with_cache("/var/cache/filterlists", filterlists_api::DEFAULT_CACHE_TTL);
+/// let filters = client.get_filters().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FilterListsClient {
+    base_url: String,
+    http_client: reqwest::Client,
+    retries: u32,
+    cache: Option<Cache>,
+}
+
+impl Default for FilterListsClient {
+    fn default() -> Self {
+        Self::new(FILTERLISTS_API_URL)
+    }
+}
+
+impl FilterListsClient {
+    /// Returns a client for the API at `base_url`, such as a local stand-in for testing.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            http_client: build_http_client(DEFAULT_TIMEOUT),
+            retries: DEFAULT_RETRIES,
+            cache: None,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.http_client = build_http_client(timeout);
+        self
+    }
+
+    /// Number of times failed requests are retried, requests rejected by the API aside.
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Caches responses in `directory` for `ttl`. Expired responses are still served when the
+    /// API can't be reached.
+    pub fn with_cache(mut self, directory: impl Into<PathBuf>, ttl: Duration) -> Self {
+        self.cache = Some(Cache {
+            directory: directory.into(),
+            ttl,
+        });
+        self
+    }
+
+    pub async fn get_filters(&self) -> Result<Vec<Filter>, FilterListError> {
+        self.get("lists").await
+    }
+
+    /// Gets the details of the FilterList.
+    pub async fn get_filter_information(
+        &self,
+        filter: FilterArgs,
+    ) -> Result<FilterDetails, FilterListError> {
+        let id = match filter {
+            FilterArgs::U32(id) => id,
+            FilterArgs::Filter(filter) => filter.id,
+        };
+        self.get(&format!("lists/{id}")).await
+    }
+
+    /// Gets the syntaxes of the FilterLists.
+    pub async fn get_syntaxes(&self) -> Result<Vec<FilterListSyntax>, FilterListError> {
+        self.get("syntaxes").await
+    }
+
+    /// Gets the licenses applied to the FilterLists.
+    pub async fn get_licenses(&self) -> Result<Vec<FilterLicense>, FilterListError> {
+        self.get("licenses").await
+    }
+
+    /// Gets the software that subscribes to the FilterLists.
+    pub async fn get_software_list(&self) -> Result<Vec<FilterSoftware>, FilterListError> {
+        self.get("software").await
+    }
+
+    /// Gets the languages targeted by the FilterLists.
+    pub async fn get_languages(&self) -> Result<Vec<FilterLanguage>, FilterListError> {
+        self.get("languages").await
+    }
+
+    /// Gets the tags of the FilterLists.
+    pub async fn get_tags(&self) -> Result<Vec<FilterTag>, FilterListError> {
+        self.get("tags").await
+    }
+
+    /// Gets the maintainers of the FilterLists.
+    pub async fn get_maintainers(&self) -> Result<Vec<FilterMaintainer>, FilterListError> {
+        self.get("maintainers").await
+    }
+
+    async fn get<T>(&self, path: &str) -> Result<T, FilterListError>
+    where
+        T: DeserializeOwned,
+    {
+        if let Some(cache) = &self.cache {
+            if let Some(body) = cache.read(path, Some(cache.ttl)).await {
+                if let Ok(data) = serde_json::from_str(&body) {
+                    return Ok(data);
+                }
+            }
+        }
+
+        let err = match self.fetch(path).await {
+            Ok(body) => {
+                let data = serde_json::from_str(&body)?;
+
+                if let Some(cache) = &self.cache {
+                    cache.write(path, &body).await;
+                }
+
+                return Ok(data);
+            }
+            Err(err) => err,
+        };
+
+        if let Some(cache) = self.cache.as_ref().filter(|_| is_unreachable(&err)) {
+            if let Some(body) = cache.read(path, None).await {
+                if let Ok(data) = serde_json::from_str(&body) {
+                    log::warn!(
+                        "FilterLists is unreachable, serving stale {}: {}",
+                        path,
+                        err
+                    );
+                    return Ok(data);
+                }
+            }
+        }
+
+        Err(err)
+    }
+
+    async fn fetch(&self, path: &str) -> Result<String, FilterListError> {
+        let url = format!("{}/{}", self.base_url, path);
+        let mut retry_delay = RETRY_DELAY;
+        let mut retries = self.retries;
+
+        loop {
+            match self.fetch_once(&url).await {
+                Err(err) if retries > 0 && is_unreachable(&err) => {
+                    log::debug!("Unable to fetch {}, retrying: {}", url, err);
+
+                    tokio::time::sleep(retry_delay).await;
+                    retry_delay *= 2;
+                    retries -= 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn fetch_once(&self, url: &str) -> Result<String, FilterListError> {
+        let response = self.http_client.get(url).send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+
+        if status.is_success() {
+            return Ok(body);
+        }
+
+        match serde_json::from_str::<FilterListAPIError>(&body) {
+            Ok(error) => Err(FilterListError::APIError(error)),
+            Err(_) => Err(FilterListError::StatusError(status.as_u16())),
+        }
+    }
+}
+
+fn build_http_client(timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .expect("Unable to build FilterLists HTTP client")
+}
+
+/// Whether the API couldn't be reached, as opposed to rejecting the request.
+fn is_unreachable(err: &FilterListError) -> bool {
+    match err {
+        FilterListError::RequestError(_) => true,
+        FilterListError::APIError(error) => error.status >= 500,
+        FilterListError::StatusError(status) => *status >= 500,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Nothing listens on this port.
+    const UNREACHABLE_URL: &str = "http://127.0.0.1:1";
+    const TAGS: &str = r#"[{"id":1,"name":"ads","filterListIds":[1,2]}]"#;
+
+    fn get_cache_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("filterlists-api-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+
+        directory
+    }
+
+    /// Caches `TAGS`, as if they had been fetched `age` ago.
+    fn write_cached_tags(directory: &PathBuf, age: Duration) {
+        std::fs::create_dir_all(directory).unwrap();
+
+        let path = directory.join("tags.json");
+        std::fs::write(&path, TAGS).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - age)
+            .unwrap();
+    }
+
+    /// Serves every request with `status` and `body`, returning the url of the stand-in API and
+    /// the number of requests it received.
+    async fn serve(status: &'static str, body: &'static str) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let requests_clone = requests.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _address) = listener.accept().await.unwrap();
+                requests_clone.fetch_add(1, Ordering::SeqCst);
+
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buffer).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => request.extend_from_slice(&buffer[..read]),
+                    }
+                }
+
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        (url, requests)
+    }
+
+    fn tag_names(tags: &[FilterTag]) -> Vec<&str> {
+        tags.iter().map(|tag| tag.name.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_fresh_cache() {
+        let cache_directory = get_cache_directory("fresh");
+        write_cached_tags(&cache_directory, Duration::ZERO);
+
+        let (url, requests) = serve("200 OK", "[]").await;
+        let client = FilterListsClient::new(url).with_cache(&cache_directory, DEFAULT_CACHE_TTL);
+
+        assert_eq!(tag_names(&client.get_tags().await.unwrap()), vec!["ads"]);
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+
+        std::fs::remove_dir_all(cache_directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_expired_cache_is_refreshed() {
+        let cache_directory = get_cache_directory("expired");
+        write_cached_tags(&cache_directory, 2 * DEFAULT_CACHE_TTL);
+
+        let (url, requests) = serve(
+            "200 OK",
+            r#"[{"id":2,"name":"privacy","filterListIds":[]}]"#,
+        )
+        .await;
+        let client = FilterListsClient::new(url).with_cache(&cache_directory, DEFAULT_CACHE_TTL);
+
+        assert_eq!(
+            tag_names(&client.get_tags().await.unwrap()),
+            vec!["privacy"]
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // The response replaced the cached one.
+        let client = FilterListsClient::new(UNREACHABLE_URL)
+            .with_retries(0)
+            .with_cache(&cache_directory, DEFAULT_CACHE_TTL);
+        assert_eq!(
+            tag_names(&client.get_tags().await.unwrap()),
+            vec!["privacy"]
+        );
+
+        std::fs::remove_dir_all(cache_directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stale_cache_when_unreachable() {
+        let cache_directory = get_cache_directory("unreachable");
+        write_cached_tags(&cache_directory, 2 * DEFAULT_CACHE_TTL);
+
+        let client = FilterListsClient::new(UNREACHABLE_URL)
+            .with_retries(0)
+            .with_cache(&cache_directory, DEFAULT_CACHE_TTL);
+        assert_eq!(tag_names(&client.get_tags().await.unwrap()), vec!["ads"]);
+
+        // Without a cached copy, the failure is reported.
+        assert!(matches!(
+            client.get_languages().await,
+            Err(FilterListError::RequestError(_))
+        ));
+
+        std::fs::remove_dir_all(cache_directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stale_cache_on_server_errors() {
+        let cache_directory = get_cache_directory("server-error");
+        write_cached_tags(&cache_directory, 2 * DEFAULT_CACHE_TTL);
+
+        let (url, requests) = serve("503 Service Unavailable", "").await;
+        let client = FilterListsClient::new(url)
+            .with_retries(1)
+            .with_cache(&cache_directory, DEFAULT_CACHE_TTL);
+
+        assert_eq!(tag_names(&client.get_tags().await.unwrap()), vec!["ads"]);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        std::fs::remove_dir_all(cache_directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_errors() {
+        let cache_directory = get_cache_directory("client-error");
+        write_cached_tags(&cache_directory, 2 * DEFAULT_CACHE_TTL);
+
+        let (url, requests) = serve("404 Not Found", "").await;
+        let client = FilterListsClient::new(url)
+            .with_retries(2)
+            .with_cache(&cache_directory, DEFAULT_CACHE_TTL);
+
+        assert!(matches!(
+            client.get_tags().await,
+            Err(FilterListError::StatusError(404))
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        std::fs::remove_dir_all(cache_directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_api_errors() {
+        let (url, requests) = serve(
+            "400 Bad Request",
+            r#"{"type":"validation","title":"Bad id","status":400,"traceId":"1"}"#,
+        )
+        .await;
+        let client = FilterListsClient::new(url).with_retries(2);
+
+        match client.get_tags().await {
+            Err(FilterListError::APIError(error)) => assert_eq!(error.status, 400),
+            _ => panic!("expected an API error"),
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_cache_writes() {
+        let cache_directory = get_cache_directory("concurrent-writes");
+        let cache = Cache {
+            directory: cache_directory.clone(),
+            ttl: DEFAULT_CACHE_TTL,
+        };
+        let bodies = (0..16)
+            .map(|index| format!("[{}]", index).repeat(10_000))
+            .collect::<Vec<_>>();
+
+        let writes = bodies
+            .iter()
+            .cloned()
+            .map(|body| {
+                let cache = cache.clone();
+                tokio::spawn(async move { cache.write("tags", &body).await })
+            })
+            .collect::<Vec<_>>();
+        for write in writes {
+            write.await.unwrap();
+        }
+
+        // One of the writes wins as a whole, and no temporary file is left behind.
+        let cached = cache.read("tags", None).await.unwrap();
+        assert!(bodies.contains(&cached));
+        assert_eq!(std::fs::read_dir(&cache_directory).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(cache_directory).unwrap();
+    }
+
+    #[test]
+    fn test_is_unreachable() {
+        assert!(is_unreachable(&FilterListError::StatusError(502)));
+        assert!(!is_unreachable(&FilterListError::StatusError(404)));
+        assert!(!is_unreachable(&FilterListError::SerializationError(
+            serde_json::from_str::<u32>("").unwrap_err()
+        )));
+    }
+}
@@ -211,6 +211,8 @@ pub enum FilterListError {
     RequestError(#[from] reqwest::Error),
     #[error("Serialization error: {0}")]
     SerializationError(#[from] serde_json::Error),
+    #[error("API responded with status {0}")]
+    StatusError(u16),
     #[error("Unknown error: {0}")]
     GenericError(#[from] Box<dyn std::error::Error + Send + Sync>),
 }
@@ -7,6 +7,10 @@ pub use self::dtypes::*;
 mod get;
 #[cfg(any(feature = "reqwasm", feature = "reqwest"))]
 use crate::get::_get;
+#[cfg(feature = "reqwest")]
+mod client;
+#[cfg(feature = "reqwest")]
+pub use self::client::*;
 
 pub const FILTERLISTS_API_URL: &str = "https://api.filterlists.com";
 
@@ -26,9 +30,9 @@ pub async fn get_filter_information(filter: FilterArgs) -> Result<FilterDetails,
 }
 
 #[cfg(any(feature = "reqwasm", feature = "reqwest"))]
-/// Gets the tags of the FilterLists.
-pub async fn get_syntaxes() -> Result<Vec<Filter>, FilterListError> {
-    _get::<Vec<Filter>>(&format!("{FILTERLISTS_API_URL}/syntaxes")).await
+/// Gets the syntaxes of the FilterLists.
+pub async fn get_syntaxes() -> Result<Vec<FilterListSyntax>, FilterListError> {
+    _get::<Vec<FilterListSyntax>>(&format!("{FILTERLISTS_API_URL}/syntaxes")).await
 }
 
 #[cfg(any(feature = "reqwasm", feature = "reqwest"))]
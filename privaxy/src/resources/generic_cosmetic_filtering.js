@@ -0,0 +1,135 @@
+(function () {
+  "use strict";
+
+  var endpoint = "#{endpoint}#";
+  var seenIds = new Set(#{ids}#);
+  var seenClasses = new Set(#{classes}#);
+
+  var pendingIds = new Set();
+  var pendingClasses = new Set();
+  var flushScheduled = false;
+  var style = null;
+
+  // Avoid flooding privaxy with requests on pages that mutate a lot.
+  var FLUSH_DELAY_MS = 50;
+  var MAX_BATCH_SIZE = 500;
+
+  function applySelectors(selectors) {
+    if (selectors.length === 0) {
+      return;
+    }
+
+    if (style === null || !style.isConnected) {
+      style = document.createElement("style");
+      (document.head || document.documentElement).appendChild(style);
+    }
+
+    style.textContent +=
+      selectors.join(",\n") + " { display: none !important; }\n";
+  }
+
+  function flush() {
+    flushScheduled = false;
+
+    if (pendingIds.size === 0 && pendingClasses.size === 0) {
+      return;
+    }
+
+    var ids = Array.from(pendingIds).slice(0, MAX_BATCH_SIZE);
+    var classes = Array.from(pendingClasses).slice(0, MAX_BATCH_SIZE);
+    ids.forEach(function (id) {
+      pendingIds.delete(id);
+    });
+    classes.forEach(function (className) {
+      pendingClasses.delete(className);
+    });
+
+    fetch(endpoint, {
+      method: "POST",
+      credentials: "omit",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify({
+        url: location.href,
+        ids: ids,
+        classes: classes,
+      }),
+    })
+      .then(function (response) {
+        return response.ok ? response.json() : [];
+      })
+      .then(applySelectors)
+      .catch(function () {});
+
+    if (pendingIds.size > 0 || pendingClasses.size > 0) {
+      scheduleFlush();
+    }
+  }
+
+  function scheduleFlush() {
+    if (!flushScheduled) {
+      flushScheduled = true;
+      setTimeout(flush, FLUSH_DELAY_MS);
+    }
+  }
+
+  function collect(element) {
+    if (element.id && !seenIds.has(element.id)) {
+      seenIds.add(element.id);
+      pendingIds.add(element.id);
+    }
+
+    var classList = element.classList;
+    if (classList) {
+      for (var i = 0; i < classList.length; i++) {
+        var className = classList[i];
+        if (!seenClasses.has(className)) {
+          seenClasses.add(className);
+          pendingClasses.add(className);
+        }
+      }
+    }
+  }
+
+  function collectTree(root) {
+    if (root.nodeType !== Node.ELEMENT_NODE) {
+      return;
+    }
+
+    collect(root);
+
+    var descendants = root.querySelectorAll("[id],[class]");
+    for (var i = 0; i < descendants.length; i++) {
+      collect(descendants[i]);
+    }
+  }
+
+  var observer = new MutationObserver(function (mutations) {
+    for (var i = 0; i < mutations.length; i++) {
+      var mutation = mutations[i];
+
+      if (mutation.type === "attributes") {
+        collect(mutation.target);
+        continue;
+      }
+
+      for (var j = 0; j < mutation.addedNodes.length; j++) {
+        collectTree(mutation.addedNodes[j]);
+      }
+    }
+
+    if (pendingIds.size > 0 || pendingClasses.size > 0) {
+      scheduleFlush();
+    }
+  });
+
+  observer.observe(document.documentElement, {
+    childList: true,
+    subtree: true,
+    attributes: true,
+    attributeFilter: ["id", "class"],
+  });
+
+  // Scripts that ran before this one may already have inserted elements.
+  collectTree(document.documentElement);
+  scheduleFlush();
+})();
@@ -0,0 +1,543 @@
+(function () {
+  "use strict";
+
+  var endpoint = "#{endpoint}#";
+  var nonce = "#{nonce}#";
+  var selectors = #{selectors}#;
+
+  var HIDDEN_ATTRIBUTE = "data-privaxy-hidden";
+  var RUN_DELAY_MS = 50;
+  var REPORT_DELAY_MS = 1000;
+
+  var OPERATORS = [
+    "-abp-contains",
+    "-abp-has",
+    "contains",
+    "has-text",
+    "has",
+    "if-not",
+    "if",
+    "matches-attr",
+    "matches-css-after",
+    "matches-css-before",
+    "matches-css",
+    "matches-path",
+    "min-text-length",
+    "not",
+    "nth-ancestor",
+    "others",
+    "remove-attr",
+    "remove-class",
+    "remove",
+    "style",
+    "upward",
+    "watch-attr",
+    "xpath",
+  ];
+
+  var ALIASES = {
+    "-abp-contains": "has-text",
+    contains: "has-text",
+    "-abp-has": "has",
+    if: "has",
+    "if-not": "not",
+    "nth-ancestor": "upward",
+  };
+
+  var ACTIONS = ["remove", "remove-attr", "remove-class", "style"];
+
+  // Parsing
+
+  // Returns the index of the parenthesis closing the argument starting at `start`.
+  function findArgumentEnd(text, start) {
+    var depth = 1;
+    var quote = null;
+    var i = start;
+
+    // Regular expressions may contain unbalanced parentheses.
+    if (text[i] === "/") {
+      for (i = i + 1; i < text.length; i++) {
+        if (text[i] === "\\") {
+          i++;
+        } else if (text[i] === "/") {
+          i++;
+          break;
+        }
+      }
+    }
+
+    for (; i < text.length; i++) {
+      var character = text[i];
+
+      if (character === "\\") {
+        i++;
+      } else if (quote !== null) {
+        if (character === quote) {
+          quote = null;
+        }
+      } else if (character === '"' || character === "'") {
+        quote = character;
+      } else if (character === "(") {
+        depth++;
+      } else if (character === ")") {
+        depth--;
+        if (depth === 0) {
+          return i;
+        }
+      }
+    }
+
+    return -1;
+  }
+
+  function operatorAt(text, index) {
+    if (text[index] !== ":") {
+      return null;
+    }
+
+    for (var i = 0; i < OPERATORS.length; i++) {
+      if (text.startsWith(OPERATORS[i] + "(", index + 1)) {
+        return OPERATORS[i];
+      }
+    }
+
+    return null;
+  }
+
+  // Compiles a selector into a list of `[operator, argument]` tasks.
+  function compile(text) {
+    var tasks = [];
+    var css = "";
+    var depth = 0;
+    var quote = null;
+    var i = 0;
+
+    function flushCss() {
+      if (css.trim() !== "") {
+        tasks.push(["css", css.trim()]);
+      }
+      css = "";
+    }
+
+    while (i < text.length) {
+      var character = text[i];
+      var operator = depth === 0 && quote === null ? operatorAt(text, i) : null;
+
+      if (operator === null) {
+        if (character === "\\") {
+          css += text.slice(i, i + 2);
+          i += 2;
+          continue;
+        }
+        if (quote !== null) {
+          if (character === quote) {
+            quote = null;
+          }
+        } else if (character === '"' || character === "'") {
+          quote = character;
+        } else if (character === "(" || character === "[") {
+          depth++;
+        } else if (character === ")" || character === "]") {
+          depth--;
+        }
+        css += character;
+        i++;
+        continue;
+      }
+
+      var argumentStart = i + operator.length + 2;
+      var argumentEnd = findArgumentEnd(text, argumentStart);
+      if (argumentEnd === -1) {
+        throw new Error("Unbalanced parentheses");
+      }
+
+      // `div:has-text(...)` applies to the `div` elements, the css part has to be
+      // evaluated first.
+      flushCss();
+
+      var argument = text.slice(argumentStart, argumentEnd).trim();
+      operator = ALIASES[operator] || operator;
+
+      if (operator === "has" || operator === "not") {
+        tasks.push([operator, compile(argument)]);
+      } else {
+        tasks.push([operator, argument]);
+      }
+
+      i = argumentEnd + 1;
+    }
+
+    flushCss();
+
+    return tasks;
+  }
+
+  function toRegExp(argument) {
+    var match = /^\/(.+)\/([gimsu]*)$/.exec(argument);
+    if (match !== null) {
+      return new RegExp(match[1], match[2]);
+    }
+
+    return new RegExp(argument.replace(/[.*+?^${}()|[\]\\]/g, "\\$&"));
+  }
+
+  function unquote(value) {
+    var match = /^(["'])(.*)\1$/.exec(value.trim());
+    return match !== null ? match[2] : value.trim();
+  }
+
+  // Evaluation
+
+  function querySubtree(node, css) {
+    // Combinators, such as `> span`, are relative to the node.
+    if (/^[>+~]/.test(css)) {
+      if (css[0] === ">") {
+        return Array.from(node.querySelectorAll(":scope " + css));
+      }
+
+      // Siblings are not part of the subtree of the node.
+      var parent = node.parentElement;
+      if (parent === null) {
+        return [];
+      }
+
+      var rest = css.slice(1).trim();
+      var candidates = Array.from(parent.querySelectorAll(":scope > " + rest));
+
+      return candidates.filter(function (candidate) {
+        if (css[0] === "+") {
+          return node.nextElementSibling === candidate;
+        }
+        return (
+          node.compareDocumentPosition(candidate) &
+          Node.DOCUMENT_POSITION_FOLLOWING
+        );
+      });
+    }
+
+    return Array.from(node.querySelectorAll(css));
+  }
+
+  function matchesCss(node, argument, pseudoElement) {
+    var separator = argument.indexOf(":");
+    if (separator === -1) {
+      return false;
+    }
+
+    var property = argument.slice(0, separator).trim();
+    var expected = toRegExp(unquote(argument.slice(separator + 1)));
+    var style = window.getComputedStyle(node, pseudoElement);
+
+    return style !== null && expected.test(style.getPropertyValue(property));
+  }
+
+  function matchesAttribute(node, argument) {
+    var separator = argument.indexOf("=");
+    var name = toRegExp(unquote(separator === -1 ? argument : argument.slice(0, separator)));
+    var value = separator === -1 ? null : toRegExp(unquote(argument.slice(separator + 1)));
+
+    return Array.from(node.attributes).some(function (attribute) {
+      return name.test(attribute.name) && (value === null || value.test(attribute.value));
+    });
+  }
+
+  function upward(node, argument) {
+    if (/^\d+$/.test(argument)) {
+      var count = parseInt(argument, 10);
+      while (node !== null && count > 0) {
+        node = node.parentElement;
+        count--;
+      }
+      return node;
+    }
+
+    return node.parentElement !== null ? node.parentElement.closest(argument) : null;
+  }
+
+  function xpath(node, expression) {
+    var result = document.evaluate(
+      expression,
+      node,
+      null,
+      XPathResult.ORDERED_NODE_SNAPSHOT_TYPE,
+      null
+    );
+
+    var nodes = [];
+    for (var i = 0; i < result.snapshotLength; i++) {
+      var item = result.snapshotItem(i);
+      if (item.nodeType === Node.ELEMENT_NODE) {
+        nodes.push(item);
+      }
+    }
+
+    return nodes;
+  }
+
+  function others(nodes) {
+    var kept = new Set();
+
+    nodes.forEach(function (node) {
+      for (var ancestor = node; ancestor !== null; ancestor = ancestor.parentElement) {
+        kept.add(ancestor);
+      }
+      node.querySelectorAll("*").forEach(function (descendant) {
+        kept.add(descendant);
+      });
+    });
+
+    return Array.from(document.querySelectorAll("body *")).filter(function (node) {
+      return !kept.has(node);
+    });
+  }
+
+  function evaluate(tasks, roots) {
+    var nodes = roots === null ? [] : roots;
+
+    tasks = tasks.filter(function (task) {
+      return ACTIONS.indexOf(task[0]) === -1;
+    });
+
+    for (var i = 0; i < tasks.length; i++) {
+      var operator = tasks[i][0];
+      var argument = tasks[i][1];
+
+      if (i === 0 && roots === null) {
+        if (operator === "css") {
+          nodes = Array.from(document.querySelectorAll(argument));
+          continue;
+        }
+        if (operator === "xpath") {
+          nodes = xpath(document, argument);
+          continue;
+        }
+        nodes = Array.from(document.querySelectorAll("*"));
+      }
+
+      nodes = apply(operator, argument, nodes);
+
+      if (nodes.length === 0) {
+        break;
+      }
+    }
+
+    return Array.from(new Set(nodes));
+  }
+
+  function apply(operator, argument, nodes) {
+    switch (operator) {
+      case "css":
+        return nodes.reduce(function (result, node) {
+          return result.concat(querySubtree(node, argument));
+        }, []);
+      case "has-text":
+        var text = toRegExp(argument);
+        return nodes.filter(function (node) {
+          return text.test(node.textContent);
+        });
+      case "has":
+        return nodes.filter(function (node) {
+          return hasMatch(argument, node);
+        });
+      case "not":
+        return nodes.filter(function (node) {
+          return !selfMatches(argument, node);
+        });
+      case "matches-attr":
+        return nodes.filter(function (node) {
+          return matchesAttribute(node, argument);
+        });
+      case "matches-css":
+        return nodes.filter(function (node) {
+          return matchesCss(node, argument, null);
+        });
+      case "matches-css-before":
+        return nodes.filter(function (node) {
+          return matchesCss(node, argument, "::before");
+        });
+      case "matches-css-after":
+        return nodes.filter(function (node) {
+          return matchesCss(node, argument, "::after");
+        });
+      case "matches-path":
+        return toRegExp(argument).test(location.pathname + location.search) ? nodes : [];
+      case "min-text-length":
+        var length = parseInt(argument, 10);
+        return nodes.filter(function (node) {
+          return node.textContent.length >= length;
+        });
+      case "upward":
+        return nodes
+          .map(function (node) {
+            return upward(node, argument);
+          })
+          .filter(function (node) {
+            return node !== null;
+          });
+      case "xpath":
+        return nodes.reduce(function (result, node) {
+          return result.concat(xpath(node, argument));
+        }, []);
+      case "others":
+        return others(nodes);
+      // Mutations are always observed, attributes included.
+      case "watch-attr":
+        return nodes;
+      default:
+        return [];
+    }
+  }
+
+  // `:has()` looks for matches within the node, css parts being evaluated against its subtree.
+  function hasMatch(tasks, node) {
+    return evaluate(tasks, [node]).length > 0;
+  }
+
+  // `:not()` checks the node itself.
+  function selfMatches(tasks, node) {
+    if (tasks.length > 0 && tasks[0][0] === "css") {
+      if (!node.matches(tasks[0][1])) {
+        return false;
+      }
+      tasks = tasks.slice(1);
+    }
+
+    return evaluate(tasks, [node]).length > 0;
+  }
+
+  function act(tasks, nodes) {
+    var action = tasks.find(function (task) {
+      return ACTIONS.indexOf(task[0]) !== -1;
+    });
+
+    nodes.forEach(function (node) {
+      if (action === undefined) {
+        node.setAttribute(HIDDEN_ATTRIBUTE, "");
+        return;
+      }
+
+      switch (action[0]) {
+        case "remove":
+          node.remove();
+          break;
+        case "remove-attr":
+          var attribute = toRegExp(unquote(action[1]));
+          Array.from(node.attributes).forEach(function (item) {
+            if (attribute.test(item.name)) {
+              node.removeAttribute(item.name);
+            }
+          });
+          break;
+        case "remove-class":
+          var className = toRegExp(unquote(action[1]));
+          Array.from(node.classList).forEach(function (item) {
+            if (className.test(item)) {
+              node.classList.remove(item);
+            }
+          });
+          break;
+        case "style":
+          node.style.cssText += ";" + action[1];
+          break;
+      }
+    });
+  }
+
+  // Running
+
+  var filters = [];
+  selectors.forEach(function (selector) {
+    try {
+      filters.push({ tasks: compile(selector), applied: false });
+    } catch (error) {
+      // Invalid filters are ignored, like other blockers do.
+    }
+  });
+
+  if (filters.length === 0) {
+    return;
+  }
+
+  var style = document.createElement("style");
+  style.textContent = "[" + HIDDEN_ATTRIBUTE + "] { display: none !important; }";
+  (document.head || document.documentElement).appendChild(style);
+
+  var newlyApplied = 0;
+  var reportScheduled = false;
+
+  function report() {
+    reportScheduled = false;
+
+    var count = newlyApplied;
+    newlyApplied = 0;
+
+    fetch(endpoint, {
+      method: "POST",
+      credentials: "omit",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify({ nonce: nonce, applied: count }),
+    }).catch(function () {});
+  }
+
+  var observer = null;
+
+  function run() {
+    runScheduled = false;
+
+    // Our own changes must not trigger another run.
+    if (observer !== null) {
+      observer.disconnect();
+    }
+
+    filters.forEach(function (filter) {
+      var nodes;
+      try {
+        nodes = evaluate(filter.tasks, null);
+      } catch (error) {
+        return;
+      }
+
+      if (nodes.length === 0) {
+        return;
+      }
+
+      act(filter.tasks, nodes);
+
+      if (!filter.applied) {
+        filter.applied = true;
+        newlyApplied++;
+      }
+    });
+
+    if (newlyApplied > 0 && !reportScheduled) {
+      reportScheduled = true;
+      setTimeout(report, REPORT_DELAY_MS);
+    }
+
+    observe();
+  }
+
+  var runScheduled = false;
+
+  function scheduleRun() {
+    if (!runScheduled) {
+      runScheduled = true;
+      setTimeout(run, RUN_DELAY_MS);
+    }
+  }
+
+  function observe() {
+    if (observer === null) {
+      observer = new MutationObserver(scheduleRun);
+    }
+
+    observer.observe(document.documentElement, {
+      childList: true,
+      subtree: true,
+      attributes: true,
+      characterData: true,
+    });
+  }
+
+  run();
+})();
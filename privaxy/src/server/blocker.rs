@@ -1,8 +1,12 @@
 use crate::blocker_utils::{
-    build_resource_from_file_contents, 
-    read_redirectable_resource_mapping, 
-    ResourceProperties
+    build_resource_from_file_contents, read_redirectable_resource_mapping, ResourceProperties,
 };
+use crate::cosmetic_filters::ExtendedCosmeticFilters;
+use crate::header_filters::HeaderFilters;
+use crate::hsts_preload::HstsPreloadList;
+use crate::json_prune_filters::{JsonPruneFilters, JsonPruneRule};
+use crate::replace_filters::{ReplaceFilters, ReplaceRule};
+use crate::url_cleaner::{CleanedUrl, UrlCleaner};
 
 use adblock::blocker::BlockerResult as AdblockerBlockerResult;
 use adblock::lists::FilterSet;
@@ -18,16 +22,37 @@ use tokio::sync::oneshot;
 
 pub type AdblockRequestChannel = Sender<BlockerRequest>;
 
+#[derive(Debug, Default)]
+pub struct BlockingState {
+    pub disabled: bool,
+    /// Filters are evaluated and their matches reported, but nothing is blocked.
+    pub observe: bool,
+}
+
 #[derive(Debug, Clone)]
-pub struct BlockingDisabledStore(pub Arc<RwLock<bool>>);
+pub struct BlockingDisabledStore(pub Arc<RwLock<BlockingState>>);
 
 impl BlockingDisabledStore {
     pub fn is_enabled(&self) -> bool {
-        !*self.0.read().unwrap()
+        !self.0.read().unwrap().disabled
     }
 
     pub fn set(&self, enabled: bool) {
-        *self.0.write().unwrap() = !enabled
+        self.0.write().unwrap().disabled = !enabled
+    }
+
+    pub fn is_observing(&self) -> bool {
+        self.0.read().unwrap().observe
+    }
+
+    pub fn set_observing(&self, observe: bool) {
+        self.0.write().unwrap().observe = observe
+    }
+
+    /// Whether filters are applied, rather than only observed.
+    pub fn is_enforcing(&self) -> bool {
+        let state = self.0.read().unwrap();
+        !state.disabled && !state.observe
     }
 }
 
@@ -44,17 +69,65 @@ pub struct NetworkUrl {
     referer: String,
 }
 
+#[derive(Debug)]
+pub struct ResponseRulesRequest {
+    url: String,
+    referer: String,
+    request_type: String,
+}
+
 #[derive(Debug)]
 pub enum RequestKind {
     Url(NetworkUrl),
     Cosmetic(CosmeticRequest),
+    /// Generic selectors for ids and classes seen after the initial page load.
+    HiddenClassIdSelectors(CosmeticRequest),
+    /// Html filters applying to an url.
+    HtmlFilters(String),
+    /// `$csp`, header removal and `$replace` filters applying to a request.
+    ResponseRules(ResponseRulesRequest),
+    /// ClearURLs cleaning of a navigation url.
+    CleanUrl(String),
     ReplaceEngine(Vec<String>),
+    /// Replaces the filter lists whose matches are only reported.
+    ReplaceObservedEngine(Vec<String>),
+    /// Replaces the ClearURLs rulesets.
+    ReplaceUrlCleaner(Vec<String>),
+    /// Whether a host is in the HSTS preload list.
+    IsHstsPreloaded(String),
+    ReplaceHstsPreloadList(HstsPreloadList),
 }
 
 #[derive(Debug)]
 pub enum BlockerResult {
-    Network(adblock::blocker::BlockerResult),
+    Network(NetworkBlockerResult),
     Cosmetic(CosmeticBlockerResult),
+    HiddenClassIdSelectors(Vec<String>),
+    HtmlFilters(Vec<String>),
+    ResponseRules(ResponseRules),
+    CleanedUrl(Option<CleanedUrl>),
+    IsHstsPreloaded(bool),
+}
+
+#[derive(Debug, Default)]
+pub struct NetworkBlockerResult {
+    /// Result of the enforced filter lists.
+    pub enforced: AdblockerBlockerResult,
+    /// Result of the observed filter lists, or of all lists while blocking is observed. The
+    /// request is let through when it only matches here.
+    pub observed: AdblockerBlockerResult,
+}
+
+#[derive(Debug, Default)]
+pub struct ResponseRules {
+    /// Policy to add to document responses, out of `$csp` filters.
+    pub content_security_policy: Option<String>,
+    pub removed_request_headers: Vec<String>,
+    pub removed_response_headers: Vec<String>,
+    /// Replacements to apply to the response body, out of `$replace` filters.
+    pub replace_rules: Vec<ReplaceRule>,
+    /// Properties to delete from JSON bodies, out of `$jsonprune` filters.
+    pub json_prune_rules: Vec<JsonPruneRule>,
 }
 
 #[derive(Debug)]
@@ -62,6 +135,10 @@ pub struct CosmeticBlockerResult {
     pub hidden_selectors: Vec<String>,
     pub style_selectors: HashMap<String, Vec<String>>,
     pub injected_script: Option<String>,
+    /// Procedural filters, which are evaluated by a script injected into the page.
+    pub procedural_selectors: Vec<String>,
+    /// Whether generic cosmetic filters are disabled on the page.
+    pub generichide: bool,
 }
 
 pub struct BlockerRequest {
@@ -73,6 +150,14 @@ pub struct Blocker {
     pub sender: Sender<BlockerRequest>,
     receiver: Receiver<BlockerRequest>,
     engine: Engine,
+    /// Network filters of the lists in observe mode, cosmetic filters can't be observed.
+    observed_engine: Engine,
+    extended_cosmetic_filters: ExtendedCosmeticFilters,
+    header_filters: HeaderFilters,
+    replace_filters: ReplaceFilters,
+    json_prune_filters: JsonPruneFilters,
+    url_cleaner: UrlCleaner,
+    hsts_preload_list: HstsPreloadList,
     blocking_disabled: BlockingDisabledStore,
 }
 
@@ -127,6 +212,13 @@ impl Blocker {
             sender,
             receiver,
             engine: Engine::new(true),
+            observed_engine: Engine::new(true),
+            extended_cosmetic_filters: ExtendedCosmeticFilters::default(),
+            header_filters: HeaderFilters::default(),
+            replace_filters: ReplaceFilters::default(),
+            json_prune_filters: JsonPruneFilters::default(),
+            url_cleaner: UrlCleaner::default(),
+            hsts_preload_list: HstsPreloadList::default(),
             blocking_disabled,
         }
     }
@@ -135,12 +227,14 @@ impl Blocker {
         while let Ok(request) = self.receiver.recv() {
             match request.kind {
                 RequestKind::Cosmetic(cosmetic_request) => {
-                    if !self.blocking_disabled.is_enabled() {
+                    if !self.blocking_disabled.is_enforcing() {
                         let _ = request.respond_to.send(BlockerResult::Cosmetic(
                             CosmeticBlockerResult {
                                 hidden_selectors: Vec::new(),
                                 style_selectors: HashMap::new(),
                                 injected_script: None,
+                                procedural_selectors: Vec::new(),
+                                generichide: true,
                             },
                         ));
                         continue;
@@ -176,20 +270,119 @@ impl Blocker {
                                 hidden_selectors,
                                 style_selectors: url_specific_resources.style_selectors,
                                 injected_script,
+                                procedural_selectors: self
+                                    .extended_cosmetic_filters
+                                    .procedural_selectors_for_url(&cosmetic_request.url),
+                                generichide: url_specific_resources.generichide,
                             }));
                 }
+                RequestKind::HiddenClassIdSelectors(cosmetic_request) => {
+                    if !self.blocking_disabled.is_enforcing() {
+                        let _ = request
+                            .respond_to
+                            .send(BlockerResult::HiddenClassIdSelectors(Vec::new()));
+                        continue;
+                    }
+
+                    let url_specific_resources = self
+                        .engine
+                        .url_cosmetic_resources(cosmetic_request.url.as_str());
+
+                    let selectors = if url_specific_resources.generichide {
+                        Vec::new()
+                    } else {
+                        self.engine.hidden_class_id_selectors(
+                            &cosmetic_request.classes,
+                            &cosmetic_request.ids,
+                            &url_specific_resources.exceptions,
+                        )
+                    };
+
+                    let _ = request
+                        .respond_to
+                        .send(BlockerResult::HiddenClassIdSelectors(selectors));
+                }
+                RequestKind::HtmlFilters(url) => {
+                    let selectors = if self.blocking_disabled.is_enforcing() {
+                        self.extended_cosmetic_filters.html_selectors_for_url(&url)
+                    } else {
+                        Vec::new()
+                    };
+
+                    let _ = request
+                        .respond_to
+                        .send(BlockerResult::HtmlFilters(selectors));
+                }
+                RequestKind::CleanUrl(url) => {
+                    let cleaned_url = if self.blocking_disabled.is_enforcing() {
+                        self.url_cleaner.clean(&url)
+                    } else {
+                        None
+                    };
+
+                    let _ = request
+                        .respond_to
+                        .send(BlockerResult::CleanedUrl(cleaned_url));
+                }
+                RequestKind::ResponseRules(response_rules_request) => {
+                    if !self.blocking_disabled.is_enforcing() {
+                        let _ = request
+                            .respond_to
+                            .send(BlockerResult::ResponseRules(ResponseRules::default()));
+                        continue;
+                    }
+
+                    let req = Request::new(
+                        response_rules_request.url.as_str(),
+                        response_rules_request.referer.as_str(),
+                        response_rules_request.request_type.as_str(),
+                    )
+                    .ok();
+
+                    // Policies only apply to documents.
+                    let content_security_policy = match response_rules_request.request_type.as_str()
+                    {
+                        "document" | "subdocument" => req
+                            .as_ref()
+                            .and_then(|req| self.engine.get_csp_directives(req)),
+                        _ => None,
+                    };
+
+                    let replace_rules = req
+                        .as_ref()
+                        .map(|req| self.replace_filters.options_for_request(req))
+                        .unwrap_or_default();
+
+                    let json_prune_rules = req
+                        .as_ref()
+                        .map(|req| self.json_prune_filters.options_for_request(req))
+                        .unwrap_or_default();
+
+                    let _ = request
+                        .respond_to
+                        .send(BlockerResult::ResponseRules(ResponseRules {
+                            content_security_policy,
+                            removed_request_headers: self
+                                .header_filters
+                                .request_headers_for_request(
+                                    &response_rules_request.url,
+                                    &response_rules_request.request_type,
+                                ),
+                            removed_response_headers: self
+                                .header_filters
+                                .response_headers_for_request(
+                                    &response_rules_request.url,
+                                    &response_rules_request.request_type,
+                                ),
+                            replace_rules,
+                            json_prune_rules,
+                        }));
+                }
                 RequestKind::Url(network_url) => {
                     if !self.blocking_disabled.is_enabled() {
-                        let _ = request.respond_to.send(BlockerResult::Network(
-                            AdblockerBlockerResult {
-                                matched: false,
-                                important: false,
-                                redirect: None,
-                                exception: None,
-                                filter: None,
-                                rewritten_url: None,
-                            },
-                        ));
+                        let _ = request
+                            .respond_to
+                            .send(BlockerResult::Network(NetworkBlockerResult::default()));
                         continue;
                     }
 
@@ -201,24 +394,87 @@ impl Blocker {
                     .unwrap();
                     let blocker_result = self.engine.check_network_request(&req);
 
+                    let network_blocker_result = if self.blocking_disabled.is_observing() {
+                        NetworkBlockerResult {
+                            enforced: AdblockerBlockerResult::default(),
+                            observed: if blocker_result.matched {
+                                blocker_result
+                            } else {
+                                self.observed_engine.check_network_request(&req)
+                            },
+                        }
+                    } else if blocker_result.matched {
+                        NetworkBlockerResult {
+                            enforced: blocker_result,
+                            observed: AdblockerBlockerResult::default(),
+                        }
+                    } else {
+                        NetworkBlockerResult {
+                            enforced: blocker_result,
+                            observed: self.observed_engine.check_network_request(&req),
+                        }
+                    };
+
                     let _ = request
                         .respond_to
-                        .send(BlockerResult::Network(blocker_result));
+                        .send(BlockerResult::Network(network_blocker_result));
                 }
                 RequestKind::ReplaceEngine(filters) => {
                     log::debug!("Configuring blocking engine.");
 
                     let mut filter_set = FilterSet::new(true);
 
-                    for filter in filters {
-                        filter_set
-                            .add_filter_list(&filter, adblock::lists::ParseOptions::default());
+                    for filter in &filters {
+                        filter_set.add_filter_list(filter, adblock::lists::ParseOptions::default());
                     }
 
                     let mut adblock_engine = Engine::from_filter_set(filter_set, true);
                     adblock_engine.use_resources(ADBLOCKING_RESOURCES.clone());
 
                     self.engine = adblock_engine;
+
+                    self.extended_cosmetic_filters =
+                        ExtendedCosmeticFilters::from_filter_lists(&filters);
+                    self.header_filters = HeaderFilters::from_filter_lists(&filters);
+                    self.replace_filters = ReplaceFilters::from_filter_lists(&filters);
+                    self.json_prune_filters = JsonPruneFilters::from_filter_lists(&filters);
+                    log::debug!(
+                        "Loaded {} procedural cosmetic filters, {} html filters, {} header filters, \
+                         {} replace filters and {} json prune filters.",
+                        self.extended_cosmetic_filters.procedural_len(),
+                        self.extended_cosmetic_filters.html_len(),
+                        self.header_filters.len(),
+                        self.replace_filters.len(),
+                        self.json_prune_filters.len()
+                    );
+                }
+                RequestKind::ReplaceObservedEngine(filters) => {
+                    let mut filter_set = FilterSet::new(true);
+
+                    for filter in &filters {
+                        filter_set.add_filter_list(filter, adblock::lists::ParseOptions::default());
+                    }
+
+                    self.observed_engine = Engine::from_filter_set(filter_set, true);
+                    log::debug!("Loaded {} observed filter lists.", filters.len());
+                }
+                // Upgrading to HTTPS is about security, it isn't affected by blocking being
+                // disabled.
+                RequestKind::IsHstsPreloaded(host) => {
+                    let _ = request.respond_to.send(BlockerResult::IsHstsPreloaded(
+                        self.hsts_preload_list.contains(&host),
+                    ));
+                }
+                RequestKind::ReplaceHstsPreloadList(hsts_preload_list) => {
+                    self.hsts_preload_list = hsts_preload_list;
+                    log::debug!(
+                        "Loaded {} HSTS preloaded hosts.",
+                        self.hsts_preload_list.len()
+                    );
+                }
+                RequestKind::ReplaceUrlCleaner(rulesets) => {
+                    self.url_cleaner = UrlCleaner::from_rulesets(&rulesets);
+                    log::debug!("Loaded {} ClearURLs providers.", self.url_cleaner.len());
                 }
             }
         }
@@ -237,8 +493,9 @@ impl AdblockRequester {
         }
     }
 
+    /// Resolves once the engine is replaced, the blocker drops the sender when it is done.
     pub(crate) async fn replace_engine(&self, filters: Vec<String>) {
-        let (sender, _receiver) = oneshot::channel();
+        let (sender, receiver) = oneshot::channel();
 
         self.adblock_request_channel
             .send(BlockerRequest {
@@ -246,6 +503,44 @@ impl AdblockRequester {
                 kind: RequestKind::ReplaceEngine(filters),
             })
             .unwrap();
+
+        let _ = receiver.await;
+    }
+
+    /// Resolves once the observed engine is replaced.
+    pub(crate) async fn replace_observed_engine(&self, filters: Vec<String>) {
+        let (sender, receiver) = oneshot::channel();
+
+        self.adblock_request_channel
+            .send(BlockerRequest {
+                respond_to: sender,
+                kind: RequestKind::ReplaceObservedEngine(filters),
+            })
+            .unwrap();
+
+        let _ = receiver.await;
+    }
+
+    pub(crate) async fn replace_url_cleaner(&self, rulesets: Vec<String>) {
+        let (sender, _receiver) = oneshot::channel();
+
+        self.adblock_request_channel
+            .send(BlockerRequest {
+                respond_to: sender,
+                kind: RequestKind::ReplaceUrlCleaner(rulesets),
+            })
+            .unwrap();
+    }
+
+    pub(crate) async fn replace_hsts_preload_list(&self, hsts_preload_list: HstsPreloadList) {
+        let (sender, _receiver) = oneshot::channel();
+
+        self.adblock_request_channel
+            .send(BlockerRequest {
+                respond_to: sender,
+                kind: RequestKind::ReplaceHstsPreloadList(hsts_preload_list),
+            })
+            .unwrap();
     }
 
     pub(crate) async fn get_cosmetic_response(
@@ -266,17 +561,128 @@ impl AdblockRequester {
         match receiver.await {
             Ok(blocker_result) => match blocker_result {
                 BlockerResult::Cosmetic(blocker_result) => blocker_result,
-                BlockerResult::Network(_) => unreachable!(),
+                _ => unreachable!(),
+            },
+            Err(_err) => unreachable!(),
+        }
+    }
+
+    pub(crate) async fn get_hidden_class_id_selectors(
+        &self,
+        url: String,
+        ids: Vec<String>,
+        classes: Vec<String>,
+    ) -> Vec<String> {
+        let (sender, receiver) = oneshot::channel();
+
+        self.adblock_request_channel
+            .send(BlockerRequest {
+                respond_to: sender,
+                kind: RequestKind::HiddenClassIdSelectors(CosmeticRequest { url, ids, classes }),
+            })
+            .unwrap();
+
+        match receiver.await {
+            Ok(blocker_result) => match blocker_result {
+                BlockerResult::HiddenClassIdSelectors(selectors) => selectors,
+                _ => unreachable!(),
+            },
+            Err(_err) => unreachable!(),
+        }
+    }
+
+    pub(crate) async fn get_html_filters(&self, url: String) -> Vec<String> {
+        let (sender, receiver) = oneshot::channel();
+
+        self.adblock_request_channel
+            .send(BlockerRequest {
+                respond_to: sender,
+                kind: RequestKind::HtmlFilters(url),
+            })
+            .unwrap();
+
+        match receiver.await {
+            Ok(blocker_result) => match blocker_result {
+                BlockerResult::HtmlFilters(selectors) => selectors,
+                _ => unreachable!(),
+            },
+            Err(_err) => unreachable!(),
+        }
+    }
+
+    /// Returns the url a navigation to `url` should be redirected to, once cleaned with the
+    /// ClearURLs rulesets.
+    pub(crate) async fn get_cleaned_url(&self, url: String) -> Option<CleanedUrl> {
+        let (sender, receiver) = oneshot::channel();
+
+        self.adblock_request_channel
+            .send(BlockerRequest {
+                respond_to: sender,
+                kind: RequestKind::CleanUrl(url),
+            })
+            .unwrap();
+
+        match receiver.await {
+            Ok(blocker_result) => match blocker_result {
+                BlockerResult::CleanedUrl(cleaned_url) => cleaned_url,
+                _ => unreachable!(),
+            },
+            Err(_err) => unreachable!(),
+        }
+    }
+
+    pub(crate) async fn is_hsts_preloaded(&self, host: String) -> bool {
+        let (sender, receiver) = oneshot::channel();
+
+        self.adblock_request_channel
+            .send(BlockerRequest {
+                respond_to: sender,
+                kind: RequestKind::IsHstsPreloaded(host),
+            })
+            .unwrap();
+
+        match receiver.await {
+            Ok(blocker_result) => match blocker_result {
+                BlockerResult::IsHstsPreloaded(is_preloaded) => is_preloaded,
+                _ => unreachable!(),
+            },
+            Err(_err) => unreachable!(),
+        }
+    }
+
+    pub(crate) async fn get_response_rules(
+        &self,
+        url: String,
+        referer: String,
+        request_type: String,
+    ) -> ResponseRules {
+        let (sender, receiver) = oneshot::channel();
+
+        self.adblock_request_channel
+            .send(BlockerRequest {
+                respond_to: sender,
+                kind: RequestKind::ResponseRules(ResponseRulesRequest {
+                    url,
+                    referer,
+                    request_type,
+                }),
+            })
+            .unwrap();
+
+        match receiver.await {
+            Ok(blocker_result) => match blocker_result {
+                BlockerResult::ResponseRules(response_rules) => response_rules,
+                _ => unreachable!(),
             },
             Err(_err) => unreachable!(),
         }
     }
 
-    pub(crate) async fn is_network_url_blocked(
+    pub(crate) async fn check_network_url(
         &self,
         network_url: String,
         referer: String,
-    ) -> (bool, adblock::blocker::BlockerResult) {
+    ) -> NetworkBlockerResult {
         let (sender, receiver) = oneshot::channel();
 
         self.adblock_request_channel
@@ -291,8 +697,8 @@ impl AdblockRequester {
 
         match receiver.await {
             Ok(blocker_result) => match blocker_result {
-                BlockerResult::Network(blocker_result) => (blocker_result.matched, blocker_result),
-                BlockerResult::Cosmetic(_) => unreachable!(),
+                BlockerResult::Network(network_blocker_result) => network_blocker_result,
+                _ => unreachable!(),
             },
             Err(_err) => unreachable!(),
         }
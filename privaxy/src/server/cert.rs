@@ -21,6 +21,10 @@ use uluru::LRUCache;
 
 const MAX_CACHED_CERTIFICATES: usize = 1_000;
 
+pub(crate) const ALPN_H2: &[u8] = b"h2";
+const ALPN_HTTP_1_1: &[u8] = b"http/1.1";
+const ALPN_PROTOCOLS: [&[u8]; 2] = [ALPN_H2, ALPN_HTTP_1_1];
+
 #[derive(Clone)]
 pub struct SignedWithCaCert {
     authority: Authority,
@@ -42,7 +46,7 @@ impl SignedWithCaCert {
             Certificate(ca_certificate.to_der().unwrap()),
         ];
 
-        let server_configuration = ServerConfig::builder()
+        let mut server_configuration = ServerConfig::builder()
             .with_safe_default_cipher_suites()
             .with_safe_default_kx_groups()
             .with_safe_default_protocol_versions()
@@ -51,6 +55,10 @@ impl SignedWithCaCert {
             .with_single_cert(certs, PrivateKey(private_key.private_key_to_der().unwrap()))
             .unwrap();
 
+        // Offer HTTP/2 first so that browsers can multiplex requests over a single
+        // intercepted connection instead of opening one connection per request.
+        server_configuration.alpn_protocols = ALPN_PROTOCOLS.iter().map(|p| p.to_vec()).collect();
+
         Self {
             authority,
             server_configuration,
@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+/// Limits applied when rewriting the bodies of text responses with `$replace` filters.
+pub struct BodyRewritingConfig {
+    /// Largest body, in bytes, that gets rewritten. Larger bodies are passed through untouched.
+    pub max_body_size: u64,
+    /// Time, in seconds, allowed to receive a body before giving up on rewriting it.
+    pub timeout_secs: u64,
+}
+
+impl Default for BodyRewritingConfig {
+    fn default() -> Self {
+        Self {
+            max_body_size: 10 * 1024 * 1024,
+            timeout_secs: 10,
+        }
+    }
+}
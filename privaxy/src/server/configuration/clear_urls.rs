@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use tokio::fs;
+use url::Url;
+
+/// Rules maintained by the ClearURLs project.
+const DEFAULT_CLEAR_URLS_LIST_URL: &str = "https://rules2.clearurls.xyz/data.minify.json";
+
+#[serde_as]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+/// A ClearURLs ruleset, listing tracking query parameters and redirection wrappers per provider.
+pub struct ClearUrlsList {
+    /// If the list is enabled
+    pub enabled: bool,
+    /// Title of the list
+    pub title: String,
+    /// Local file name of the list
+    pub file_name: String,
+    #[serde_as(as = "DisplayFromStr")]
+    /// Remote URL of the list
+    pub url: Url,
+}
+
+impl ClearUrlsList {
+    /// Lists of freshly generated configurations.
+    pub(crate) fn default_lists() -> Vec<Self> {
+        vec![Self {
+            enabled: true,
+            title: "ClearURLs".to_string(),
+            file_name: super::filter::calc_filter_filename(DEFAULT_CLEAR_URLS_LIST_URL),
+            url: Url::parse(DEFAULT_CLEAR_URLS_LIST_URL).unwrap(),
+        }]
+    }
+
+    pub(super) async fn update(
+        &self,
+        http_client: &reqwest::Client,
+    ) -> super::ConfigurationResult<String> {
+        log::debug!("Updating ClearURLs list: {}", self.title);
+
+        let response = http_client.get(self.url.as_str()).send().await?;
+        if !response.status().is_success() {
+            return Err(super::ConfigurationError::FilterError(format!(
+                "Failed to fetch ClearURLs list: {}",
+                response.status()
+            )));
+        }
+
+        let content = response.text().await?;
+
+        // Keep the previous version of the list rather than storing something we can't use.
+        if let Err(err) = serde_json::from_str::<serde_json::Value>(&content) {
+            return Err(super::ConfigurationError::FilterError(format!(
+                "Invalid ClearURLs list: {err}"
+            )));
+        }
+
+        let filters_directory = super::filter::get_filter_directory();
+        fs::create_dir_all(&filters_directory).await?;
+        fs::write(filters_directory.join(&self.file_name), &content).await?;
+
+        Ok(content)
+    }
+
+    pub(super) async fn get_contents(
+        &self,
+        http_client: &reqwest::Client,
+    ) -> super::ConfigurationResult<String> {
+        let list_path = super::filter::get_filter_directory().join(&self.file_name);
+        match fs::read(&list_path).await {
+            Err(err) => {
+                if err.kind() == std::io::ErrorKind::NotFound {
+                    self.update(http_client).await
+                } else {
+                    Err(super::ConfigurationError::FileSystemError(err))
+                }
+            }
+            Ok(list) => Ok(std::str::from_utf8(&list)?.to_string()),
+        }
+    }
+}
+
+pub(crate) async fn get_clear_urls_lists_content(
+    configuration: &super::Configuration,
+    http_client: &reqwest::Client,
+) -> Vec<String> {
+    let futures = configuration
+        .clear_urls
+        .iter()
+        .filter(|list| list.enabled)
+        .map(|list| list.get_contents(http_client));
+
+    futures::future::join_all(futures)
+        .await
+        .into_iter()
+        .filter_map(|result| match result {
+            Ok(content) => Some(content),
+            Err(err) => {
+                log::error!("Unable to retrieve ClearURLs list: {:?}, skipping.", err);
+                None
+            }
+        })
+        .collect()
+}
@@ -1,7 +1,9 @@
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::fs;
 use url::Url;
 
@@ -51,6 +53,17 @@ pub struct DefaultFilter {
     url: Url,
 }
 
+/// Part of a filter split into several files.
+#[serde_as]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FilterSegment {
+    #[serde_as(as = "DisplayFromStr")]
+    pub url: Url,
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<Url>,
+}
+
 #[serde_as]
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct Filter {
@@ -62,9 +75,28 @@ pub struct Filter {
     pub group: FilterGroup,
     /// Local file name of the filter
     pub file_name: String,
-    #[serde_as(as = "DisplayFromStr")]
-    /// Remote URL of the filter
+    /// URL of the filter, either remote or a `file://` URL to a local file or directory
+    #[serde(with = "filter_url")]
     pub url: Url,
+    /// Mirrors of the filter, tried in order when its URL can't be fetched
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<Url>,
+    /// Further parts of the filter, concatenated to it, for lists split into several files
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<FilterSegment>,
+    /// If the filter only reports the requests it would block, letting them through
+    #[serde(default)]
+    pub observe: bool,
+    /// Format of the filter, converted into Adblock Plus syntax when it is another one
+    #[serde(default)]
+    pub format: super::FilterFormat,
+    /// Version of the filter it is pinned to, pinned filters are not updated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinned_version: Option<String>,
+    /// Identifier of the filter in FilterLists, when it was added from there
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filterlists_id: Option<u32>,
 }
 
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
@@ -289,44 +321,364 @@ fn calculate_sha256_hex(input: &str) -> String {
     hex::encode(hasher.finalize())
 }
 
+/// Parses the source of a filter, plain absolute paths being taken as local sources.
+pub fn parse_filter_url(url: &str) -> Result<Url, url::ParseError> {
+    if Path::new(url).is_absolute() {
+        return Url::from_file_path(url).map_err(|_| url::ParseError::RelativeUrlWithoutBase);
+    }
+
+    Url::parse(url)
+}
+
+/// (De)serializes filter sources, accepting plain paths along with URLs.
+pub mod filter_url {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use url::Url;
+
+    pub fn serialize<S: Serializer>(url: &Url, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(url.as_str())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Url, D::Error> {
+        let url = String::deserialize(deserializer)?;
+
+        super::parse_filter_url(&url).map_err(serde::de::Error::custom)
+    }
+}
+
 pub(crate) fn calc_filter_filename(filename: &str) -> String {
     format!("{}.txt", calculate_sha256_hex(filename))
 }
 
 impl Filter {
+    /// Sets the mirrors and segments of the filter out of its FilterLists view URLs.
+    pub fn set_view_urls(&mut self, view_urls: &[filterlists_api::FilterViewURL]) {
+        let mut segments = BTreeMap::<_, Vec<_>>::new();
+
+        for view_url in view_urls {
+            match Url::parse(&view_url.url) {
+                // Local sources are only taken from the configuration file.
+                Ok(url) if !matches!(url.scheme(), "http" | "https") => {
+                    log::warn!("Ignoring view URL {}", view_url.url)
+                }
+                Ok(url) => segments
+                    .entry(view_url.segment_number)
+                    .or_default()
+                    .push((view_url.primariness, url)),
+                Err(err) => log::warn!("Invalid view URL {}: {}", view_url.url, err),
+            }
+        }
+
+        let mut segments = segments.into_values().map(|mut urls| {
+            urls.sort_by_key(|(primariness, _url)| *primariness);
+            urls.into_iter().map(|(_primariness, url)| url)
+        });
+
+        self.mirrors = match segments.next() {
+            Some(urls) => urls.filter(|url| *url != self.url).collect(),
+            None => Vec::new(),
+        };
+        self.segments = segments
+            .filter_map(|mut urls| {
+                Some(FilterSegment {
+                    url: urls.next()?,
+                    mirrors: urls.collect(),
+                })
+            })
+            .collect();
+    }
+
+    /// Returns the path of the filter if it is read from the local file system.
+    pub fn get_local_path(&self) -> Option<PathBuf> {
+        if self.url.scheme() != "file" {
+            return None;
+        }
+
+        self.url.to_file_path().ok()
+    }
+
+    /// Downloads the filter, replacing the stored one once the download passed integrity checks.
+    /// `accept_shrinking` accepts a list losing most of its rules, which integrity checks reject
+    /// otherwise. Failures are kept on disk for the filters API to report them.
     pub(super) async fn update(
         &mut self,
         http_client: &reqwest::Client,
+        accept_shrinking: bool,
     ) -> super::ConfigurationResult<String> {
         log::debug!("Updating filter: {}", self.title);
 
         let filters_directory = get_filter_directory();
         fs::create_dir_all(&filters_directory).await?;
 
+        let error_path = filters_directory.join(format!("{}.error", self.file_name));
+
+        match self
+            .download(http_client, &filters_directory, accept_shrinking)
+            .await
+        {
+            Ok(filter) => {
+                let _ = fs::remove_file(&error_path).await;
+                Ok(filter)
+            }
+            Err(err) => {
+                let message = describe_error(&err);
+
+                log::error!("Failed to update filter {}: {}", self.title, message);
+
+                if let Err(err) = fs::write(&error_path, message).await {
+                    log::error!("Unable to store update error of {}: {}", self.title, err);
+                }
+
+                Err(err)
+            }
+        }
+    }
+
+    /// Updates the filter, reporting how it went to `filter_updates`.
+    pub(super) async fn update_reporting(
+        &mut self,
+        http_client: &reqwest::Client,
+        filter_updates: &super::FilterUpdateSender,
+        accept_shrinking: bool,
+    ) -> super::ConfigurationResult<()> {
+        super::filter_update::report(
+            filter_updates,
+            Some(self),
+            super::FilterUpdateStatus::Downloading,
+        );
+
+        let previous = fs::read_to_string(get_filter_directory().join(&self.file_name))
+            .await
+            .ok();
+
+        let status = match self.update(http_client, accept_shrinking).await {
+            Ok(filter) if previous.as_deref() == Some(filter.as_str()) => {
+                super::FilterUpdateStatus::Unchanged
+            }
+            Ok(_) => super::FilterUpdateStatus::Updated,
+            Err(err) => {
+                super::filter_update::report(
+                    filter_updates,
+                    Some(self),
+                    super::FilterUpdateStatus::Failed {
+                        reason: describe_error(&err),
+                    },
+                );
+
+                return Err(err);
+            }
+        };
+
+        super::filter_update::report(filter_updates, Some(self), status);
+
+        Ok(())
+    }
+
+    async fn download(
+        &mut self,
+        http_client: &reqwest::Client,
+        filters_directory: &Path,
+        accept_shrinking: bool,
+    ) -> super::ConfigurationResult<String> {
         let filter = get_filter(self, http_client).await?;
 
         let filter_path = filters_directory.join(&self.file_name);
-        fs::write(&filter_path, &filter).await?;
+        let previous = fs::read_to_string(&filter_path).await.ok();
+
+        // Converting and parsing large lists takes a while.
+        let format = self.format;
+        let is_local = self.get_local_path().is_some();
+        let (filter, previous, conversion) = tokio::task::spawn_blocking(move || {
+            let (filter, conversion) = super::filter_format::convert(filter, format);
+
+            // Local filters are maintained by the user, whatever they contain is intended.
+            if is_local {
+                return Ok((filter, previous, conversion));
+            }
+
+            // Without a previous version to compare to, the size of the list isn't checked.
+            let reference = if accept_shrinking {
+                None
+            } else {
+                previous.as_deref()
+            };
+
+            super::filter_integrity::check(&filter, reference)
+                .map(|_| (filter, previous, conversion))
+        })
+        .await
+        .map_err(|err| super::ConfigurationError::FilterError(err.to_string()))??;
+
+        let conversion_path = get_conversion_path(&filter_path);
+        match conversion {
+            Some(conversion) => {
+                log::info!(
+                    "Converted filter {} from {:?} format: {} lines converted, {} skipped",
+                    self.title,
+                    conversion.format,
+                    conversion.converted,
+                    conversion.skipped
+                );
+                fs::write(
+                    &conversion_path,
+                    serde_json::to_string(&conversion).unwrap(),
+                )
+                .await?;
+            }
+            None => {
+                let _ = fs::remove_file(&conversion_path).await;
+            }
+        }
+
+        if let Some(previous) = previous {
+            if let Err(err) =
+                super::filter_history::record_update(&self.file_name, &previous, &filter).await
+            {
+                log::error!(
+                    "Unable to record update of filter {}: {:?}",
+                    self.title,
+                    err
+                );
+            }
+
+            // The last known good copy, in case the current one goes missing.
+            fs::write(get_previous_filter_path(&filter_path), &previous).await?;
+        }
+
+        write_atomically(&filter_path, &filter).await?;
 
         Ok(filter)
     }
 
+    /// Restores the contents the filter had before the update recorded as `version`, and stops
+    /// updating it.
+    pub(super) async fn pin(&mut self, version: &str) -> super::ConfigurationResult<()> {
+        let contents =
+            super::filter_history::get_version_contents(&self.file_name, version).await?;
+
+        write_atomically(&get_filter_directory().join(&self.file_name), &contents).await?;
+        self.pinned_version = Some(version.to_string());
+
+        Ok(())
+    }
+
     pub async fn get_contents(
         &mut self,
         http_client: &reqwest::Client,
     ) -> super::ConfigurationResult<String> {
         let filter_path = get_filter_directory().join(&self.file_name);
+
+        if let Some(filter) = self.read_local_contents().await {
+            return Ok(filter);
+        }
+
         match fs::read(&filter_path).await {
             Err(err) => {
-                if err.kind() == std::io::ErrorKind::NotFound {
-                    self.update(http_client).await
-                } else {
-                    Err(super::ConfigurationError::FileSystemError(err))
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    return Err(super::ConfigurationError::FileSystemError(err));
+                }
+
+                match self.update(http_client, false).await {
+                    Ok(filter) => Ok(filter),
+                    Err(err) => {
+                        match fs::read_to_string(get_previous_filter_path(&filter_path)).await {
+                            Ok(previous) => {
+                                log::warn!("Using last known good copy of {}", self.title);
+                                Ok(previous)
+                            }
+                            Err(_) => Err(err),
+                        }
+                    }
                 }
             }
             Ok(filter) => Ok(std::str::from_utf8(&filter)?.to_string()),
         }
     }
+
+    /// Reads a local filter from its source, converted into Adblock Plus syntax. Local filters are
+    /// cheap to read, they are read again so that edits are applied. Their stored copy is only
+    /// written by updates, pinned filters keep theirs.
+    async fn read_local_contents(&self) -> Option<String> {
+        let path = self
+            .get_local_path()
+            .filter(|_| self.pinned_version.is_none())?;
+
+        match read_local_filter(&path).await {
+            Ok(filter) => Some(super::filter_format::convert(filter, self.format).0),
+            Err(err) => {
+                log::warn!(
+                    "Using stored copy of local filter {}: {}",
+                    self.title,
+                    describe_error(&err)
+                );
+                None
+            }
+        }
+    }
+
+    /// Returns how the filter was converted into Adblock Plus syntax, if it was in another format.
+    pub async fn get_conversion(&self) -> Option<super::FilterConversion> {
+        let conversion = fs::read(get_conversion_path(
+            &get_filter_directory().join(&self.file_name),
+        ))
+        .await
+        .ok()?;
+
+        serde_json::from_slice(&conversion).ok()
+    }
+
+    /// Returns the error the last update of the filter failed with, if it did.
+    pub async fn get_update_error(&self) -> Option<String> {
+        fs::read_to_string(get_filter_directory().join(format!("{}.error", self.file_name)))
+            .await
+            .ok()
+    }
+}
+
+/// Describes an update failure along with its cause, for the user to act upon.
+fn describe_error(err: &super::ConfigurationError) -> String {
+    let mut message = err.to_string();
+
+    if let Some(source) = std::error::Error::source(err) {
+        let source = source.to_string();
+        if !message.contains(&source) {
+            message = format!("{}: {}", message, source);
+        }
+    }
+
+    message
+}
+
+fn get_conversion_path(filter_path: &Path) -> PathBuf {
+    filter_path.with_extension("conversion")
+}
+
+fn get_previous_filter_path(filter_path: &Path) -> PathBuf {
+    filter_path.with_extension("previous")
+}
+
+/// Writes `contents` through a temporary file, so that a failed write doesn't leave a truncated
+/// filter behind. Each write gets its own temporary file, as manual and periodic updates of a
+/// filter may run at the same time.
+async fn write_atomically(path: &Path, contents: &str) -> std::io::Result<()> {
+    static WRITE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let temporary_path = path.with_extension(format!(
+        "{}.{}.tmp",
+        std::process::id(),
+        WRITE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = match fs::write(&temporary_path, contents).await {
+        Ok(()) => fs::rename(&temporary_path, path).await,
+        Err(err) => Err(err),
+    };
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary_path).await;
+    }
+
+    result
 }
 
 impl From<DefaultFilter> for Filter {
@@ -345,6 +697,12 @@ impl From<DefaultFilter> for Filter {
             },
             file_name: default_filter.file_name,
             url: default_filter.url,
+            mirrors: Vec::new(),
+            segments: Vec::new(),
+            observe: false,
+            format: super::FilterFormat::Adblock,
+            pinned_version: None,
+            filterlists_id: None,
         }
     }
 }
@@ -353,9 +711,68 @@ pub(crate) async fn get_filter(
     filter: &mut Filter,
     http_client: &reqwest::Client,
 ) -> super::ConfigurationResult<String> {
-    let response = http_client.get(filter.url.as_str()).send().await?;
+    if let Some(path) = filter.get_local_path() {
+        return read_local_filter(&path).await;
+    }
+
+    let mut content = fetch_from_mirrors(&filter.url, &filter.mirrors, http_client).await?;
+
+    for segment in &filter.segments {
+        if !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(&fetch_from_mirrors(&segment.url, &segment.mirrors, http_client).await?);
+        super::filter_integrity::check_size(content.len() as u64)?;
+    }
+
+    Ok(content)
+}
+
+/// Fetches `url`, falling back to its `mirrors` in order.
+async fn fetch_from_mirrors(
+    url: &Url,
+    mirrors: &[Url],
+    http_client: &reqwest::Client,
+) -> super::ConfigurationResult<String> {
+    let mut result = fetch(url, http_client).await;
+    let mut fetched_url = url;
+
+    for mirror in mirrors {
+        match &result {
+            Ok(_) => break,
+            Err(err) => log::warn!(
+                "Unable to fetch {}: {}, trying {}",
+                fetched_url,
+                err,
+                mirror
+            ),
+        }
+
+        result = fetch(mirror, http_client).await;
+        fetched_url = mirror;
+    }
+
+    result
+}
+
+async fn fetch(url: &Url, http_client: &reqwest::Client) -> super::ConfigurationResult<String> {
+    let response = http_client.get(url.as_str()).send().await?;
     if response.status().is_success() {
+        super::filter_integrity::check_content_type(
+            response
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|content_type| content_type.to_str().ok()),
+        )?;
+        if let Some(content_length) = response.content_length() {
+            super::filter_integrity::check_size(content_length)?;
+        }
+
         let content = response.text().await?;
+        super::filter_integrity::check_size(content.len() as u64)?;
+        // Checked for each file, so that a corrupted mirror is skipped.
+        super::filter_integrity::check_checksum(&content)?;
+
         Ok(content)
     } else {
         log::error!("Failed to fetch filter content: {}", response.status());
@@ -366,7 +783,42 @@ pub(crate) async fn get_filter(
     }
 }
 
-fn get_filter_directory() -> PathBuf {
+/// Reads a local filter, directories being read as the concatenation of the files they contain.
+async fn read_local_filter(path: &Path) -> super::ConfigurationResult<String> {
+    let metadata = fs::metadata(path).await?;
+
+    if !metadata.is_dir() {
+        super::filter_integrity::check_size(metadata.len())?;
+        return Ok(fs::read_to_string(path).await?);
+    }
+
+    let mut entries = fs::read_dir(path).await?;
+    let mut paths = Vec::new();
+
+    while let Some(entry) = entries.next_entry().await? {
+        let is_hidden = entry.file_name().to_string_lossy().starts_with('.');
+
+        if !is_hidden && entry.file_type().await?.is_file() {
+            paths.push(entry.path());
+        }
+    }
+
+    // Sorted, so that the files get concatenated in a predictable order.
+    paths.sort_unstable();
+
+    let mut contents = String::new();
+
+    for path in paths {
+        contents.push_str(&fs::read_to_string(&path).await?);
+        contents.push('\n');
+    }
+
+    super::filter_integrity::check_size(contents.len() as u64)?;
+
+    Ok(contents)
+}
+
+pub(super) fn get_filter_directory() -> PathBuf {
     let filter_dir: PathBuf = match env::var("PRIVAXY_FILTER_PATH") {
         Ok(val) => PathBuf::from(&val),
         // Assume home directory
@@ -375,14 +827,14 @@ fn get_filter_directory() -> PathBuf {
     return super::get_base_directory().unwrap().join(filter_dir);
 }
 
-pub(crate) async fn get_filters_content(
-    configuration: &mut super::Configuration,
+async fn get_contents<'a>(
+    filters: impl Iterator<Item = &'a mut Filter>,
     http_client: &reqwest::Client,
 ) -> Vec<String> {
-    let mut filters = Vec::new();
+    let mut contents = Vec::new();
     let mut futures = vec![];
 
-    for filter in configuration.get_enabled_filters() {
+    for filter in filters {
         let future = filter.get_contents(http_client);
         futures.push(future);
     }
@@ -390,16 +842,115 @@ pub(crate) async fn get_filters_content(
     let results = futures::future::join_all(futures).await;
     for result in results {
         match result {
-            Ok(filter_content) => filters.push(filter_content),
+            Ok(filter_content) => contents.push(filter_content),
             Err(err) => {
                 log::error!("Unable to retrieve filter: {:?}, skipping.", err)
             }
         }
     }
 
-    filters.append(&mut configuration.custom_filters);
-    filters.sort_unstable();
+    contents
+}
+
+/// Where [`get_effective_filters_content`] gets the contents of filters from.
+enum ContentsSource<'a> {
+    /// Filters are fetched when they are out of date, and their stored copy updated.
+    Fetched(&'a reqwest::Client),
+    /// Local filters are read from their source, other filters from their stored copy.
+    Stored,
+}
+
+/// Returns the contents of the enabled filters which aren't observed, along with the custom
+/// filters and the `$jsonprune` rules, sorted and without duplicates.
+async fn get_effective_filters_content(
+    configuration: &mut super::Configuration,
+    source: ContentsSource<'_>,
+) -> Vec<String> {
+    let filters = configuration
+        .filters
+        .iter_mut()
+        .filter(|filter| filter.enabled && !filter.observe);
+
+    let mut contents = match source {
+        ContentsSource::Fetched(http_client) => get_contents(filters, http_client).await,
+        ContentsSource::Stored => get_stored_contents(filters).await,
+    };
+
+    contents.extend(configuration.custom_filters.iter().cloned());
+    contents.extend(
+        configuration
+            .json_prune
+            .iter()
+            .map(super::JsonPruneConfig::to_filter),
+    );
+
+    contents.sort_unstable();
     // Filter out duplicate lines, if present
-    filters.dedup();
-    filters
+    contents.dedup();
+    contents
+}
+
+async fn get_stored_contents<'a>(filters: impl Iterator<Item = &'a mut Filter>) -> Vec<String> {
+    let filters_directory = get_filter_directory();
+
+    let futures = filters.map(|filter| {
+        let filters_directory = &filters_directory;
+
+        async move {
+            match filter.read_local_contents().await {
+                Some(filter_content) => Ok(filter_content),
+                None => fs::read_to_string(filters_directory.join(&filter.file_name)).await,
+            }
+        }
+    });
+
+    futures::future::join_all(futures)
+        .await
+        .into_iter()
+        .filter_map(|result| match result {
+            Ok(filter_content) => Some(filter_content),
+            Err(err) => {
+                log::warn!("Unable to read stored filter: {}, skipping.", err);
+                None
+            }
+        })
+        .collect()
+}
+
+pub(crate) async fn get_filters_content(
+    configuration: &mut super::Configuration,
+    http_client: &reqwest::Client,
+) -> Vec<String> {
+    get_effective_filters_content(configuration, ContentsSource::Fetched(http_client)).await
+}
+
+/// Returns the same filters as [`get_filters_content`], but without fetching or writing
+/// anything: exports reflect the filters the blocker currently runs with, and never wait on the
+/// network.
+pub(crate) async fn get_stored_filters_content(
+    configuration: &mut super::Configuration,
+) -> Vec<String> {
+    get_effective_filters_content(configuration, ContentsSource::Stored).await
+}
+
+/// Returns the contents of the enabled filters, whether they are in observe mode or not.
+pub(crate) async fn get_enabled_filters_content(
+    configuration: &mut super::Configuration,
+    http_client: &reqwest::Client,
+) -> Vec<String> {
+    get_contents(configuration.get_enabled_filters(), http_client).await
+}
+
+/// Returns the contents of the enabled filters in observe mode.
+pub(crate) async fn get_observed_filters_content(
+    configuration: &mut super::Configuration,
+    http_client: &reqwest::Client,
+) -> Vec<String> {
+    get_contents(
+        configuration
+            .get_enabled_filters()
+            .filter(|filter| filter.observe),
+        http_client,
+    )
+    .await
 }
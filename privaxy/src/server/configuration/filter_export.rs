@@ -0,0 +1,151 @@
+//! Export of the rules in effect, for blockers such as DNS resolvers to follow the selection of
+//! filters made in Privaxy.
+use adblock::lists::{parse_filter, ParseOptions, ParsedFilter};
+use std::collections::BTreeSet;
+
+/// Address blocked domains resolve to in exported hosts files.
+const HOSTS_BLOCKING_ADDRESS: &str = "0.0.0.0";
+
+/// Returns the deduplicated rules of `filters_content`, leaving out comments and rules the
+/// blocking engine can't parse. `network_only` leaves out cosmetic rules as well.
+pub fn get_effective_rules(filters_content: &[String], network_only: bool) -> Vec<String> {
+    filters_content
+        .iter()
+        .flat_map(|content| content.lines())
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .filter(|line| {
+            match parse_filter(line, false, ParseOptions::default()) {
+                Ok(ParsedFilter::Network(_)) => true,
+                Ok(ParsedFilter::Cosmetic(_)) => !network_only,
+                // Comments and headers end up here.
+                Err(_) => false,
+            }
+        })
+        .map(String::from)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Returns the domains blocked as a whole by `rules`, `||tracker.example^` rules without options.
+/// Domains excepted by `@@||tracker.example^` rules are left out, a hosts file being unable to
+/// express exceptions.
+pub fn get_blocked_domains(rules: &[String]) -> Vec<&str> {
+    let excepted_domains: BTreeSet<&str> = rules
+        .iter()
+        .filter_map(|rule| get_rule_domain(rule.strip_prefix("@@")?))
+        .collect();
+
+    rules
+        .iter()
+        .filter_map(|rule| get_rule_domain(rule))
+        .filter(|domain| !excepted_domains.contains(domain))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Returns `domains` as a hosts file.
+pub fn to_hosts(domains: &[&str]) -> String {
+    let mut hosts = String::from("# Domains blocked by Privaxy\n");
+
+    for domain in domains {
+        hosts.push_str(HOSTS_BLOCKING_ADDRESS);
+        hosts.push(' ');
+        hosts.push_str(domain);
+        hosts.push('\n');
+    }
+
+    hosts
+}
+
+/// Returns `custom_filters` as a list other blockers can subscribe to.
+pub fn to_custom_filters_list(custom_filters: &[String]) -> String {
+    let mut list = String::from("[Adblock Plus 2.0]\n! Title: Privaxy custom filters\n");
+
+    for custom_filter in custom_filters {
+        list.push_str(custom_filter);
+        list.push('\n');
+    }
+
+    list
+}
+
+fn get_rule_domain(rule: &str) -> Option<&str> {
+    let domain = rule.strip_prefix("||")?.strip_suffix('^')?;
+
+    super::filter_format::is_domain(domain).then_some(domain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rules: &[&str]) -> Vec<String> {
+        rules.iter().map(|rule| rule.to_string()).collect()
+    }
+
+    #[test]
+    fn test_get_effective_rules() {
+        let filters_content = vec![
+            "[Adblock Plus 2.0]\n! Title: Ads\n||ads.example^\n\n  example.com##.ad  \n"
+                .to_string(),
+            "||ads.example^\n/banner/*\n@@||cdn.example^\n".to_string(),
+        ];
+
+        assert_eq!(
+            get_effective_rules(&filters_content, false),
+            rules(&[
+                "/banner/*",
+                "@@||cdn.example^",
+                "example.com##.ad",
+                "||ads.example^"
+            ])
+        );
+        assert_eq!(
+            get_effective_rules(&filters_content, true),
+            rules(&["/banner/*", "@@||cdn.example^", "||ads.example^"])
+        );
+    }
+
+    #[test]
+    fn test_get_blocked_domains() {
+        let rules = rules(&[
+            "||tracker.example^",
+            "||ads.example^",
+            "||ads.example^",
+            "||cdn.example^",
+            "@@||cdn.example^",
+            "||partial.example^$third-party",
+            "||path.example/ads^",
+            "/banner/*",
+            "example.com##.ad",
+            "||other.example^",
+            "@@||other.example^$document",
+        ]);
+
+        assert_eq!(
+            get_blocked_domains(&rules),
+            vec!["ads.example", "other.example", "tracker.example"]
+        );
+        assert!(get_blocked_domains(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_to_hosts() {
+        assert_eq!(
+            to_hosts(&["ads.example", "tracker.example"]),
+            "# Domains blocked by Privaxy\n0.0.0.0 ads.example\n0.0.0.0 tracker.example\n"
+        );
+        assert_eq!(to_hosts(&[]), "# Domains blocked by Privaxy\n");
+    }
+
+    #[test]
+    fn test_to_custom_filters_list() {
+        assert_eq!(
+            to_custom_filters_list(&rules(&["||ads.example^", "example.com##.ad"])),
+            "[Adblock Plus 2.0]\n! Title: Privaxy custom filters\n||ads.example^\nexample.com##.ad\n"
+        );
+    }
+}
@@ -0,0 +1,288 @@
+//! Blocklist formats other than Adblock Plus syntax: hosts files, dnsmasq configurations and
+//! plain lists of domains. They are converted into equivalent `||domain^` network rules before
+//! being stored.
+use serde::{Deserialize, Serialize};
+
+/// Number of rule lines looked at to detect the format of a list.
+const DETECTION_SAMPLE_SIZE: usize = 200;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterFormat {
+    /// Detected out of the contents of the list.
+    #[default]
+    Auto,
+    Adblock,
+    /// `0.0.0.0 tracker.example` lines.
+    Hosts,
+    /// `address=/tracker.example/` and `server=/tracker.example/` lines.
+    Dnsmasq,
+    /// One domain per line.
+    Domains,
+}
+
+/// Outcome of the conversion of a list into Adblock Plus syntax.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FilterConversion {
+    pub format: FilterFormat,
+    /// Lines converted into network rules.
+    pub converted: usize,
+    /// Lines that couldn't be converted, such as hosts entries pointing to an actual address.
+    pub skipped: usize,
+}
+
+/// Returns `contents` in Adblock Plus syntax, along with how the conversion went when the list
+/// was in another format.
+pub(super) fn convert(
+    contents: String,
+    format: FilterFormat,
+) -> (String, Option<FilterConversion>) {
+    let format = match format {
+        FilterFormat::Auto => detect(&contents),
+        format => format,
+    };
+
+    let convert_line = match format {
+        FilterFormat::Auto | FilterFormat::Adblock => return (contents, None),
+        FilterFormat::Hosts => convert_hosts_line,
+        FilterFormat::Dnsmasq => convert_dnsmasq_line,
+        FilterFormat::Domains => convert_domains_line,
+    };
+
+    let mut converted = String::with_capacity(contents.len());
+    let mut conversion = FilterConversion {
+        format,
+        converted: 0,
+        skipped: 0,
+    };
+
+    for line in rule_lines(&contents) {
+        let domains = match convert_line(line) {
+            Some(domains) if !domains.is_empty() => domains,
+            _ => {
+                conversion.skipped += 1;
+                continue;
+            }
+        };
+
+        for domain in domains {
+            converted.push_str("||");
+            converted.push_str(&domain.to_ascii_lowercase());
+            converted.push_str("^\n");
+        }
+        conversion.converted += 1;
+    }
+
+    (converted, Some(conversion))
+}
+
+/// Lines which are neither blank nor comments, hosts files and dnsmasq configurations using `#`
+/// for comments.
+fn rule_lines(contents: &str) -> impl Iterator<Item = &str> {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('!'))
+}
+
+fn detect(contents: &str) -> FilterFormat {
+    if contents.trim_start().starts_with('[') {
+        return FilterFormat::Adblock;
+    }
+
+    let (mut hosts, mut dnsmasq, mut domains, mut other) = (0, 0, 0, 0);
+
+    // Cosmetic filters contain `#`, they have to be looked for before comments are stripped.
+    for line in contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('!') && !line.starts_with("# "))
+        .take(DETECTION_SAMPLE_SIZE)
+    {
+        // Adblock Plus lists may have bare domain lines, converting them would drop their
+        // other rules.
+        if is_adblock_rule(line) {
+            return FilterFormat::Adblock;
+        } else if line.starts_with('#') {
+            continue;
+        } else if line.starts_with("address=/") || line.starts_with("server=/") {
+            dnsmasq += 1;
+        } else if convert_hosts_line(line).is_some() {
+            hosts += 1;
+        } else if convert_domains_line(line).is_some() {
+            domains += 1;
+        } else {
+            other += 1;
+        }
+    }
+
+    [
+        (FilterFormat::Hosts, hosts),
+        (FilterFormat::Dnsmasq, dnsmasq),
+        (FilterFormat::Domains, domains),
+    ]
+    .into_iter()
+    .filter(|(_format, count)| *count > other)
+    .max_by_key(|(_format, count)| *count)
+    .map(|(format, _count)| format)
+    .unwrap_or(FilterFormat::Adblock)
+}
+
+/// Whether `line` uses syntax only found in Adblock Plus lists.
+fn is_adblock_rule(line: &str) -> bool {
+    line.starts_with("||")
+        || line.starts_with("@@")
+        || ["##", "#@#", "#?#", "#$#", "#%#"]
+            .iter()
+            .any(|separator| line.contains(separator))
+}
+
+/// Addresses hosts files and dnsmasq use to block a domain.
+fn is_blocking_address(address: &str) -> bool {
+    matches!(
+        address,
+        "" | "#" | "0" | "0.0.0.0" | "127.0.0.1" | "::" | "::0" | "::1"
+    )
+}
+
+pub(super) fn is_domain(domain: &str) -> bool {
+    domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_'))
+}
+
+fn convert_hosts_line(line: &str) -> Option<Vec<&str>> {
+    let mut parts = line.split_whitespace();
+    let address = parts.next()?;
+
+    if !is_blocking_address(address) {
+        // Either not a hosts entry, or one pointing to an actual address.
+        return None;
+    }
+
+    Some(
+        parts
+            .filter(|host| *host != "0.0.0.0" && is_domain(host))
+            .collect(),
+    )
+}
+
+fn convert_dnsmasq_line(line: &str) -> Option<Vec<&str>> {
+    let rest = line
+        .strip_prefix("address=/")
+        .or_else(|| line.strip_prefix("server=/"))?;
+
+    // `address=/first.example/second.example/0.0.0.0`, the address being last.
+    let (domains, address) = rest.rsplit_once('/')?;
+
+    if !is_blocking_address(address) {
+        return None;
+    }
+
+    domains
+        .split('/')
+        .map(|domain| is_domain(domain).then_some(domain))
+        .collect()
+}
+
+fn convert_domains_line(line: &str) -> Option<Vec<&str>> {
+    let domain = line.trim_start_matches("*.").trim_start_matches('.');
+
+    is_domain(domain).then(|| vec![domain])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert_auto(contents: &str) -> (String, Option<FilterConversion>) {
+        convert(contents.to_string(), FilterFormat::Auto)
+    }
+
+    #[test]
+    fn test_hosts() {
+        let contents = "# Hosts file\n127.0.0.1 localhost\n::1 localhost ip6-localhost\n0.0.0.0 0.0.0.0\n0.0.0.0 ads.example # Ads\n127.0.0.1 tracker.example Metrics.Example\n";
+
+        assert_eq!(detect(contents), FilterFormat::Hosts);
+        assert_eq!(
+            convert_auto(contents),
+            (
+                "||ads.example^\n||tracker.example^\n||metrics.example^\n".to_string(),
+                Some(FilterConversion {
+                    format: FilterFormat::Hosts,
+                    converted: 2,
+                    skipped: 3,
+                })
+            )
+        );
+    }
+
+    #[test]
+    fn test_hosts_actual_address() {
+        assert_eq!(convert_hosts_line("192.168.1.1 router.example"), None);
+    }
+
+    #[test]
+    fn test_dnsmasq() {
+        let contents = "address=/ads.example/0.0.0.0\nserver=/tracker.example/\naddress=/first.example/second.example/#\naddress=/local.example/192.168.1.1\n";
+
+        assert_eq!(detect(contents), FilterFormat::Dnsmasq);
+        assert_eq!(
+            convert_auto(contents),
+            (
+                "||ads.example^\n||tracker.example^\n||first.example^\n||second.example^\n"
+                    .to_string(),
+                Some(FilterConversion {
+                    format: FilterFormat::Dnsmasq,
+                    converted: 3,
+                    skipped: 1,
+                })
+            )
+        );
+    }
+
+    #[test]
+    fn test_domains() {
+        let contents = "# Domains\nads.example\n*.tracker.example\n.metrics.example\nlocalhost\n";
+
+        assert_eq!(detect(contents), FilterFormat::Domains);
+        assert_eq!(
+            convert_auto(contents),
+            (
+                "||ads.example^\n||tracker.example^\n||metrics.example^\n".to_string(),
+                Some(FilterConversion {
+                    format: FilterFormat::Domains,
+                    converted: 3,
+                    skipped: 1,
+                })
+            )
+        );
+    }
+
+    #[test]
+    fn test_adblock() {
+        let contents = "[Adblock Plus 2.0]\n! Title: Test list\n||ads.example^\n##.ad\n";
+
+        assert_eq!(detect(contents), FilterFormat::Adblock);
+        assert_eq!(convert_auto(contents), (contents.to_string(), None));
+    }
+
+    #[test]
+    fn test_adblock_with_domain_lines() {
+        let contents = "! Title: Test list\nads.example\ntracker.example\nmetrics.example\n||banners.example^\nexample.com##.ad\n";
+
+        assert_eq!(detect(contents), FilterFormat::Adblock);
+        assert_eq!(convert_auto(contents), (contents.to_string(), None));
+    }
+
+    #[test]
+    fn test_explicit_format() {
+        let (converted, conversion) = convert("ads.example\n".to_string(), FilterFormat::Adblock);
+
+        assert_eq!(converted, "ads.example\n");
+        assert_eq!(conversion, None);
+    }
+}
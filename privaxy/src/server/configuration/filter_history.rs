@@ -0,0 +1,293 @@
+//! History of filter updates. Each update changing the rules of a filter records the lines it
+//! added and removed, along with the contents it replaced so that the filter can be pinned back
+//! to them.
+use super::{ConfigurationError, ConfigurationResult};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Number of updates kept per filter.
+const KEPT_CHANGES: usize = 10;
+
+const HISTORY_DIRECTORY_NAME: &str = "history";
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FilterChange {
+    /// Version of the filter replaced by the update, which the filter can be pinned to.
+    pub version: String,
+    pub updated_at: DateTime<Utc>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+fn get_history_directory(file_name: &str) -> PathBuf {
+    super::filter::get_filter_directory()
+        .join(HISTORY_DIRECTORY_NAME)
+        .join(file_name)
+}
+
+/// Returns the lines added and removed between two contents of a filter, in the order they are
+/// listed, leaving comments and blank lines aside.
+fn diff(previous: &str, current: &str) -> (Vec<String>, Vec<String>) {
+    fn rules(contents: &str) -> Vec<&str> {
+        contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('!') && !line.starts_with('['))
+            .collect()
+    }
+
+    let (removed, added) = super::line_diff::diff(&rules(previous), &rules(current));
+    let to_strings = |lines: Vec<&str>| lines.into_iter().map(str::to_string).collect();
+
+    (to_strings(added), to_strings(removed))
+}
+
+/// Returns the versions recorded in `history_directory`, oldest first.
+async fn get_versions(history_directory: &Path) -> ConfigurationResult<Vec<String>> {
+    let mut entries = match fs::read_dir(history_directory).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut versions = Vec::new();
+
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+
+        if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+            continue;
+        }
+
+        if let Some(version) = path.file_stem().and_then(|stem| stem.to_str()) {
+            versions.push(version.to_string());
+        }
+    }
+
+    // Versions are timestamps, they sort chronologically.
+    versions.sort_unstable();
+
+    Ok(versions)
+}
+
+/// Records the update of a filter from its `previous` contents to its `current` ones.
+pub(super) async fn record_update(
+    file_name: &str,
+    previous: &str,
+    current: &str,
+) -> ConfigurationResult<()> {
+    record_change(
+        &get_history_directory(file_name),
+        previous,
+        current,
+        Utc::now(),
+    )
+    .await
+}
+
+async fn record_change(
+    history_directory: &Path,
+    previous: &str,
+    current: &str,
+    updated_at: DateTime<Utc>,
+) -> ConfigurationResult<()> {
+    // Lists are large, diffing them takes a while.
+    let (previous_contents, current_contents) = (previous.to_string(), current.to_string());
+    let (added, removed) =
+        tokio::task::spawn_blocking(move || diff(&previous_contents, &current_contents))
+            .await
+            .map_err(|err| ConfigurationError::FilterError(err.to_string()))?;
+
+    if added.is_empty() && removed.is_empty() {
+        return Ok(());
+    }
+
+    fs::create_dir_all(history_directory).await?;
+
+    let change = FilterChange {
+        version: updated_at.format("%Y%m%dT%H%M%S%.3fZ").to_string(),
+        updated_at,
+        added,
+        removed,
+    };
+
+    log::info!(
+        "Filter {} updated: {} rules added, {} rules removed",
+        history_directory
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy(),
+        change.added.len(),
+        change.removed.len()
+    );
+
+    fs::write(
+        history_directory.join(format!("{}.txt", change.version)),
+        previous,
+    )
+    .await?;
+    fs::write(
+        history_directory.join(format!("{}.json", change.version)),
+        serde_json::to_string(&change).unwrap(),
+    )
+    .await?;
+
+    let versions = get_versions(history_directory).await?;
+
+    for version in versions
+        .iter()
+        .take(versions.len().saturating_sub(KEPT_CHANGES))
+    {
+        fs::remove_file(history_directory.join(format!("{}.json", version))).await?;
+        fs::remove_file(history_directory.join(format!("{}.txt", version))).await?;
+    }
+
+    Ok(())
+}
+
+/// Returns the recorded updates of a filter, most recent first.
+pub async fn get_filter_changes(file_name: &str) -> ConfigurationResult<Vec<FilterChange>> {
+    let history_directory = get_history_directory(file_name);
+    let mut changes = Vec::new();
+
+    for version in get_versions(&history_directory).await?.iter().rev() {
+        let change = fs::read(history_directory.join(format!("{}.json", version))).await?;
+
+        match serde_json::from_slice(&change) {
+            Ok(change) => changes.push(change),
+            Err(err) => log::error!(
+                "Unable to read change {} of {}: {}",
+                version,
+                file_name,
+                err
+            ),
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Returns the contents of a filter before the update recorded as `version`.
+pub(super) async fn get_version_contents(
+    file_name: &str,
+    version: &str,
+) -> ConfigurationResult<String> {
+    let history_directory = get_history_directory(file_name);
+
+    if !get_versions(&history_directory)
+        .await?
+        .iter()
+        .any(|known_version| known_version == version)
+    {
+        return Err(ConfigurationError::FilterError(format!(
+            "Unknown version {} of filter {}",
+            version, file_name
+        )));
+    }
+
+    let contents = fs::read(history_directory.join(format!("{}.txt", version))).await?;
+
+    Ok(std::str::from_utf8(&contents)?.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(strings: &[&str]) -> Vec<String> {
+        strings.iter().map(|string| string.to_string()).collect()
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(
+            diff(
+                "[Adblock Plus 2.0]\n! Version: 1\n||b.example^\n||a.example^\n||c.example^\n",
+                "! Version: 2\n||z.example^\n  ||a.example^  \n\n||d.example^\n||z.example^\n",
+            ),
+            (
+                strings(&["||z.example^", "||d.example^", "||z.example^"]),
+                strings(&["||b.example^", "||c.example^"])
+            )
+        );
+    }
+
+    #[test]
+    fn test_diff_moved_and_duplicated() {
+        assert_eq!(
+            diff(
+                "||a.example^\n||b.example^\n",
+                "||b.example^\n||a.example^\n||a.example^\n! Comment\n"
+            ),
+            (
+                strings(&["||b.example^", "||a.example^"]),
+                strings(&["||b.example^"])
+            )
+        );
+        assert_eq!(
+            diff("||a.example^\n", "! Version: 2\n\n||a.example^\n"),
+            (Vec::new(), Vec::new())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_record_change() {
+        let history_directory =
+            std::env::temp_dir().join(format!("privaxy-filter-history-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&history_directory).await;
+
+        let started_at = Utc::now();
+        let updated_at = |update: i64| started_at + chrono::Duration::seconds(update);
+
+        // Updates which don't change any rule aren't recorded.
+        record_change(
+            &history_directory,
+            "||a^\n",
+            "! Comment\n||a^\n",
+            updated_at(0),
+        )
+        .await
+        .unwrap();
+        assert!(get_versions(&history_directory).await.unwrap().is_empty());
+
+        for update in 0..KEPT_CHANGES as i64 + 2 {
+            record_change(
+                &history_directory,
+                &format!("||rule{}^\n", update),
+                &format!("||rule{}^\n", update + 1),
+                updated_at(update),
+            )
+            .await
+            .unwrap();
+        }
+
+        let versions = get_versions(&history_directory).await.unwrap();
+        assert_eq!(versions.len(), KEPT_CHANGES);
+
+        // The oldest changes are the ones pruned.
+        let oldest = fs::read(history_directory.join(format!("{}.json", versions[0])))
+            .await
+            .unwrap();
+        let oldest: FilterChange = serde_json::from_slice(&oldest).unwrap();
+        assert_eq!(oldest.updated_at, updated_at(2));
+        assert_eq!(oldest.added, strings(&["||rule3^"]));
+        assert_eq!(oldest.removed, strings(&["||rule2^"]));
+        assert_eq!(
+            fs::read_to_string(history_directory.join(format!("{}.txt", versions[0])))
+                .await
+                .unwrap(),
+            "||rule2^\n"
+        );
+
+        let mut entries = fs::read_dir(&history_directory).await.unwrap();
+        let mut entry_count = 0;
+        while entries.next_entry().await.unwrap().is_some() {
+            entry_count += 1;
+        }
+        assert_eq!(entry_count, KEPT_CHANGES * 2);
+
+        fs::remove_dir_all(&history_directory).await.unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+//! Sanity checks of downloaded filters, so that a truncated download or a captive portal page
+//! doesn't replace a good filter.
+use adblock::lists::{parse_filter, ParseOptions};
+use base64::{engine::general_purpose, Engine};
+use lazy_static::lazy_static;
+use openssl::hash::{hash, MessageDigest};
+use regex::Regex;
+use thiserror::Error;
+
+/// Largest filter accepted, the largest lists in use are a few dozen megabytes.
+const MAX_FILTER_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Share of the parsable rules of the previous version a new version has to keep. Lists don't
+/// shrink that much from one update to the next.
+const MIN_PARSABLE_RULES_RATIO: f64 = 0.5;
+
+lazy_static! {
+    static ref CHECKSUM_RE: Regex =
+        Regex::new(r"(?mi)^\s*!\s*checksum[\s\-:]+([\w+/=]+).*\n").unwrap();
+    static ref NEWLINES_RE: Regex = Regex::new(r"\n+").unwrap();
+}
+
+#[derive(Error, Debug)]
+pub enum FilterIntegrityError {
+    #[error("unexpected content type: {0}")]
+    UnexpectedContentType(String),
+    #[error("filter is too large: {0} bytes")]
+    TooLarge(u64),
+    #[error("checksum mismatch, expected {expected} but got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+    #[error("only {rules} parsable rules, the previous version had {previous_rules}")]
+    TooFewRules { rules: usize, previous_rules: usize },
+}
+
+/// Rejects HTML pages, typically served by captive portals or as error pages.
+pub(super) fn check_content_type(content_type: Option<&str>) -> Result<(), FilterIntegrityError> {
+    let mime_type = content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    match mime_type.as_str() {
+        "text/html" | "application/xhtml+xml" => {
+            Err(FilterIntegrityError::UnexpectedContentType(mime_type))
+        }
+        _ => Ok(()),
+    }
+}
+
+pub(super) fn check_size(size: u64) -> Result<(), FilterIntegrityError> {
+    if size > MAX_FILTER_SIZE {
+        return Err(FilterIntegrityError::TooLarge(size));
+    }
+
+    Ok(())
+}
+
+/// Checks the contents of a downloaded filter against the previous version of the filter. Empty
+/// or comment only lists are accepted unless the previous version had rules.
+pub(super) fn check(contents: &str, previous: Option<&str>) -> Result<(), FilterIntegrityError> {
+    let start = contents
+        .trim_start()
+        .chars()
+        .take(16)
+        .collect::<String>()
+        .to_ascii_lowercase();
+
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        return Err(FilterIntegrityError::UnexpectedContentType(
+            "text/html".to_string(),
+        ));
+    }
+
+    let rules = count_parsable_rules(contents);
+    let previous_rules = previous.map(count_parsable_rules).unwrap_or(0);
+
+    if (rules as f64) < previous_rules as f64 * MIN_PARSABLE_RULES_RATIO {
+        return Err(FilterIntegrityError::TooFewRules {
+            rules,
+            previous_rules,
+        });
+    }
+
+    Ok(())
+}
+
+/// Verifies the `! Checksum:` header of Adblock Plus lists: the base64 encoded MD5 digest of the
+/// list without the header, once line breaks are normalized.
+pub(super) fn check_checksum(contents: &str) -> Result<(), FilterIntegrityError> {
+    let normalized = contents.replace('\r', "");
+    let normalized = NEWLINES_RE.replace_all(&normalized, "\n");
+
+    let expected = match CHECKSUM_RE.captures(&normalized) {
+        Some(captures) => captures[1].trim_end_matches('=').to_string(),
+        None => return Ok(()),
+    };
+
+    let data = CHECKSUM_RE.replace(&normalized, "");
+
+    let digest = match hash(MessageDigest::md5(), data.as_bytes()) {
+        Ok(digest) => digest,
+        Err(err) => {
+            log::warn!("Unable to verify filter checksum: {}", err);
+            return Ok(());
+        }
+    };
+
+    let actual = general_purpose::STANDARD_NO_PAD.encode(digest);
+
+    if actual != expected {
+        return Err(FilterIntegrityError::ChecksumMismatch { expected, actual });
+    }
+
+    Ok(())
+}
+
+fn count_parsable_rules(contents: &str) -> usize {
+    contents
+        .lines()
+        .filter(|line| parse_filter(line, false, ParseOptions::default()).is_ok())
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIST: &str = "[Adblock Plus 2.0]\n! Checksum: d4tw6NdvRa1yZH7Iuo0TpQ\n! Title: Test list\n||example.com^\n##.ad\n";
+
+    #[test]
+    fn test_checksum_matches() {
+        assert!(check_checksum(LIST).is_ok());
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let tampered = LIST.replace("example.com", "example.org");
+
+        assert!(matches!(
+            check_checksum(&tampered),
+            Err(FilterIntegrityError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_checksum_crlf() {
+        assert!(check_checksum(&LIST.replace('\n', "\r\n")).is_ok());
+    }
+
+    #[test]
+    fn test_checksum_missing() {
+        assert!(check_checksum("||example.com^\n").is_ok());
+    }
+
+    #[test]
+    fn test_check_empty_list() {
+        assert!(check("! Title: Empty list\n", None).is_ok());
+        assert!(check("! Title: Empty list\n", Some("! Title: Empty list\n")).is_ok());
+        assert!(matches!(
+            check("! Title: Emptied list\n", Some("||example.com^\n")),
+            Err(FilterIntegrityError::TooFewRules { .. })
+        ));
+    }
+
+    #[test]
+    fn test_check_shrinking_list() {
+        let previous = "||a.example^\n||b.example^\n||c.example^\n||d.example^\n";
+
+        assert!(check("||a.example^\n||b.example^\n", Some(previous)).is_ok());
+        assert!(check("||a.example^\n", Some(previous)).is_err());
+        assert!(check("||a.example^\n", None).is_ok());
+    }
+
+    #[test]
+    fn test_check_html() {
+        assert!(matches!(
+            check("<!DOCTYPE html><html></html>", None),
+            Err(FilterIntegrityError::UnexpectedContentType(_))
+        ));
+    }
+}
@@ -0,0 +1,46 @@
+//! Progress of filter updates, streamed to the web interface.
+use super::Filter;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+pub type FilterUpdateSender = broadcast::Sender<FilterUpdateEvent>;
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum FilterUpdateStatus {
+    Downloading,
+    Unchanged,
+    Updated,
+    Failed {
+        reason: String,
+    },
+    /// The blocking engine was rebuilt out of the filters, concluding an update.
+    EngineRebuilt,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FilterUpdateEvent {
+    pub now: DateTime<Utc>,
+    /// Filter the event is about, none for events about all the filters.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(flatten)]
+    pub status: FilterUpdateStatus,
+}
+
+/// Reports the progress of an update, whether anyone is listening or not.
+pub(super) fn report(
+    filter_updates: &FilterUpdateSender,
+    filter: Option<&Filter>,
+    status: FilterUpdateStatus,
+) {
+    let _ = filter_updates.send(FilterUpdateEvent {
+        now: Utc::now(),
+        file_name: filter.map(|filter| filter.file_name.clone()),
+        title: filter.map(|filter| filter.title.clone()),
+        status,
+    });
+}
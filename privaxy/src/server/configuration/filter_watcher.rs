@@ -0,0 +1,85 @@
+//! Watches local filter sources, so that edits to them are applied without waiting for the next
+//! update of the filters.
+use super::Filter;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+/// Time given to a batch of changes, such as a `git pull`, to settle before filters are reloaded.
+const SETTLE_DELAY: Duration = Duration::from_millis(500);
+
+/// Directories to watch for the local sources of `filters`. Files are watched through their
+/// parent directory, as editors and git replace files rather than writing to them.
+fn get_watched_paths<'a>(filters: impl Iterator<Item = &'a Filter>) -> BTreeSet<PathBuf> {
+    filters
+        .filter_map(Filter::get_local_path)
+        .filter_map(|path| {
+            if path.is_dir() {
+                Some(path)
+            } else {
+                path.parent().map(PathBuf::from)
+            }
+        })
+        .collect()
+}
+
+pub(super) struct FilterWatcher {
+    /// Watching stops once dropped.
+    _watcher: RecommendedWatcher,
+    changes: UnboundedReceiver<()>,
+}
+
+impl FilterWatcher {
+    /// Returns a watcher for the local sources of the enabled `filters`, if there are any.
+    pub(super) fn new<'a>(filters: impl Iterator<Item = &'a Filter>) -> Option<Self> {
+        let paths = get_watched_paths(filters);
+
+        if paths.is_empty() {
+            return None;
+        }
+
+        let (sender, changes) = unbounded_channel();
+
+        let watcher =
+            notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+                Ok(event) if !event.kind.is_access() => {
+                    let _ = sender.send(());
+                }
+                Ok(_event) => {}
+                Err(err) => log::error!("Unable to watch local filters: {}", err),
+            });
+
+        let mut watcher = match watcher {
+            Ok(watcher) => watcher,
+            Err(err) => {
+                log::error!("Unable to watch local filters: {}", err);
+                return None;
+            }
+        };
+
+        for path in paths {
+            match watcher.watch(&path, RecursiveMode::NonRecursive) {
+                Ok(()) => log::debug!("Watching {} for filter changes", path.display()),
+                Err(err) => log::error!("Unable to watch {}: {}", path.display(), err),
+            }
+        }
+
+        Some(Self {
+            _watcher: watcher,
+            changes,
+        })
+    }
+
+    /// Resolves once a local source changed.
+    pub(super) async fn changed(&mut self) {
+        if self.changes.recv().await.is_none() {
+            return std::future::pending().await;
+        }
+
+        tokio::time::sleep(SETTLE_DELAY).await;
+
+        while self.changes.try_recv().is_ok() {}
+    }
+}
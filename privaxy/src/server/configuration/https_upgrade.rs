@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use std::time::Duration;
+use tokio::fs;
+use url::Url;
+
+/// Chromium's HSTS preload list.
+const DEFAULT_HSTS_PRELOAD_LIST_URL: &str = "https://raw.githubusercontent.com/chromium/chromium/main/net/http/transport_security_state_static.json";
+
+/// The preload list is large and changes slowly, it isn't downloaded along with every filters
+/// update.
+const HSTS_PRELOAD_LIST_MAX_AGE: Duration = Duration::from_secs(60 * 60 * 24);
+
+#[serde_as]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+/// Upgrade of plain HTTP requests to HTTPS.
+pub struct HttpsUpgradeConfig {
+    /// Attempt HTTPS first for plain HTTP requests.
+    pub enabled: bool,
+    /// Hosts in this list are never fetched over plain HTTP.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hsts_preload_list_url: Option<Url>,
+    /// Time, in seconds, during which hosts that failed to answer over HTTPS are reached over
+    /// plain HTTP without trying HTTPS again.
+    pub failure_memory_secs: u64,
+}
+
+impl Default for HttpsUpgradeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            hsts_preload_list_url: Some(Url::parse(DEFAULT_HSTS_PRELOAD_LIST_URL).unwrap()),
+            failure_memory_secs: 60 * 60 * 24,
+        }
+    }
+}
+
+impl HttpsUpgradeConfig {
+    /// Returns the content of the HSTS preload list, downloading it again once it's a day old.
+    /// A stale copy is used when it can't be downloaded.
+    pub(crate) async fn get_hsts_preload_list(
+        &self,
+        http_client: &reqwest::Client,
+    ) -> Option<String> {
+        let url = self.hsts_preload_list_url.as_ref()?;
+
+        let list_path = super::filter::get_filter_directory()
+            .join(super::filter::calc_filter_filename(url.as_str()));
+
+        let is_fresh = fs::metadata(&list_path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .map(|age| age < HSTS_PRELOAD_LIST_MAX_AGE)
+            .unwrap_or(false);
+
+        if !is_fresh {
+            match download(url, http_client).await {
+                Ok(content) => {
+                    if let Err(err) = store(&list_path, &content).await {
+                        log::error!("Unable to store HSTS preload list: {:?}", err);
+                    }
+                    return Some(content);
+                }
+                Err(err) => log::error!("Unable to download HSTS preload list: {:?}", err),
+            }
+        }
+
+        fs::read_to_string(&list_path).await.ok()
+    }
+}
+
+async fn download(url: &Url, http_client: &reqwest::Client) -> super::ConfigurationResult<String> {
+    let response = http_client.get(url.as_str()).send().await?;
+    if !response.status().is_success() {
+        return Err(super::ConfigurationError::FilterError(format!(
+            "Failed to fetch HSTS preload list: {}",
+            response.status()
+        )));
+    }
+
+    Ok(response.text().await?)
+}
+
+async fn store(list_path: &std::path::Path, content: &str) -> std::io::Result<()> {
+    fs::create_dir_all(super::filter::get_filter_directory()).await?;
+    fs::write(list_path, content).await
+}
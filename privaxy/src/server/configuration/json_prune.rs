@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+/// Properties to delete from JSON responses, the configuration file counterpart of `$jsonprune`
+/// filters.
+pub struct JsonPruneConfig {
+    /// Pattern of the URLs whose responses are pruned, using the filter lists syntax such as
+    /// `||example.com/api/player`.
+    pub url_pattern: String,
+    /// Paths of the properties to delete, such as `playerAds` or `..adPlacements`.
+    pub paths: Vec<String>,
+}
+
+impl JsonPruneConfig {
+    /// Returns the rule written as a `$jsonprune` filter.
+    pub(crate) fn to_filter(&self) -> String {
+        format!(
+            "{}$jsonprune={}",
+            self.url_pattern,
+            self.paths.join(" ").replace(',', "\\,")
+        )
+    }
+}
@@ -0,0 +1,294 @@
+//! Line diff of filter contents, using Myers' algorithm in its linear space variant.
+//!
+//! Filter lists run into hundreds of thousands of lines, but updates usually change few of them.
+//! The search for the shortest diff is bounded all the same: past [`MAX_EDIT_DISTANCE`], the
+//! lines left to compare are reported as removed and added as a whole.
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Edit distance at which the search of a part of the diff is given up.
+const MAX_EDIT_DISTANCE: usize = 2000;
+
+/// Returns the lines removed from `previous` and added to `current`, in the order they are
+/// listed. Moved lines are both removed and added, duplicated lines are reported each time.
+pub(super) fn diff<'a>(previous: &[&'a str], current: &[&'a str]) -> (Vec<&'a str>, Vec<&'a str>) {
+    // Hashes are compared first, lines only when they collide.
+    let hashes = |lines: &[&str]| {
+        lines
+            .iter()
+            .map(|line| {
+                let mut hasher = DefaultHasher::new();
+                line.hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut diff = Diff {
+        previous: Lines {
+            lines: previous,
+            hashes: hashes(previous),
+        },
+        current: Lines {
+            lines: current,
+            hashes: hashes(current),
+        },
+        removed: Vec::new(),
+        added: Vec::new(),
+    };
+
+    diff.compare(0, previous.len(), 0, current.len());
+
+    let removed = diff
+        .removed
+        .into_iter()
+        .map(|index| previous[index])
+        .collect();
+    let added = diff.added.into_iter().map(|index| current[index]).collect();
+
+    (removed, added)
+}
+
+struct Lines<'a, 'b> {
+    lines: &'b [&'a str],
+    hashes: Vec<u64>,
+}
+
+struct Diff<'a, 'b> {
+    previous: Lines<'a, 'b>,
+    current: Lines<'a, 'b>,
+    /// Indexes of the removed lines of `previous`.
+    removed: Vec<usize>,
+    /// Indexes of the added lines of `current`.
+    added: Vec<usize>,
+}
+
+/// The middle snake of a part of the diff: lines `x..u` of `previous` match lines `y..v` of
+/// `current`.
+struct Snake {
+    x: usize,
+    y: usize,
+    u: usize,
+    v: usize,
+}
+
+impl Diff<'_, '_> {
+    fn is_equal(&self, previous_index: usize, current_index: usize) -> bool {
+        self.previous.hashes[previous_index] == self.current.hashes[current_index]
+            && self.previous.lines[previous_index] == self.current.lines[current_index]
+    }
+
+    /// Compares lines `previous_start..previous_end` of `previous` with lines
+    /// `current_start..current_end` of `current`.
+    fn compare(
+        &mut self,
+        mut previous_start: usize,
+        mut previous_end: usize,
+        mut current_start: usize,
+        mut current_end: usize,
+    ) {
+        while previous_start < previous_end
+            && current_start < current_end
+            && self.is_equal(previous_start, current_start)
+        {
+            previous_start += 1;
+            current_start += 1;
+        }
+
+        while previous_start < previous_end
+            && current_start < current_end
+            && self.is_equal(previous_end - 1, current_end - 1)
+        {
+            previous_end -= 1;
+            current_end -= 1;
+        }
+
+        if previous_start == previous_end || current_start == current_end {
+            self.removed.extend(previous_start..previous_end);
+            self.added.extend(current_start..current_end);
+            return;
+        }
+
+        match self.find_middle_snake(previous_start, previous_end, current_start, current_end) {
+            Some(snake) => {
+                self.compare(previous_start, snake.x, current_start, snake.y);
+                self.compare(snake.u, previous_end, snake.v, current_end);
+            }
+            None => {
+                self.removed.extend(previous_start..previous_end);
+                self.added.extend(current_start..current_end);
+            }
+        }
+    }
+
+    /// Looks for the shortest diff from both ends of the compared lines at once, returning where
+    /// the two searches meet. Returns `None` when the lines differ too much.
+    fn find_middle_snake(
+        &self,
+        previous_start: usize,
+        previous_end: usize,
+        current_start: usize,
+        current_end: usize,
+    ) -> Option<Snake> {
+        let n = (previous_end - previous_start) as isize;
+        let m = (current_end - current_start) as isize;
+        let delta = n - m;
+        let is_odd = delta % 2 != 0;
+        let max_distance = ((n + m + 1) / 2).min(MAX_EDIT_DISTANCE as isize);
+
+        // Furthest reaching `x` per diagonal `k = x - y`, going forward and backward.
+        let offset = max_distance + 1;
+        let mut forward = vec![0isize; 2 * offset as usize + 1];
+        let mut backward = vec![0isize; 2 * offset as usize + 1];
+        let index = |k: isize| (k + offset) as usize;
+
+        let is_equal = |x: isize, y: isize| {
+            self.is_equal(previous_start + x as usize, current_start + y as usize)
+        };
+
+        for d in 0..=max_distance {
+            for k in (-d..=d).step_by(2) {
+                let mut x = if k == -d || (k != d && forward[index(k - 1)] < forward[index(k + 1)])
+                {
+                    forward[index(k + 1)]
+                } else {
+                    forward[index(k - 1)] + 1
+                };
+                let mut y = x - k;
+                let (start_x, start_y) = (x, y);
+
+                while x < n && y < m && is_equal(x, y) {
+                    x += 1;
+                    y += 1;
+                }
+                forward[index(k)] = x;
+
+                let backward_k = delta - k;
+                if is_odd
+                    && backward_k.abs() < d
+                    && forward[index(k)] + backward[index(backward_k)] >= n
+                {
+                    return Some(Snake {
+                        x: previous_start + start_x as usize,
+                        y: current_start + start_y as usize,
+                        u: previous_start + x as usize,
+                        v: current_start + y as usize,
+                    });
+                }
+            }
+
+            for k in (-d..=d).step_by(2) {
+                let mut x =
+                    if k == -d || (k != d && backward[index(k - 1)] < backward[index(k + 1)]) {
+                        backward[index(k + 1)]
+                    } else {
+                        backward[index(k - 1)] + 1
+                    };
+                let mut y = x - k;
+                let (start_x, start_y) = (x, y);
+
+                while x < n && y < m && is_equal(n - 1 - x, m - 1 - y) {
+                    x += 1;
+                    y += 1;
+                }
+                backward[index(k)] = x;
+
+                let forward_k = delta - k;
+                if !is_odd
+                    && forward_k.abs() <= d
+                    && backward[index(k)] + forward[index(forward_k)] >= n
+                {
+                    return Some(Snake {
+                        x: previous_start + (n - x) as usize,
+                        y: current_start + (m - y) as usize,
+                        u: previous_start + (n - start_x) as usize,
+                        v: current_start + (m - start_y) as usize,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(contents: &str) -> Vec<&str> {
+        contents.split_whitespace().collect()
+    }
+
+    fn diff_lines<'a>(previous: &'a str, current: &'a str) -> (Vec<&'a str>, Vec<&'a str>) {
+        diff(&lines(previous), &lines(current))
+    }
+
+    /// Checks that applying the diff to `previous` gives `current`, as long as lines are unique.
+    fn assert_consistent(previous: &str, current: &str) {
+        let (removed, added) = diff_lines(previous, current);
+
+        let kept = lines(previous)
+            .into_iter()
+            .filter(|line| !removed.contains(line))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kept,
+            lines(current)
+                .into_iter()
+                .filter(|line| !added.contains(line))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff_lines("a b c", "a b c"), (vec![], vec![]));
+        assert_eq!(diff_lines("a b c", "a x c"), (vec!["b"], vec!["x"]));
+        assert_eq!(diff_lines("", "a b"), (vec![], vec!["a", "b"]));
+        assert_eq!(diff_lines("a b", ""), (vec!["a", "b"], vec![]));
+        assert_eq!(
+            diff_lines("a b c d e f", "x a c d y f z"),
+            (vec!["b", "e"], vec!["x", "y", "z"])
+        );
+    }
+
+    #[test]
+    fn test_diff_is_shortest() {
+        // The classic example of Myers' paper, with an edit distance of 5.
+        let (removed, added) = diff_lines("a b c a b b a", "c b a b a c");
+        assert_eq!(removed.len() + added.len(), 5);
+    }
+
+    #[test]
+    fn test_moved_and_duplicated_lines() {
+        assert_eq!(diff_lines("a b c", "b c a"), (vec!["a"], vec!["a"]));
+        assert_eq!(diff_lines("a b", "a a b b"), (vec![], vec!["a", "b"]));
+        assert_eq!(diff_lines("a a b", "a b"), (vec!["a"], vec![]));
+    }
+
+    #[test]
+    fn test_consistency() {
+        assert_consistent("a b c d e f g h", "h b d x e y g a");
+        assert_consistent("a b c d", "e f g h");
+        assert_consistent("a c e g i k", "b c d g h k l");
+    }
+
+    #[test]
+    fn test_large_diff() {
+        let previous = (0..3 * MAX_EDIT_DISTANCE)
+            .map(|line| format!("||{}.example^", line))
+            .collect::<Vec<_>>();
+        let current = (0..3 * MAX_EDIT_DISTANCE)
+            .map(|line| format!("||{}.test^", line))
+            .chain(std::iter::once("||0.example^".to_string()))
+            .collect::<Vec<_>>();
+        let previous = previous.iter().map(String::as_str).collect::<Vec<_>>();
+        let current = current.iter().map(String::as_str).collect::<Vec<_>>();
+
+        // The search is given up, the line left in place is reported as moved.
+        let (removed, added) = diff(&previous, &current);
+        assert_eq!(removed, previous);
+        assert_eq!(added, current);
+    }
+}
@@ -2,14 +2,36 @@ use serde::{Deserialize, Serialize};
 use std::{collections::BTreeSet, time::Duration};
 use thiserror::Error;
 use tokio::fs;
+mod body_rewriting;
 mod ca;
+mod clear_urls;
 mod filter;
+mod filter_export;
+mod filter_format;
+mod filter_history;
+mod filter_integrity;
+mod filter_update;
+mod filter_watcher;
+mod https_upgrade;
+mod json_prune;
+mod line_diff;
 mod network;
+mod privacy;
 mod updater;
+pub use body_rewriting::*;
 pub use ca::*;
+pub use clear_urls::*;
 pub use filter::*;
-use futures::future::try_join_all;
+pub use filter_export::*;
+pub use filter_format::*;
+pub use filter_history::*;
+pub use filter_integrity::*;
+pub use filter_update::*;
+use futures::future::join_all;
+pub use https_upgrade::*;
+pub use json_prune::*;
 pub use network::*;
+pub use privacy::*;
 use std::env;
 use std::path::{Path, PathBuf};
 pub use updater::*;
@@ -22,6 +44,9 @@ pub(crate) const CONFIGURATION_FILE_NAME: &str = "config";
 /// Default configuration directory name.
 const CONFIGURATION_DIRECTORY_NAME: &str = "/etc/privaxy";
 
+/// Name of the directory, within the configuration directory, FilterLists responses are cached in.
+const FILTERLISTS_CACHE_DIRECTORY_NAME: &str = "filterlists";
+
 #[derive(Error, Debug)]
 pub enum ConfigurationError {
     #[error("NetworkConfigError error: {0}")]
@@ -42,6 +67,8 @@ pub enum ConfigurationError {
     UnableToDecodePem(#[from] openssl::error::ErrorStack),
     #[error("filter error: {0}")]
     FilterError(String),
+    #[error("filter integrity error: {0}")]
+    FilterIntegrityError(#[from] FilterIntegrityError),
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
@@ -51,6 +78,17 @@ pub struct Configuration {
     pub ca: Ca,
     pub network: NetworkConfig,
     pub filters: Vec<Filter>,
+    #[serde(default)]
+    pub body_rewriting: BodyRewritingConfig,
+    #[serde(default)]
+    pub json_prune: Vec<JsonPruneConfig>,
+    #[serde(default)]
+    pub privacy: PrivacyConfig,
+    /// Configurations predating ClearURLs support don't get it enabled, it has to be opted into.
+    #[serde(default)]
+    pub clear_urls: Vec<ClearUrlsList>,
+    #[serde(default)]
+    pub https_upgrade: HttpsUpgradeConfig,
 }
 
 #[derive(Error, Debug)]
@@ -148,10 +186,12 @@ impl Configuration {
         Ok(())
     }
 
-    pub async fn set_filter_enabled_status(
+    /// Sets whether a filter is enabled and, when `observe` is given, whether it is observed.
+    pub async fn set_filter_status(
         &mut self,
         filter_file_name: &str,
         enabled: bool,
+        observe: Option<bool>,
     ) -> ConfigurationResult<()> {
         let filter = self
             .filters
@@ -160,6 +200,9 @@ impl Configuration {
 
         if let Some(filter) = filter {
             filter.enabled = enabled;
+            if let Some(observe) = observe {
+                filter.observe = observe;
+            }
         }
 
         self.save().await?;
@@ -170,31 +213,134 @@ impl Configuration {
         self.filters.iter_mut().filter(|f| f.enabled)
     }
 
+    /// Updates the enabled filters and ClearURLs lists. A list failing to update doesn't keep
+    /// the others from being updated.
     pub async fn update_filters(
         &mut self,
-        http_client: reqwest::Client,
+        http_client: &reqwest::Client,
+        filter_updates: &FilterUpdateSender,
     ) -> ConfigurationResult<()> {
         log::debug!("Updating filters");
 
-        let futures = self.filters.iter_mut().filter_map(|filter| {
-            if filter.enabled {
-                Some(filter.update(&http_client))
-            } else {
-                None
+        let futures = self
+            .filters
+            .iter_mut()
+            .filter(|filter| filter.enabled && filter.pinned_version.is_none())
+            .map(|filter| filter.update_reporting(http_client, filter_updates, false));
+
+        let mut failures = join_all(futures)
+            .await
+            .into_iter()
+            .filter(Result::is_err)
+            .count();
+
+        let futures = self
+            .clear_urls
+            .iter()
+            .filter(|list| list.enabled)
+            .map(|list| list.update(http_client));
+
+        for result in join_all(futures).await {
+            if let Err(err) = result {
+                log::error!("Failed to update ClearURLs list: {}", err);
+                failures += 1;
             }
-        });
+        }
 
-        try_join_all(futures).await?;
+        if failures > 0 {
+            return Err(ConfigurationError::FilterError(format!(
+                "{} lists failed to update",
+                failures
+            )));
+        }
 
         Ok(())
     }
 
+    /// Updates the enabled local filters which aren't pinned, after their sources changed.
+    pub(crate) async fn update_local_filters(
+        &mut self,
+        http_client: &reqwest::Client,
+        filter_updates: &FilterUpdateSender,
+    ) {
+        let futures = self
+            .filters
+            .iter_mut()
+            .filter(|filter| {
+                filter.enabled
+                    && filter.pinned_version.is_none()
+                    && filter.get_local_path().is_some()
+            })
+            .map(|filter| filter.update_reporting(http_client, filter_updates, false));
+
+        // Failures are reported as they happen, the stored copy remains in use.
+        join_all(futures).await;
+    }
+
+    /// Updates a single filter, disabled filters included. Pinned filters have to be unpinned
+    /// first. `accept_shrinking` accepts a list that legitimately lost most of its rules.
+    pub async fn update_filter(
+        &mut self,
+        file_name: &str,
+        http_client: &reqwest::Client,
+        filter_updates: &FilterUpdateSender,
+        accept_shrinking: bool,
+    ) -> ConfigurationResult<()> {
+        let filter = self.get_filter_mut(file_name)?;
+
+        if filter.pinned_version.is_some() {
+            return Err(ConfigurationError::FilterError(format!(
+                "Filter {} is pinned",
+                file_name
+            )));
+        }
+
+        filter
+            .update_reporting(http_client, filter_updates, accept_shrinking)
+            .await
+    }
+
+    /// Pins a filter to the contents it had before the update recorded as `version`.
+    pub async fn pin_filter(&mut self, file_name: &str, version: &str) -> ConfigurationResult<()> {
+        self.get_filter_mut(file_name)?.pin(version).await?;
+
+        self.save().await
+    }
+
+    /// Unpins a filter, bringing it up to date. The filter stays pinned when the update fails.
+    pub async fn unpin_filter(
+        &mut self,
+        file_name: &str,
+        http_client: &reqwest::Client,
+        filter_updates: &FilterUpdateSender,
+    ) -> ConfigurationResult<()> {
+        let filter = self.get_filter_mut(file_name)?;
+        let pinned_version = filter.pinned_version.take();
+
+        if let Err(err) = filter
+            .update_reporting(http_client, filter_updates, false)
+            .await
+        {
+            filter.pinned_version = pinned_version;
+            return Err(err);
+        }
+
+        self.save().await
+    }
+
+    fn get_filter_mut(&mut self, file_name: &str) -> ConfigurationResult<&mut Filter> {
+        self.filters
+            .iter_mut()
+            .find(|filter| filter.file_name == file_name)
+            .ok_or_else(|| ConfigurationError::FilterError(format!("Unknown filter {}", file_name)))
+    }
+
     pub async fn add_filter(
         &mut self,
         filter: &mut Filter,
         http_client: &reqwest::Client,
     ) -> ConfigurationResult<()> {
-        match filter.update(http_client).await {
+        match filter.update(http_client, false).await {
             Ok(_) => {
                 self.filters.push(filter.clone());
                 Ok(())
@@ -265,6 +411,11 @@ impl Configuration {
             },
             exclusions: BTreeSet::new(),
             custom_filters: Vec::new(),
+            body_rewriting: BodyRewritingConfig::default(),
+            json_prune: Vec::new(),
+            privacy: PrivacyConfig::default(),
+            clear_urls: ClearUrlsList::default_lists(),
+            https_upgrade: HttpsUpgradeConfig::default(),
         })
     }
 }
@@ -275,6 +426,10 @@ pub(crate) fn get_config_file() -> PathBuf {
         .join(CONFIGURATION_FILE_NAME)
 }
 
+/// Directory FilterLists responses are cached in.
+pub(crate) fn get_filterlists_cache_directory() -> ConfigurationResult<PathBuf> {
+    Ok(get_base_directory()?.join(FILTERLISTS_CACHE_DIRECTORY_NAME))
+}
 
 fn get_base_directory() -> ConfigurationResult<PathBuf> {
     let base_directory: PathBuf = match env::var("PRIVAXY_BASE_PATH") {
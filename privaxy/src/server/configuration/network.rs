@@ -20,7 +20,7 @@ use openssl::{
         X509NameBuilder, X509Ref, X509Req, X509ReqBuilder, X509,
     },
 };
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 #[serde_as]
@@ -244,6 +244,17 @@ impl NetworkConfig {
         };
         ip_addr
     }
+
+    /// URL of the web interface for clients reaching Privaxy at `ip_address`, the web server
+    /// listening on the same address as the proxy.
+    pub(crate) fn get_web_gui_url(&self, ip_address: IpAddr) -> String {
+        match (&self.listen_url, self.tls) {
+            // The certificate is only valid for the listen URL.
+            (Some(listen_url), true) => format!("https://{}:{}", listen_url, self.web_port),
+            (_, true) => format!("https://{}", SocketAddr::new(ip_address, self.web_port)),
+            (_, false) => format!("http://{}", SocketAddr::new(ip_address, self.web_port)),
+        }
+    }
 }
 
 fn build_certificate_request(key_pair: &PKey<Private>, authority: String) -> X509Req {
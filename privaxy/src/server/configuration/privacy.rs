@@ -0,0 +1,29 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+/// Header hardening applied to every proxied request, to reduce passive tracking. Every action
+/// is opt-in: trimming the `Referer` may break hotlink protected assets, payments or single sign
+/// on.
+pub struct PrivacyConfig {
+    /// Trim the `Referer` of cross-site requests to the origin of the referring page.
+    pub trim_cross_site_referer: bool,
+    /// Send `Sec-GPC: 1`, the Global Privacy Control signal.
+    pub global_privacy_control: bool,
+    /// Send `DNT: 1`.
+    pub do_not_track: bool,
+    /// Remove headers browsers or carriers use to identify users, such as `X-Client-Data`.
+    pub strip_tracking_headers: bool,
+    /// `User-Agent` sent in place of the browser's one. High entropy client hints are removed
+    /// as well, as they would give the actual browser away.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// `Accept-Language` sent in place of the browser's one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accept_language: Option<String>,
+    /// Drop cookies sent to, and set by, third parties.
+    pub block_third_party_cookies: bool,
+    /// Sites, along with their subdomains, to which no hardening is applied.
+    pub exemptions: BTreeSet<String>,
+}
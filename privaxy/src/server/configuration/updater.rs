@@ -1,13 +1,24 @@
+use super::filter_watcher::FilterWatcher;
 use crate::blocker::AdblockRequester;
+use crate::filter_lint::EnabledRulesStore;
+use crate::hsts_preload::HstsPreloadList;
 use futures::future::{AbortHandle, Abortable};
 
 use tokio::sync::mpsc::Receiver;
-use tokio::sync::{self, mpsc::Sender};
+use tokio::sync::{self, broadcast, mpsc::Sender};
+
+/// Number of filter update events kept for slow listeners, enough for every filter to report
+/// its progress.
+const FILTER_UPDATES_CAPACITY: usize = 512;
 
 pub struct ConfigurationUpdater {
     filters_updater_abort_handle: AbortHandle,
     rx: Receiver<super::Configuration>,
     pub tx: Sender<super::Configuration>,
+    /// Progress of filter updates, periodic and manual ones alike.
+    pub filter_updates: super::FilterUpdateSender,
+    /// Rules of the enabled filters, which custom filters are linted against.
+    pub enabled_rules: EnabledRulesStore,
     http_client: reqwest::Client,
     adblock_requester: AdblockRequester,
 }
@@ -29,8 +40,13 @@ impl ConfigurationUpdater {
             None => sync::mpsc::channel(1),
         };
 
+        let (filter_updates, _) = broadcast::channel(FILTER_UPDATES_CAPACITY);
+        let enabled_rules = EnabledRulesStore::default();
+
         let http_client_clone = http_client.clone();
         let adblock_requester_clone = adblock_requester.clone();
+        let filter_updates_clone = filter_updates.clone();
+        let enabled_rules_clone = enabled_rules.clone();
 
         let filters_updater = Abortable::new(
             async move {
@@ -38,6 +54,8 @@ impl ConfigurationUpdater {
                     configuration,
                     adblock_requester_clone,
                     http_client_clone.clone(),
+                    filter_updates_clone,
+                    enabled_rules_clone,
                 )
                 .await
             },
@@ -50,6 +68,8 @@ impl ConfigurationUpdater {
             filters_updater_abort_handle: abort_handle,
             rx,
             tx,
+            filter_updates,
+            enabled_rules,
             http_client,
             adblock_requester,
         }
@@ -61,21 +81,38 @@ impl ConfigurationUpdater {
                 let mut configuration = self.rx.recv().await.unwrap();
                 self.filters_updater_abort_handle.abort();
 
-                let filters =
-                    super::filter::get_filters_content(&mut configuration, &self.http_client).await;
-                self.adblock_requester.replace_engine(filters).await;
+                Self::apply_filters(
+                    &mut configuration,
+                    &self.adblock_requester,
+                    &self.http_client,
+                    &self.filter_updates,
+                    &self.enabled_rules,
+                )
+                .await;
 
                 let adblock_requester_clone = self.adblock_requester.clone();
                 let http_client_clone = self.http_client.clone();
-
-                tokio::spawn(async move {
-                    Self::filters_updater(
-                        configuration,
-                        adblock_requester_clone,
-                        http_client_clone,
-                    )
-                    .await;
-                });
+                let filter_updates_clone = self.filter_updates.clone();
+                let enabled_rules_clone = self.enabled_rules.clone();
+
+                // Keeping the updater abortable, so that it doesn't keep watching the local
+                // filters of a replaced configuration.
+                let (abort_handle, abort_registration) = AbortHandle::new_pair();
+                self.filters_updater_abort_handle = abort_handle;
+
+                tokio::spawn(Abortable::new(
+                    async move {
+                        Self::filters_updater(
+                            configuration,
+                            adblock_requester_clone,
+                            http_client_clone,
+                            filter_updates_clone,
+                            enabled_rules_clone,
+                        )
+                        .await;
+                    },
+                    abort_registration,
+                ));
 
                 log::info!("Applied new configuration");
             }
@@ -86,21 +123,121 @@ impl ConfigurationUpdater {
         mut configuration: super::Configuration,
         adblock_requester: AdblockRequester,
         http_client: reqwest::Client,
+        filter_updates: super::FilterUpdateSender,
+        enabled_rules: EnabledRulesStore,
     ) {
+        let mut filter_watcher =
+            FilterWatcher::new(configuration.filters.iter().filter(|filter| filter.enabled));
+        let mut update_interval = tokio::time::interval_at(
+            tokio::time::Instant::now() + super::FILTERS_UPDATE_AFTER,
+            super::FILTERS_UPDATE_AFTER,
+        );
+
         loop {
-            tokio::time::sleep(super::FILTERS_UPDATE_AFTER).await;
+            tokio::select! {
+                _ = update_interval.tick() => {},
+                _ = Self::local_filters_changed(&mut filter_watcher) => {
+                    log::info!("Local filters changed, reloading filters");
+                    configuration
+                        .update_local_filters(&http_client, &filter_updates)
+                        .await;
+                    Self::apply_filters(
+                        &mut configuration,
+                        &adblock_requester,
+                        &http_client,
+                        &filter_updates,
+                        &enabled_rules,
+                    )
+                    .await;
+                    continue;
+                }
+            }
 
-            if let Err(err) = configuration.update_filters(http_client.clone()).await {
+            if let Err(err) = configuration
+                .update_filters(&http_client, &filter_updates)
+                .await
+            {
                 log::error!("An error occured while trying to update filters: {:?}", err);
             }
 
             // We don't bother diffing the filters as replacing the engine is very cheap and
             // filters are not updated often enough that the cost would matter.
-            let filters =
-                super::filter::get_filters_content(&mut configuration, &http_client).await;
-            adblock_requester.replace_engine(filters).await;
+            Self::apply_filters(
+                &mut configuration,
+                &adblock_requester,
+                &http_client,
+                &filter_updates,
+                &enabled_rules,
+            )
+            .await;
 
             log::info!("Updated filters");
         }
     }
+
+    async fn local_filters_changed(filter_watcher: &mut Option<FilterWatcher>) {
+        match filter_watcher {
+            Some(filter_watcher) => filter_watcher.changed().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn apply_filters(
+        configuration: &mut super::Configuration,
+        adblock_requester: &AdblockRequester,
+        http_client: &reqwest::Client,
+        filter_updates: &super::FilterUpdateSender,
+        enabled_rules: &EnabledRulesStore,
+    ) {
+        let filters = super::filter::get_filters_content(configuration, http_client).await;
+        adblock_requester.replace_engine(filters).await;
+
+        let observed_filters =
+            super::filter::get_observed_filters_content(configuration, http_client).await;
+        adblock_requester
+            .replace_observed_engine(observed_filters)
+            .await;
+
+        super::filter_update::report(
+            filter_updates,
+            None,
+            super::FilterUpdateStatus::EngineRebuilt,
+        );
+
+        let enabled_filters =
+            super::filter::get_enabled_filters_content(configuration, http_client).await;
+        let enabled_rules = enabled_rules.clone();
+        if let Err(err) =
+            tokio::task::spawn_blocking(move || enabled_rules.replace(&enabled_filters)).await
+        {
+            log::error!("Unable to collect the rules of enabled filters: {}", err);
+        }
+
+        let clear_urls_rulesets =
+            super::clear_urls::get_clear_urls_lists_content(configuration, http_client).await;
+        adblock_requester
+            .replace_url_cleaner(clear_urls_rulesets)
+            .await;
+
+        if !configuration.https_upgrade.enabled {
+            return;
+        }
+
+        if let Some(content) = configuration
+            .https_upgrade
+            .get_hsts_preload_list(http_client)
+            .await
+        {
+            // The list is large, parsing it takes a while.
+            match tokio::task::spawn_blocking(move || HstsPreloadList::parse(&content)).await {
+                Ok(Ok(hsts_preload_list)) => {
+                    adblock_requester
+                        .replace_hsts_preload_list(hsts_preload_list)
+                        .await
+                }
+                Ok(Err(err)) => log::error!("Unable to parse HSTS preload list: {}", err),
+                Err(err) => log::error!("Unable to parse HSTS preload list: {}", err),
+            }
+        }
+    }
 }
@@ -0,0 +1,268 @@
+//! Cosmetic filters `adblock` doesn't support, extracted from the raw filter lists:
+//!
+//! - Procedural filters, such as `example.com##div:has-text(Sponsored)`, which are evaluated on
+//!   pages by an injected executor.
+//! - HTML filters, such as `example.com##^script:has-text(adsbygoogle)`, which remove elements
+//!   from documents before they reach the browser.
+//!
+//! Like uBlock Origin, only filters restricted to some hostnames are supported: generic filters
+//! of these kinds would be far too expensive to run on every page.
+use std::collections::{HashMap, HashSet};
+
+/// Operators that can't be expressed with plain CSS.
+const PROCEDURAL_OPERATORS: &[&str] = &[
+    ":-abp-contains(",
+    ":-abp-has(",
+    ":contains(",
+    ":has-text(",
+    ":if(",
+    ":if-not(",
+    ":matches-attr(",
+    ":matches-css(",
+    ":matches-css-after(",
+    ":matches-css-before(",
+    ":matches-path(",
+    ":min-text-length(",
+    ":nth-ancestor(",
+    ":others(",
+    ":remove(",
+    ":remove-attr(",
+    ":remove-class(",
+    ":upward(",
+    ":watch-attr(",
+    ":xpath(",
+];
+
+#[derive(Default)]
+pub(crate) struct ExtendedCosmeticFilters {
+    procedural: HostnameSelectors,
+    html: HostnameSelectors,
+}
+
+impl ExtendedCosmeticFilters {
+    pub(crate) fn from_filter_lists(filter_lists: &[String]) -> Self {
+        let mut filters = Self::default();
+
+        for line in filter_lists
+            .iter()
+            .flat_map(|filter_list| filter_list.lines())
+        {
+            filters.add_filter(line.trim());
+        }
+
+        filters
+    }
+
+    fn add_filter(&mut self, line: &str) {
+        if line.starts_with('!') {
+            return;
+        }
+
+        // Extended css syntax used by AdGuard and Adblock Plus.
+        let (hostnames, selector, is_exception) = if let Some((h, s)) = line.split_once("#?#") {
+            (h, s, false)
+        } else if let Some((h, s)) = line.split_once("#@?#") {
+            (h, s, true)
+        } else if let Some((h, s)) = line.split_once("#@#") {
+            (h, s, true)
+        } else if let Some((h, s)) = line.split_once("##") {
+            (h, s, false)
+        } else {
+            return;
+        };
+
+        if let Some(selector) = selector.strip_prefix('^') {
+            let selector = selector.trim();
+
+            // Response header removal shares the html filters syntax, it is handled by
+            // `header_filters`.
+            if !selector.is_empty() && !selector.starts_with("responseheader(") {
+                self.html.add(hostnames, selector, is_exception, false);
+            }
+
+            return;
+        }
+
+        let selector = selector.trim();
+
+        // Scriptlets are handled by `adblock`.
+        if selector.starts_with("+js(") {
+            return;
+        }
+
+        if !selector.is_empty() && is_procedural(selector) {
+            self.procedural
+                .add(hostnames, selector, is_exception, false);
+        }
+    }
+
+    pub(crate) fn procedural_len(&self) -> usize {
+        self.procedural.len()
+    }
+
+    pub(crate) fn html_len(&self) -> usize {
+        self.html.len()
+    }
+
+    /// Returns the selectors of the procedural filters that apply to `url`.
+    pub(crate) fn procedural_selectors_for_url(&self, url: &str) -> Vec<String> {
+        self.procedural.selectors_for_url(url)
+    }
+
+    /// Returns the selectors of the html filters that apply to `url`.
+    pub(crate) fn html_selectors_for_url(&self, url: &str) -> Vec<String> {
+        self.html.selectors_for_url(url)
+    }
+}
+
+struct HostnameSelector {
+    excluded_hostnames: Vec<String>,
+    selector: String,
+}
+
+/// Selectors indexed by the hostnames they apply to.
+#[derive(Default)]
+pub(crate) struct HostnameSelectors {
+    selectors: Vec<HostnameSelector>,
+    /// Indexes of `selectors` by hostname. Entities, like `example.*`, are kept as is. Generic
+    /// selectors have an empty hostname.
+    selectors_by_hostname: HashMap<String, Vec<usize>>,
+    /// Selectors disabled on a given hostname, or everywhere with an empty hostname.
+    exceptions: HashMap<String, HashSet<String>>,
+}
+
+impl HostnameSelectors {
+    /// Adds a selector restricted to a comma separated list of `hostnames`. Selectors without
+    /// any hostname are ignored unless `allow_generic` is set.
+    pub(crate) fn add(
+        &mut self,
+        hostnames: &str,
+        selector: &str,
+        is_exception: bool,
+        allow_generic: bool,
+    ) {
+        let mut included_hostnames = Vec::new();
+        let mut excluded_hostnames = Vec::new();
+
+        for hostname in hostnames.split(',').map(str::trim) {
+            if hostname.is_empty() {
+                continue;
+            }
+
+            match hostname.strip_prefix('~') {
+                Some(hostname) => excluded_hostnames.push(hostname.to_lowercase()),
+                None => included_hostnames.push(hostname.to_lowercase()),
+            }
+        }
+
+        if is_exception {
+            if included_hostnames.is_empty() {
+                included_hostnames.push(String::new());
+            }
+
+            for hostname in included_hostnames {
+                self.exceptions
+                    .entry(hostname)
+                    .or_default()
+                    .insert(selector.to_string());
+            }
+
+            return;
+        }
+
+        if included_hostnames.is_empty() {
+            if !allow_generic {
+                return;
+            }
+
+            included_hostnames.push(String::new());
+        }
+
+        let index = self.selectors.len();
+        self.selectors.push(HostnameSelector {
+            excluded_hostnames,
+            selector: selector.to_string(),
+        });
+
+        for hostname in included_hostnames {
+            self.selectors_by_hostname
+                .entry(hostname)
+                .or_default()
+                .push(index);
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.selectors.len()
+    }
+
+    pub(crate) fn selectors_for_url(&self, url: &str) -> Vec<String> {
+        let hostname = match url::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_lowercase))
+        {
+            Some(hostname) => hostname,
+            None => return Vec::new(),
+        };
+
+        let candidates = hostname_candidates(&hostname);
+
+        let mut indexes = std::iter::once("")
+            .chain(candidates.iter().map(String::as_str))
+            .filter_map(|candidate| self.selectors_by_hostname.get(candidate))
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        indexes.sort_unstable();
+        indexes.dedup();
+
+        let is_excepted = |selector: &str| {
+            std::iter::once("")
+                .chain(candidates.iter().map(String::as_str))
+                .filter_map(|candidate| self.exceptions.get(candidate))
+                .any(|exceptions| exceptions.contains(selector))
+        };
+
+        indexes
+            .into_iter()
+            .map(|index| &self.selectors[index])
+            .filter(|selector| {
+                !selector
+                    .excluded_hostnames
+                    .iter()
+                    .any(|excluded| candidates.contains(excluded))
+            })
+            .filter(|selector| !is_excepted(&selector.selector))
+            .map(|selector| selector.selector.clone())
+            .collect()
+    }
+}
+
+fn is_procedural(selector: &str) -> bool {
+    PROCEDURAL_OPERATORS
+        .iter()
+        .any(|operator| selector.contains(operator))
+}
+
+/// Returns `hostname`, its parent domains and the entities they match: `www.example.co.uk`
+/// gives `example.co.uk` and `example.*` among others.
+fn hostname_candidates(hostname: &str) -> Vec<String> {
+    let labels = hostname.split('.').collect::<Vec<_>>();
+    let mut candidates = Vec::new();
+
+    for start in 0..labels.len() {
+        candidates.push(labels[start..].join("."));
+
+        // Entities replace a public suffix of one or two labels.
+        for suffix_length in 1..=2 {
+            if labels.len() - start > suffix_length {
+                candidates.push(format!(
+                    "{}.*",
+                    labels[start..labels.len() - suffix_length].join(".")
+                ));
+            }
+        }
+    }
+
+    candidates
+}
@@ -0,0 +1,384 @@
+//! Diagnostics of custom filters, pointing out the rules the blocking engine would silently drop.
+use crate::cosmetic_filters::ExtendedCosmeticFilters;
+use crate::header_filters::HeaderFilters;
+use crate::json_prune_filters::JsonPruneFilters;
+use crate::replace_filters::ReplaceFilters;
+use adblock::filters::cosmetic::CosmeticFilterMask;
+use adblock::filters::network::NetworkFilterError;
+use adblock::lists::{parse_filter, FilterParseError, ParseOptions, ParsedFilter};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+/// Options of network rules supported by `adblock`, or handled by Privaxy itself.
+const SUPPORTED_OPTIONS: &[&str] = &[
+    "1p",
+    "3p",
+    "badfilter",
+    "beacon",
+    "css",
+    "csp",
+    "doc",
+    "document",
+    "domain",
+    "first-party",
+    "font",
+    "frame",
+    "from",
+    "generichide",
+    "ghide",
+    "image",
+    "important",
+    "jsonprune",
+    "match-case",
+    "media",
+    "object",
+    "object-subrequest",
+    "other",
+    "ping",
+    "redirect",
+    "redirect-rule",
+    "removeheader",
+    "removeparam",
+    "replace",
+    "script",
+    "stylesheet",
+    "subdocument",
+    "tag",
+    "third-party",
+    "websocket",
+    "xhr",
+    "xmlhttprequest",
+];
+
+/// Separators of cosmetic rules, extended css and scriptlet injection included.
+const COSMETIC_SEPARATORS: &[&str] = &["##", "#@#", "#?#", "#@?#", "#$#", "#@$#", "#%#", "#@%#"];
+
+/// Rules of the enabled filter lists, rebuilt when filters are applied rather than on every lint.
+#[derive(Debug, Clone, Default)]
+pub struct EnabledRulesStore(Arc<RwLock<HashSet<String>>>);
+
+impl EnabledRulesStore {
+    pub(crate) fn replace(&self, enabled_filters_content: &[String]) {
+        let enabled_rules = enabled_filters_content
+            .iter()
+            .flat_map(|content| content.lines())
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty() && !rule.starts_with('!'))
+            .map(str::to_string)
+            .collect();
+
+        *self.0.write().unwrap() = enabled_rules;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RuleKind {
+    Network,
+    Cosmetic,
+    Scriptlet,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Severity {
+    /// The rule is ignored.
+    Error,
+    /// The rule is loaded, but is useless, such as a duplicate.
+    Warning,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct FilterDiagnostic {
+    /// Line of the rule, starting from 1.
+    pub(crate) line: usize,
+    pub(crate) rule: String,
+    /// Kind of the rule, when it can be told.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) kind: Option<RuleKind>,
+    pub(crate) severity: Severity,
+    pub(crate) message: String,
+}
+
+/// Returns the diagnostics of the rules of `custom_filters`, flagging the ones already in
+/// `enabled_rules`.
+pub(crate) fn lint(
+    custom_filters: &str,
+    enabled_rules: &EnabledRulesStore,
+) -> Vec<FilterDiagnostic> {
+    let enabled_rules = enabled_rules.0.read().unwrap();
+    let mut first_lines: HashMap<&str, usize> = HashMap::new();
+    let mut diagnostics = Vec::new();
+
+    for (index, rule) in custom_filters.lines().enumerate() {
+        let rule = rule.trim();
+        let line = index + 1;
+
+        if rule.is_empty() || rule.starts_with('!') {
+            continue;
+        }
+
+        let (kind, issue) = check_rule(rule);
+
+        let issue = issue.or_else(|| {
+            if let Some(first_line) = first_lines.get(rule) {
+                Some((Severity::Warning, format!("Duplicate of line {first_line}")))
+            } else if enabled_rules.contains(rule) {
+                Some((
+                    Severity::Warning,
+                    "Already in an enabled filter list".to_string(),
+                ))
+            } else {
+                None
+            }
+        });
+
+        first_lines.entry(rule).or_insert(line);
+
+        if let Some((severity, message)) = issue {
+            diagnostics.push(FilterDiagnostic {
+                line,
+                rule: rule.to_string(),
+                kind,
+                severity,
+                message,
+            });
+        }
+    }
+
+    diagnostics
+}
+
+/// Returns the kind of `rule`, along with the reason it is ignored if it is.
+fn check_rule(rule: &str) -> (Option<RuleKind>, Option<(Severity, String)>) {
+    let is_cosmetic = COSMETIC_SEPARATORS
+        .iter()
+        .any(|separator| rule.contains(separator));
+    let guessed_kind = if is_cosmetic {
+        RuleKind::Cosmetic
+    } else {
+        RuleKind::Network
+    };
+
+    if is_handled_by_privaxy(rule) {
+        return (Some(guessed_kind), None);
+    }
+
+    match parse_filter(rule, false, ParseOptions::default()) {
+        Ok(ParsedFilter::Network(_)) => (Some(RuleKind::Network), None),
+        Ok(ParsedFilter::Cosmetic(filter))
+            if filter.mask.contains(CosmeticFilterMask::SCRIPT_INJECT) =>
+        {
+            (Some(RuleKind::Scriptlet), None)
+        }
+        Ok(ParsedFilter::Cosmetic(_)) => (Some(RuleKind::Cosmetic), None),
+        Err(FilterParseError::Network(NetworkFilterError::UnrecognisedOption)) => {
+            let message = match get_unsupported_options(rule).as_slice() {
+                [] => "Uses an unsupported option, the rule is ignored".to_string(),
+                options => format!(
+                    "Unsupported option {}, the rule is ignored",
+                    options.join(", ")
+                ),
+            };
+
+            // `adblock` drops the whole rule, not just the option.
+            (Some(RuleKind::Network), Some((Severity::Error, message)))
+        }
+        Err(FilterParseError::Unsupported) => (
+            None,
+            Some((Severity::Error, "Unsupported syntax".to_string())),
+        ),
+        Err(err) => (Some(guessed_kind), Some((Severity::Error, err.to_string()))),
+    }
+}
+
+/// Whether `rule` is one of the rules `adblock` doesn't support, which Privaxy applies itself.
+fn is_handled_by_privaxy(rule: &str) -> bool {
+    // Only rules with options and cosmetic rules may be, the others aren't worth building filter
+    // sets for.
+    if !rule.contains('$') && !rule.contains('#') {
+        return false;
+    }
+
+    let rules = [rule.to_string()];
+    let extended_cosmetic_filters = ExtendedCosmeticFilters::from_filter_lists(&rules);
+
+    HeaderFilters::from_filter_lists(&rules).len() > 0
+        || ReplaceFilters::from_filter_lists(&rules).len() > 0
+        || JsonPruneFilters::from_filter_lists(&rules).len() > 0
+        || extended_cosmetic_filters.procedural_len() + extended_cosmetic_filters.html_len() > 0
+}
+
+fn get_unsupported_options(rule: &str) -> Vec<&str> {
+    let options = match rule.rsplit_once('$') {
+        Some((_pattern, options)) => options,
+        None => return Vec::new(),
+    };
+
+    options
+        .split(',')
+        .map(|option| option.split('=').next().unwrap_or_default().trim())
+        .map(|option| option.trim_start_matches('~'))
+        .filter(|option| !option.is_empty() && !SUPPORTED_OPTIONS.contains(option))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_issues(custom_filters: &str, enabled_rules: &EnabledRulesStore) -> Vec<(usize, String)> {
+        lint(custom_filters, enabled_rules)
+            .into_iter()
+            .map(|diagnostic| (diagnostic.line, diagnostic.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_line_numbers() {
+        let custom_filters = "! Comment\n\n  ||a.example^$foo  \n||b.example^\n##\n";
+        let diagnostics = lint(custom_filters, &EnabledRulesStore::default());
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].line, 3);
+        assert_eq!(diagnostics[0].rule, "||a.example^$foo");
+        assert_eq!(diagnostics[1].line, 5);
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let custom_filters = "||a.example^\nexample.com##.ad\n||a.example^\n  ||a.example^\n";
+
+        assert_eq!(
+            get_issues(custom_filters, &EnabledRulesStore::default()),
+            vec![
+                (3, "Duplicate of line 1".to_string()),
+                (4, "Duplicate of line 1".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_enabled_rules() {
+        let enabled_rules = EnabledRulesStore::default();
+        enabled_rules.replace(&[
+            "! Title: List\n||tracker.example^\n".to_string(),
+            "  example.com##.banner\n".to_string(),
+        ]);
+
+        let diagnostics = lint(
+            "! Title: List\n||tracker.example^\nexample.com##.banner\n||other.example^\n",
+            &enabled_rules,
+        );
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].line, 2);
+        assert_eq!(diagnostics[0].kind, Some(RuleKind::Network));
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].message, "Already in an enabled filter list");
+        assert_eq!(diagnostics[1].line, 3);
+        assert_eq!(diagnostics[1].kind, Some(RuleKind::Cosmetic));
+
+        enabled_rules.replace(&[]);
+        assert!(lint("||tracker.example^", &enabled_rules).is_empty());
+    }
+
+    #[test]
+    fn test_kinds() {
+        assert_eq!(check_rule("||a.example^"), (Some(RuleKind::Network), None));
+        assert_eq!(
+            check_rule("example.com##.ad"),
+            (Some(RuleKind::Cosmetic), None)
+        );
+        assert_eq!(
+            check_rule("example.com#@#.ad"),
+            (Some(RuleKind::Cosmetic), None)
+        );
+        assert_eq!(
+            check_rule("example.com##+js(set-constant, a, 1)"),
+            (Some(RuleKind::Scriptlet), None)
+        );
+    }
+
+    #[test]
+    fn test_rules_handled_by_privaxy() {
+        for rule in [
+            "example.com##.ad:has-text(Sponsored)",
+            "example.com##^script:has-text(ads)",
+            "example.com##^responseheader(refresh)",
+            "||a.example^$removeheader=refresh",
+            "||a.example^$script,replace=/a/b/",
+            "||a.example^$jsonprune=playerAds",
+        ] {
+            assert!(is_handled_by_privaxy(rule), "{rule}");
+            assert_eq!(check_rule(rule).1, None, "{rule}");
+        }
+
+        assert_eq!(
+            check_rule("example.com##.ad:has-text(Sponsored)").0,
+            Some(RuleKind::Cosmetic)
+        );
+        assert_eq!(
+            check_rule("||a.example^$jsonprune=playerAds").0,
+            Some(RuleKind::Network)
+        );
+
+        for rule in ["||a.example^", "example.com##.ad", "||a.example^$foo"] {
+            assert!(!is_handled_by_privaxy(rule), "{rule}");
+        }
+    }
+
+    #[test]
+    fn test_unsupported_options() {
+        assert_eq!(
+            check_rule("||a.example^$foo,~bar=1,script"),
+            (
+                Some(RuleKind::Network),
+                Some((
+                    Severity::Error,
+                    "Unsupported option foo, bar, the rule is ignored".to_string()
+                ))
+            )
+        );
+        assert_eq!(
+            check_rule("||a.example^$~third-party,domain=~b.example|c.example"),
+            (Some(RuleKind::Network), None)
+        );
+        assert_eq!(
+            get_unsupported_options("||a.example^$~foo,bar=a|b,~script,csp=default-src 'self'"),
+            vec!["foo", "bar"]
+        );
+        assert!(get_unsupported_options("||a.example^").is_empty());
+
+        // Rules with unsupported options are not loaded, which makes them errors.
+        let mut filter_set = adblock::lists::FilterSet::new(true);
+        filter_set
+            .add_filter("||a.example^$foo", Default::default())
+            .unwrap_err();
+        assert_eq!(
+            check_rule("||a.example^$foo")
+                .1
+                .map(|(severity, _message)| severity),
+            Some(Severity::Error)
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            check_rule("[Adblock Plus 2.0]"),
+            (
+                None,
+                Some((Severity::Error, "Unsupported syntax".to_string()))
+            )
+        );
+
+        let (kind, issue) = check_rule("##");
+        assert_eq!(kind, Some(RuleKind::Cosmetic));
+        assert_eq!(
+            issue.map(|(severity, _message)| severity),
+            Some(Severity::Error)
+        );
+    }
+}
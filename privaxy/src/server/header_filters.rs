@@ -0,0 +1,533 @@
+//! Header removal filters, which `adblock` doesn't support:
+//!
+//! - uBlock Origin's `example.com##^responseheader(refresh)`.
+//! - AdGuard's `||example.com^$removeheader=refresh`, and `$removeheader=request:x-client-data`
+//!   for request headers.
+//!
+//! Headers are removed from documents and subdocuments only, unless a `$removeheader` filter
+//! lists the types of requests it applies to, as in `||example.com^$script,removeheader=refresh`.
+//!
+//! Only hostname anchored (`||example.com^`) and generic patterns are supported for
+//! `$removeheader`, filters using any other option are ignored as they can't be honoured.
+use crate::cosmetic_filters::HostnameSelectors;
+use std::collections::HashMap;
+
+/// Headers filter lists are not trusted to remove, as doing so would weaken the security of
+/// websites.
+const PROTECTED_HEADERS: &[&str] = &[
+    "access-control-allow-credentials",
+    "access-control-allow-headers",
+    "access-control-allow-methods",
+    "access-control-allow-origin",
+    "access-control-expose-headers",
+    "access-control-max-age",
+    "content-encoding",
+    "content-length",
+    "content-security-policy",
+    "content-security-policy-report-only",
+    "content-type",
+    "cross-origin-embedder-policy",
+    "cross-origin-opener-policy",
+    "cross-origin-resource-policy",
+    "host",
+    "origin",
+    "permissions-policy",
+    "strict-transport-security",
+    "transfer-encoding",
+    "x-content-type-options",
+    "x-frame-options",
+];
+
+/// Request types headers are removed from when a filter doesn't list any.
+const DEFAULT_REQUEST_TYPES: &[&str] = &["document", "subdocument"];
+
+/// Request types filters may list, as named by the proxy, along with their aliases in filter
+/// lists.
+const REQUEST_TYPES: &[(&str, &[&str])] = &[
+    ("document", &["document", "doc"]),
+    ("subdocument", &["subdocument", "frame"]),
+    ("script", &["script"]),
+    ("stylesheet", &["stylesheet", "css"]),
+    ("image", &["image"]),
+    ("font", &["font"]),
+    ("media", &["media"]),
+    ("xmlhttprequest", &["xmlhttprequest", "xhr"]),
+    ("other", &["other"]),
+];
+
+const REMOVE_HEADER_OPTION: &str = "removeheader";
+const REQUEST_HEADER_PREFIX: &str = "request:";
+
+#[derive(Default)]
+struct Selectors {
+    request: HostnameSelectors,
+    response: HostnameSelectors,
+}
+
+/// A parsed `$removeheader` filter.
+#[derive(Debug, PartialEq, Eq)]
+struct RemoveHeaderFilter<'a> {
+    /// Empty for generic filters.
+    hostname: &'a str,
+    header: String,
+    is_exception: bool,
+    /// Empty when the filter doesn't list any.
+    request_types: Vec<&'static str>,
+}
+
+#[derive(Default)]
+pub(crate) struct HeaderFilters {
+    selectors_by_request_type: HashMap<&'static str, Selectors>,
+    len: usize,
+}
+
+impl HeaderFilters {
+    pub(crate) fn from_filter_lists(filter_lists: &[String]) -> Self {
+        let mut filters = Self::default();
+
+        for line in filter_lists
+            .iter()
+            .flat_map(|filter_list| filter_list.lines())
+        {
+            filters.add_filter(line.trim());
+        }
+
+        filters
+    }
+
+    fn add_filter(&mut self, line: &str) {
+        if line.starts_with('!') {
+            return;
+        }
+
+        if let Some((hostnames, header, is_exception)) = parse_response_header_filter(line) {
+            if is_removable(&header) {
+                for request_type in DEFAULT_REQUEST_TYPES {
+                    self.selectors_by_request_type
+                        .entry(request_type)
+                        .or_default()
+                        .response
+                        .add(hostnames, &header, is_exception, false);
+                }
+                self.len += usize::from(!is_exception);
+            }
+            return;
+        }
+
+        if let Some(filter) = parse_remove_header_filter(line) {
+            let (is_request_header, header) =
+                match filter.header.strip_prefix(REQUEST_HEADER_PREFIX) {
+                    Some(header) => (true, header),
+                    None => (false, filter.header.as_str()),
+                };
+
+            if !is_removable(header) {
+                return;
+            }
+
+            // Exceptions without types lift the removal from any request.
+            let request_types: Vec<&str> = match filter.request_types.as_slice() {
+                [] if filter.is_exception => REQUEST_TYPES.iter().map(|(name, _)| *name).collect(),
+                [] => DEFAULT_REQUEST_TYPES.to_vec(),
+                request_types => request_types.to_vec(),
+            };
+
+            for request_type in request_types {
+                let selectors = self
+                    .selectors_by_request_type
+                    .entry(request_type)
+                    .or_default();
+                let selectors = if is_request_header {
+                    &mut selectors.request
+                } else {
+                    &mut selectors.response
+                };
+
+                selectors.add(filter.hostname, header, filter.is_exception, true);
+            }
+            self.len += usize::from(!filter.is_exception);
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the names of the request headers to remove from a request of `request_type` to
+    /// `url`.
+    pub(crate) fn request_headers_for_request(&self, url: &str, request_type: &str) -> Vec<String> {
+        self.selectors_by_request_type
+            .get(request_type)
+            .map(|selectors| selectors.request.selectors_for_url(url))
+            .unwrap_or_default()
+    }
+
+    /// Returns the names of the response headers to remove from the response to a request of
+    /// `request_type` to `url`.
+    pub(crate) fn response_headers_for_request(
+        &self,
+        url: &str,
+        request_type: &str,
+    ) -> Vec<String> {
+        self.selectors_by_request_type
+            .get(request_type)
+            .map(|selectors| selectors.response.selectors_for_url(url))
+            .unwrap_or_default()
+    }
+}
+
+/// Parses `hostnames##^responseheader(name)`, and its `#@#` exception counterpart.
+fn parse_response_header_filter(line: &str) -> Option<(&str, String, bool)> {
+    let (hostnames, selector, is_exception) = if let Some((h, s)) = line.split_once("#@#^") {
+        (h, s, true)
+    } else {
+        let (h, s) = line.split_once("##^")?;
+        (h, s, false)
+    };
+
+    let header = selector
+        .trim()
+        .strip_prefix("responseheader(")?
+        .strip_suffix(')')?
+        .trim()
+        .to_ascii_lowercase();
+
+    Some((hostnames, header, is_exception))
+}
+
+/// Parses `||hostname^$removeheader=name`, and its `@@` exception counterpart. Options naming
+/// request types, such as `$script`, may come along.
+fn parse_remove_header_filter(line: &str) -> Option<RemoveHeaderFilter<'_>> {
+    let (pattern, options) = line.rsplit_once('$')?;
+
+    let mut header = None;
+    let mut request_types = Vec::new();
+
+    // Options we can't honour would make the filter apply more broadly than intended.
+    for option in options.split(',').map(str::trim) {
+        if let Some(value) = option.strip_prefix(REMOVE_HEADER_OPTION) {
+            let value = value.strip_prefix('=')?.trim().to_ascii_lowercase();
+
+            if value.is_empty() || header.replace(value).is_some() {
+                return None;
+            }
+        } else {
+            let (request_type, _aliases) = REQUEST_TYPES
+                .iter()
+                .find(|(_request_type, aliases)| aliases.contains(&option))?;
+
+            if !request_types.contains(request_type) {
+                request_types.push(*request_type);
+            }
+        }
+    }
+
+    let (pattern, is_exception) = match pattern.strip_prefix("@@") {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+
+    let hostname = if pattern.is_empty() || pattern == "*" {
+        ""
+    } else {
+        let hostname = pattern.strip_prefix("||")?;
+        let hostname = hostname
+            .strip_suffix("^*")
+            .or_else(|| hostname.strip_suffix('^'))
+            .unwrap_or(hostname);
+
+        if hostname.is_empty()
+            || !hostname
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+        {
+            return None;
+        }
+
+        hostname
+    };
+
+    Some(RemoveHeaderFilter {
+        hostname,
+        header: header?,
+        is_exception,
+        request_types,
+    })
+}
+
+fn is_removable(header: &str) -> bool {
+    http::header::HeaderName::from_bytes(header.as_bytes()).is_ok()
+        && !PROTECTED_HEADERS.contains(&header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_filters(filters: &str) -> HeaderFilters {
+        HeaderFilters::from_filter_lists(&[filters.to_string()])
+    }
+
+    fn remove_header_filter<'a>(
+        hostname: &'a str,
+        header: &str,
+        is_exception: bool,
+        request_types: &[&'static str],
+    ) -> Option<RemoveHeaderFilter<'a>> {
+        Some(RemoveHeaderFilter {
+            hostname,
+            header: header.to_string(),
+            is_exception,
+            request_types: request_types.to_vec(),
+        })
+    }
+
+    #[test]
+    fn test_parse_response_header_filter() {
+        assert_eq!(
+            parse_response_header_filter("example.com##^responseheader( Refresh )"),
+            Some(("example.com", "refresh".to_string(), false))
+        );
+        assert_eq!(
+            parse_response_header_filter("example.com#@#^responseheader(refresh)"),
+            Some(("example.com", "refresh".to_string(), true))
+        );
+        assert_eq!(
+            parse_response_header_filter("##^responseheader(refresh)"),
+            Some(("", "refresh".to_string(), false))
+        );
+        assert_eq!(parse_response_header_filter("example.com##^script"), None);
+        assert_eq!(parse_response_header_filter("example.com##.ad"), None);
+    }
+
+    #[test]
+    fn test_parse_remove_header_filter() {
+        assert_eq!(
+            parse_remove_header_filter("||example.com^$removeheader=Refresh"),
+            remove_header_filter("example.com", "refresh", false, &[])
+        );
+        assert_eq!(
+            parse_remove_header_filter("||example.com^*$removeheader=refresh"),
+            remove_header_filter("example.com", "refresh", false, &[])
+        );
+        assert_eq!(
+            parse_remove_header_filter("@@||example.com^$removeheader=refresh"),
+            remove_header_filter("example.com", "refresh", true, &[])
+        );
+        assert_eq!(
+            parse_remove_header_filter("$removeheader=request:x-client-data"),
+            remove_header_filter("", "request:x-client-data", false, &[])
+        );
+        assert_eq!(
+            parse_remove_header_filter("*$removeheader=refresh"),
+            remove_header_filter("", "refresh", false, &[])
+        );
+        assert_eq!(
+            parse_remove_header_filter("@@$removeheader=refresh"),
+            remove_header_filter("", "refresh", true, &[])
+        );
+    }
+
+    #[test]
+    fn test_parse_remove_header_filter_request_types() {
+        assert_eq!(
+            parse_remove_header_filter("||example.com^$script,xhr,removeheader=refresh,script"),
+            remove_header_filter(
+                "example.com",
+                "refresh",
+                false,
+                &["script", "xmlhttprequest"]
+            )
+        );
+        assert_eq!(
+            parse_remove_header_filter("||example.com^$removeheader=refresh,frame"),
+            remove_header_filter("example.com", "refresh", false, &["subdocument"])
+        );
+    }
+
+    #[test]
+    fn test_remove_header_filter_extra_options() {
+        assert_eq!(
+            parse_remove_header_filter("||example.com^$removeheader=refresh,third-party"),
+            None
+        );
+        assert_eq!(
+            parse_remove_header_filter("||example.com^$~script,removeheader=refresh"),
+            None
+        );
+        assert_eq!(
+            parse_remove_header_filter("||example.com^$removeheader=a,removeheader=b"),
+            None
+        );
+        assert_eq!(parse_remove_header_filter("||example.com^$script"), None);
+        assert_eq!(
+            parse_remove_header_filter("||example.com^$removeheader="),
+            None
+        );
+        assert_eq!(
+            parse_remove_header_filter("||example.com^$removeheader"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_remove_header_filter_non_hostname_patterns() {
+        assert_eq!(
+            parse_remove_header_filter("||example.com/path^$removeheader=refresh"),
+            None
+        );
+        assert_eq!(
+            parse_remove_header_filter("example.com$removeheader=refresh"),
+            None
+        );
+        assert_eq!(
+            parse_remove_header_filter("|https://example.com$removeheader=refresh"),
+            None
+        );
+        assert_eq!(parse_remove_header_filter("||^$removeheader=refresh"), None);
+        assert_eq!(
+            parse_remove_header_filter("/ads[0-9]/$removeheader=refresh"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_is_removable() {
+        assert!(is_removable("refresh"));
+        assert!(is_removable("x-client-data"));
+        assert!(!is_removable("content-security-policy"));
+        assert!(!is_removable("strict-transport-security"));
+        assert!(!is_removable("access-control-allow-origin"));
+        assert!(!is_removable("not a header"));
+        assert!(!is_removable(""));
+    }
+
+    #[test]
+    fn test_protected_headers_are_refused() {
+        let filters = header_filters(
+            "example.com##^responseheader(content-security-policy)\n\
+             ||example.com^$removeheader=x-frame-options\n\
+             ||example.com^$removeheader=request:origin\n\
+             ||example.com^$removeheader=refresh",
+        );
+
+        assert_eq!(filters.len(), 1);
+        assert_eq!(
+            filters.response_headers_for_request("https://example.com/", "document"),
+            vec!["refresh".to_string()]
+        );
+        assert!(filters
+            .request_headers_for_request("https://example.com/", "document")
+            .is_empty());
+    }
+
+    #[test]
+    fn test_request_headers() {
+        let filters = header_filters("||example.com^$removeheader=request:x-client-data");
+
+        assert_eq!(
+            filters.request_headers_for_request("https://www.example.com/", "document"),
+            vec!["x-client-data".to_string()]
+        );
+        assert!(filters
+            .response_headers_for_request("https://www.example.com/", "document")
+            .is_empty());
+        assert!(filters
+            .request_headers_for_request("https://other.com/", "document")
+            .is_empty());
+    }
+
+    #[test]
+    fn test_subresources_keep_headers() {
+        let filters = header_filters(
+            "example.com##^responseheader(refresh)\n\
+             ||example.com^$removeheader=link\n\
+             ||example.com^$removeheader=request:x-client-data",
+        );
+
+        for request_type in ["document", "subdocument"] {
+            assert_eq!(
+                filters.response_headers_for_request("https://example.com/", request_type),
+                vec!["refresh".to_string(), "link".to_string()]
+            );
+            assert_eq!(
+                filters.request_headers_for_request("https://example.com/", request_type),
+                vec!["x-client-data".to_string()]
+            );
+        }
+
+        for request_type in ["script", "xmlhttprequest", "image", "other"] {
+            assert!(filters
+                .response_headers_for_request("https://example.com/app.js", request_type)
+                .is_empty());
+            assert!(filters
+                .request_headers_for_request("https://example.com/app.js", request_type)
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn test_request_types() {
+        let filters = header_filters("||example.com^$script,xhr,removeheader=link");
+
+        assert_eq!(
+            filters.response_headers_for_request("https://example.com/app.js", "script"),
+            vec!["link".to_string()]
+        );
+        assert_eq!(
+            filters.response_headers_for_request("https://example.com/api", "xmlhttprequest"),
+            vec!["link".to_string()]
+        );
+        assert!(filters
+            .response_headers_for_request("https://example.com/", "document")
+            .is_empty());
+        assert!(filters
+            .response_headers_for_request("https://example.com/a.png", "image")
+            .is_empty());
+    }
+
+    #[test]
+    fn test_exceptions() {
+        let filters = header_filters(
+            "##^responseheader(refresh)\n\
+             $removeheader=link\n\
+             $script,removeheader=link\n\
+             example.com#@#^responseheader(refresh)\n\
+             @@||example.com^$removeheader=link",
+        );
+
+        assert_eq!(
+            filters.response_headers_for_request("https://other.com/", "document"),
+            vec!["link".to_string()]
+        );
+        assert_eq!(
+            filters.response_headers_for_request("https://other.com/app.js", "script"),
+            vec!["link".to_string()]
+        );
+        assert!(filters
+            .response_headers_for_request("https://www.example.com/", "document")
+            .is_empty());
+        assert!(filters
+            .response_headers_for_request("https://www.example.com/app.js", "script")
+            .is_empty());
+    }
+
+    #[test]
+    fn test_generic_filters() {
+        let filters = header_filters(
+            "! $removeheader=refresh\n\
+             *$removeheader=refresh\n\
+             $removeheader=request:x-client-data",
+        );
+
+        assert_eq!(filters.len(), 2);
+        assert_eq!(
+            filters.response_headers_for_request("https://any.example/", "document"),
+            vec!["refresh".to_string()]
+        );
+        assert_eq!(
+            filters.request_headers_for_request("https://any.example/", "subdocument"),
+            vec!["x-client-data".to_string()]
+        );
+    }
+}
@@ -0,0 +1,61 @@
+//! The HSTS preload list, as published by Chromium, listing the hosts that are only ever to be
+//! reached over HTTPS.
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+struct RawPreloadList {
+    entries: Vec<RawEntry>,
+}
+
+#[derive(Deserialize)]
+struct RawEntry {
+    name: String,
+    #[serde(default)]
+    mode: Option<String>,
+    #[serde(default)]
+    include_subdomains: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct HstsPreloadList {
+    /// Whether subdomains are included, by host.
+    hosts: HashMap<String, bool>,
+}
+
+impl HstsPreloadList {
+    /// Parses Chromium's `transport_security_state_static.json`, which has comments.
+    pub(crate) fn parse(content: &str) -> Result<Self, serde_json::Error> {
+        let content = content
+            .lines()
+            .filter(|line| !line.trim_start().starts_with("//"))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let raw = serde_json::from_str::<RawPreloadList>(&content)?;
+
+        let hosts = raw
+            .entries
+            .into_iter()
+            .filter(|entry| entry.mode.as_deref() == Some("force-https"))
+            .map(|entry| (entry.name.to_ascii_lowercase(), entry.include_subdomains))
+            .collect();
+
+        Ok(Self { hosts })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.hosts.len()
+    }
+
+    pub(crate) fn contains(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+
+        if self.hosts.contains_key(&host) {
+            return true;
+        }
+
+        host.match_indices('.')
+            .any(|(index, _)| self.hosts.get(&host[index + 1..]).copied().unwrap_or(false))
+    }
+}
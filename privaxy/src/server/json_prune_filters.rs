@@ -0,0 +1,321 @@
+//! `$jsonprune` filters, such as `||example.com/api/player$jsonprune=playerAds adPlacements`,
+//! which delete properties from JSON responses before they reach the page.
+//!
+//! Like uBlock Origin's `json-prune` scriptlet, the value is a space separated list of property
+//! paths. Their segments are separated by dots, `*` matches any property, `[]` any array item,
+//! and a segment following two dots, as in `..adPlacements`, is looked for at any depth. A
+//! leading `$`, as used by AdGuard's JSONPath expressions, is ignored.
+use crate::option_filters::{FilterOption, OptionFilters};
+use serde_json::Value;
+
+pub(crate) type JsonPruneFilters = OptionFilters<JsonPruneRule>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PathSegment {
+    Property(String),
+    AnyProperty,
+    AnyItem,
+    /// A property found at any depth.
+    Descendant(String),
+}
+
+/// Properties to delete from the JSON responses matched by a filter.
+#[derive(Debug, Clone)]
+pub struct JsonPruneRule {
+    /// The filter the rule comes from, hits are counted per filter.
+    filter: String,
+    paths: Vec<Vec<PathSegment>>,
+}
+
+impl FilterOption for JsonPruneRule {
+    const NAME: &'static str = "jsonprune";
+
+    fn parse(value: &str, line: &str) -> Option<Self> {
+        let value = value.replace("\\$", "$").replace("\\,", ",");
+
+        let paths = value
+            .split_whitespace()
+            .map(parse_path)
+            .collect::<Option<Vec<_>>>()?;
+
+        if paths.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            filter: line.to_string(),
+            paths,
+        })
+    }
+}
+
+impl JsonPruneRule {
+    pub(crate) fn filter(&self) -> &str {
+        &self.filter
+    }
+
+    /// Deletes the properties matched by the rule from `value`, returning whether any was found.
+    pub(crate) fn apply(&self, value: &mut Value) -> bool {
+        let mut is_pruned = false;
+
+        for path in &self.paths {
+            is_pruned |= prune(value, path);
+        }
+
+        is_pruned
+    }
+}
+
+fn parse_path(path: &str) -> Option<Vec<PathSegment>> {
+    let path = path.strip_prefix('$').unwrap_or(path);
+    let mut segments = Vec::new();
+    let mut is_descendant = false;
+
+    for (index, segment) in path.split('.').enumerate() {
+        if segment.is_empty() {
+            // Paths may start with a dot when written as JSONPath, only `..` is meaningful.
+            if index > 0 {
+                is_descendant = true;
+            }
+            continue;
+        }
+
+        let segment = match segment {
+            "*" if !is_descendant => PathSegment::AnyProperty,
+            "[]" | "[*]" if !is_descendant => PathSegment::AnyItem,
+            "*" | "[]" | "[*]" => return None,
+            property if is_descendant => PathSegment::Descendant(property.to_string()),
+            property => PathSegment::Property(property.to_string()),
+        };
+
+        segments.push(segment);
+        is_descendant = false;
+    }
+
+    if is_descendant || segments.is_empty() {
+        return None;
+    }
+
+    Some(segments)
+}
+
+fn prune(value: &mut Value, path: &[PathSegment]) -> bool {
+    let (segment, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return false,
+    };
+
+    let is_last = rest.is_empty();
+
+    match (segment, value) {
+        (PathSegment::Property(property), Value::Object(object)) => {
+            if is_last {
+                object.shift_remove(property).is_some()
+            } else {
+                object
+                    .get_mut(property)
+                    .map(|child| prune(child, rest))
+                    .unwrap_or(false)
+            }
+        }
+        (PathSegment::AnyProperty, Value::Object(object)) => {
+            if is_last {
+                let is_pruned = !object.is_empty();
+                object.clear();
+                is_pruned
+            } else {
+                object
+                    .values_mut()
+                    .fold(false, |is_pruned, child| prune(child, rest) | is_pruned)
+            }
+        }
+        (PathSegment::AnyItem, Value::Array(array)) => {
+            if is_last {
+                let is_pruned = !array.is_empty();
+                array.clear();
+                is_pruned
+            } else {
+                array
+                    .iter_mut()
+                    .fold(false, |is_pruned, child| prune(child, rest) | is_pruned)
+            }
+        }
+        (PathSegment::Descendant(property), value) => {
+            let mut is_pruned = false;
+
+            if let Value::Object(object) = value {
+                if is_last {
+                    is_pruned |= object.shift_remove(property).is_some();
+                } else if let Some(child) = object.get_mut(property) {
+                    is_pruned |= prune(child, rest);
+                }
+            }
+
+            let children: Box<dyn Iterator<Item = &mut Value>> = match value {
+                Value::Object(object) => Box::new(object.values_mut()),
+                Value::Array(array) => Box::new(array.iter_mut()),
+                _ => return is_pruned,
+            };
+
+            for child in children {
+                is_pruned |= prune(child, path);
+            }
+
+            is_pruned
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn property(name: &str) -> PathSegment {
+        PathSegment::Property(name.to_string())
+    }
+
+    fn prune_path(mut value: Value, path: &str) -> (bool, Value) {
+        let is_pruned = prune(&mut value, &parse_path(path).unwrap());
+        (is_pruned, value)
+    }
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(parse_path("playerAds"), Some(vec![property("playerAds")]));
+        assert_eq!(
+            parse_path("$.data.ads"),
+            Some(vec![property("data"), property("ads")])
+        );
+        assert_eq!(
+            parse_path("a.*.[].b"),
+            Some(vec![
+                property("a"),
+                PathSegment::AnyProperty,
+                PathSegment::AnyItem,
+                property("b")
+            ])
+        );
+        assert_eq!(
+            parse_path("a..b.c"),
+            Some(vec![
+                property("a"),
+                PathSegment::Descendant("b".to_string()),
+                property("c")
+            ])
+        );
+        assert_eq!(
+            parse_path("$..adPlacements"),
+            Some(vec![PathSegment::Descendant("adPlacements".to_string())])
+        );
+
+        assert_eq!(parse_path("a.."), None);
+        assert_eq!(parse_path("..*"), None);
+        assert_eq!(parse_path("$"), None);
+        assert_eq!(parse_path(""), None);
+    }
+
+    #[test]
+    fn test_prune_nested_properties() {
+        assert_eq!(
+            prune_path(json!({"a": {"b": 1, "c": 2}}), "a.b"),
+            (true, json!({"a": {"c": 2}}))
+        );
+        assert_eq!(
+            prune_path(json!({"a": {"b": 1}, "b": 2}), "b"),
+            (true, json!({"a": {"b": 1}}))
+        );
+    }
+
+    #[test]
+    fn test_prune_missing_properties() {
+        let value = json!({"a": {"b": 1}});
+
+        assert_eq!(prune_path(value.clone(), "a.c"), (false, value.clone()));
+        assert_eq!(prune_path(value.clone(), "c.b"), (false, value.clone()));
+        assert_eq!(prune_path(value.clone(), "a.b.c"), (false, value.clone()));
+        assert_eq!(prune_path(value.clone(), "..c"), (false, value));
+    }
+
+    #[test]
+    fn test_prune_wildcards() {
+        assert_eq!(
+            prune_path(
+                json!({"a": {"x": {"ad": 1, "k": 1}, "y": {"ad": 2}, "z": 3}}),
+                "a.*.ad"
+            ),
+            (true, json!({"a": {"x": {"k": 1}, "y": {}, "z": 3}}))
+        );
+        assert_eq!(
+            prune_path(
+                json!({"items": [{"ad": 1, "id": 1}, {"id": 2}]}),
+                "items.[].ad"
+            ),
+            (true, json!({"items": [{"id": 1}, {"id": 2}]}))
+        );
+        assert_eq!(
+            prune_path(json!({"a": {"b": 1}, "c": [1]}), "a.*"),
+            (true, json!({"a": {}, "c": [1]}))
+        );
+        assert_eq!(
+            prune_path(json!({"c": [1, 2]}), "c.[]"),
+            (true, json!({"c": []}))
+        );
+        assert_eq!(
+            prune_path(json!({"a": {}}), "a.*"),
+            (false, json!({"a": {}}))
+        );
+    }
+
+    #[test]
+    fn test_prune_descendants() {
+        assert_eq!(
+            prune_path(
+                json!({"adPlacements": 1, "a": [{"b": {"adPlacements": 2, "c": 3}}]}),
+                "..adPlacements"
+            ),
+            (true, json!({"a": [{"b": {"c": 3}}]}))
+        );
+        assert_eq!(
+            prune_path(
+                json!({"a": {"player": {"ads": 1, "id": 1}}, "player": {"ads": 2}}),
+                "..player.ads"
+            ),
+            (true, json!({"a": {"player": {"id": 1}}, "player": {}}))
+        );
+    }
+
+    #[test]
+    fn test_prune_non_object_roots() {
+        assert_eq!(
+            prune_path(json!([{"a": 1}]), "a"),
+            (false, json!([{"a": 1}]))
+        );
+        assert_eq!(prune_path(json!("a"), "a"), (false, json!("a")));
+        assert_eq!(prune_path(json!(null), "a.*"), (false, json!(null)));
+        assert_eq!(prune_path(json!(1), "[]"), (false, json!(1)));
+        assert_eq!(
+            prune_path(json!([{"a": 1}, {"b": 2}]), "..a"),
+            (true, json!([{}, {"b": 2}]))
+        );
+        assert_eq!(
+            prune_path(json!([{"a": 1}, {"a": 2}]), "[].a"),
+            (true, json!([{}, {}]))
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let rule = JsonPruneRule::parse("playerAds \\$.adPlacements", "filter").unwrap();
+        let mut value = json!({"playerAds": [], "adPlacements": [], "videoDetails": {}});
+
+        assert!(rule.apply(&mut value));
+        assert_eq!(value, json!({"videoDetails": {}}));
+        assert!(!rule.apply(&mut value));
+        assert_eq!(rule.filter(), "filter");
+
+        assert!(JsonPruneRule::parse("", "filter").is_none());
+        assert!(JsonPruneRule::parse("a b..", "filter").is_none());
+    }
+}
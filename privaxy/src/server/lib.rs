@@ -25,8 +25,16 @@ mod blocker_utils;
 mod ca;
 mod cert;
 pub mod configuration;
+mod cosmetic_filters;
+mod filter_lint;
+mod header_filters;
+mod hsts_preload;
+mod json_prune_filters;
+mod option_filters;
 mod proxy;
+mod replace_filters;
 pub mod statistics;
+mod url_cleaner;
 mod web_gui;
 
 pub const WEBAPP_FRONTEND_DIR: Dir<'_> = include_dir!("web_frontend/dist");
@@ -78,12 +86,23 @@ async fn handle_signals() -> (Arc<Notify>, Arc<Notify>) {
 }
 
 pub async fn start_privaxy() -> PrivaxyServer {
-    // We use reqwest instead of hyper's client to perform most of the proxying as it's more convenient
-    // to handle compression as well as offers a more convenient interface.
+    // We use reqwest instead of hyper's client to perform most of the proxying as it offers a more
+    // convenient interface. Responses are not decompressed, compressed bodies that we don't rewrite
+    // are passed through to clients.
     let client = reqwest::Client::builder()
         .use_rustls_tls()
         .redirect(Policy::none())
         .no_proxy()
+        .gzip(false)
+        .brotli(false)
+        .deflate(false)
+        .build()
+        .unwrap();
+
+    // Filter lists are downloaded by a client which takes care of decompression.
+    let filters_client = reqwest::Client::builder()
+        .use_rustls_tls()
+        .no_proxy()
         .gzip(true)
         .brotli(true)
         .deflate(true)
@@ -132,7 +151,7 @@ pub async fn start_privaxy() -> PrivaxyServer {
     let broadcast_tx_clone = broadcast_tx.clone();
 
     let blocking_disabled_store =
-        blocker::BlockingDisabledStore(Arc::new(std::sync::RwLock::new(false)));
+        blocker::BlockingDisabledStore(Arc::new(std::sync::RwLock::new(Default::default())));
     let blocking_disabled_store_clone = blocking_disabled_store.clone();
 
     let (crossbeam_sender, crossbeam_receiver) = crossbeam_channel::unbounded();
@@ -142,13 +161,15 @@ pub async fn start_privaxy() -> PrivaxyServer {
 
     let configuration_updater = configuration::ConfigurationUpdater::new(
         configuration.clone(),
-        client.clone(),
+        filters_client,
         blocker_requester.clone(),
         None,
     )
     .await;
 
     let configuration_updater_tx = configuration_updater.tx.clone();
+    let filter_updates = configuration_updater.filter_updates.clone();
+    let enabled_rules = configuration_updater.enabled_rules.clone();
     configuration_updater_tx.send(configuration).await.unwrap();
 
     configuration_updater.start();
@@ -157,29 +178,23 @@ pub async fn start_privaxy() -> PrivaxyServer {
 
     let (_notify_shutdown, notify_reload) = handle_signals().await;
 
-    let block_disable_ref = blocking_disabled_store.clone();
-    let local_exclusion_store_ref = local_exclusion_store.clone();
-    let stats_clone = statistics.clone();
-    let configuration_updater_tx_ref = configuration_updater_tx.clone();
-    let configuration_save_lock_ref = configuration_save_lock.clone();
-    let broadcast_tx_ref = broadcast_tx.clone();
-    let notify_reload_clone = notify_reload.clone();
+    let frontend_state = web_gui::FrontendState {
+        events_sender: broadcast_tx.clone(),
+        statistics: statistics.clone(),
+        blocking_disabled_store: blocking_disabled_store.clone(),
+        configuration_updater_sender: configuration_updater_tx.clone(),
+        configuration_save_lock: configuration_save_lock.clone(),
+        local_exclusions_store: local_exclusion_store.clone(),
+        notify_reload: notify_reload.clone(),
+        filter_updates,
+        enabled_rules,
+    };
 
     tokio::spawn(async move {
-        let notify_reload_frontend = notify_reload_clone.clone();
-        let cfg_lock_frontend = configuration_save_lock_ref.clone();
+        let notify_reload_frontend = frontend_state.notify_reload.clone();
         loop {
             log::info!("Starting Privaxy frontend");
-            privaxy_frontend(
-                broadcast_tx_ref.clone(),
-                local_exclusion_store_ref.clone(),
-                stats_clone.clone(),
-                block_disable_ref.clone(),
-                configuration_updater_tx_ref.clone(),
-                cfg_lock_frontend.clone(),
-                notify_reload_frontend.clone(),
-            )
-            .await;
+            privaxy_frontend(frontend_state.clone()).await;
             notify_reload_frontend.notified().await;
             log::info!("Stopping Privaxy frontend");
         }
@@ -235,24 +250,10 @@ pub async fn start_privaxy() -> PrivaxyServer {
     }
 }
 
-async fn privaxy_frontend(
-    broadcast_tx: tokio::sync::broadcast::Sender<Event>,
-    local_exclusion_store: LocalExclusionStore,
-    statistics: statistics::Statistics,
-    block_disable_ref: blocker::BlockingDisabledStore,
-    configuration_updater_tx: tokio::sync::mpsc::Sender<configuration::Configuration>,
-    configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
-    notify_reload: Arc<tokio::sync::Notify>,
-) {
-    let frontend = web_gui::get_frontend(
-        broadcast_tx.clone(),
-        statistics.clone(),
-        &block_disable_ref,
-        &configuration_updater_tx,
-        &configuration_save_lock,
-        &local_exclusion_store,
-        notify_reload.clone(),
-    );
+async fn privaxy_frontend(frontend_state: web_gui::FrontendState) {
+    let configuration_save_lock = frontend_state.configuration_save_lock.clone();
+    let notify_reload = frontend_state.notify_reload.clone();
+    let frontend = web_gui::get_frontend(frontend_state);
     let frontend_server = warp::serve(frontend);
     let config = read_configuration(&configuration_save_lock).await;
     let ip = env_or_config_ip(&config.network).await;
@@ -345,29 +346,38 @@ async fn privaxy_backend(
     // disable here.
     let hyper_client = Client::builder().build(https_connector);
 
+    let proxy_state = Arc::new(proxy::ProxyState {
+        adblock_requester: blocker_requester,
+        hyper_client,
+        client,
+        cert_cache,
+        broadcast_sender: broadcast_tx,
+        statistics,
+        local_exclusion_store,
+        body_rewriting: config.body_rewriting,
+        privacy_policy: proxy::PrivacyPolicy::new(config.privacy.clone()),
+        https_upgrader: proxy::HttpsUpgrader::new(config.https_upgrade.clone()),
+    });
+
+    let web_gui_network_config = network_config.clone();
+
     let make_service = make_service_fn(move |conn: &AddrStream| {
         let client_ip_address = conn.remote_addr().ip();
+        // Subscription links are handed over to the web interface, at the address the client
+        // reached the proxy at.
+        let web_gui_url: Arc<str> = web_gui_network_config
+            .get_web_gui_url(conn.local_addr().ip())
+            .into();
 
-        let client = client.clone();
-        let hyper_client = hyper_client.clone();
-        let cert_cache = cert_cache.clone();
-        let blocker_requester = blocker_requester.clone();
-        let broadcast_tx = broadcast_tx.clone();
-        let statistics = statistics.clone();
-        let local_exclusion_store = local_exclusion_store.clone();
+        let proxy_state = proxy_state.clone();
 
         async move {
             Ok::<_, Infallible>(service_fn(move |req| {
                 proxy::serve_mitm_session(
-                    blocker_requester.clone(),
-                    hyper_client.clone(),
-                    client.clone(),
+                    proxy_state.clone(),
                     req,
-                    cert_cache.clone(),
-                    broadcast_tx.clone(),
-                    statistics.clone(),
                     client_ip_address,
-                    local_exclusion_store.clone(),
+                    web_gui_url.clone(),
                 )
             }))
         }
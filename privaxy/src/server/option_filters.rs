@@ -0,0 +1,183 @@
+//! Network filters carrying an option `adblock` doesn't support, such as `$replace`. The option
+//! is parsed here while `adblock` still takes care of matching the pattern and other options of
+//! these filters against requests.
+use adblock::filters::network::{NetworkFilter, NetworkMatchable};
+use adblock::lists::ParseOptions;
+use adblock::regex_manager::RegexManager;
+use adblock::request::Request;
+
+/// Value of an option handled outside of `adblock`.
+pub(crate) trait FilterOption: Clone + Sized {
+    /// Name of the option, as written in filters.
+    const NAME: &'static str;
+
+    /// Parses the value of the option, `line` being the whole filter.
+    fn parse(value: &str, line: &str) -> Option<Self>;
+}
+
+struct OptionFilter<T> {
+    network_filter: NetworkFilter,
+    /// Raw value of the option, exceptions refer to filters with it.
+    value: String,
+    option: T,
+}
+
+struct OptionException {
+    network_filter: NetworkFilter,
+    /// Exceptions without a value disable every filter with the option.
+    value: Option<String>,
+}
+
+pub(crate) struct OptionFilters<T> {
+    filters: Vec<OptionFilter<T>>,
+    exceptions: Vec<OptionException>,
+    regex_manager: RegexManager,
+}
+
+impl<T> Default for OptionFilters<T> {
+    fn default() -> Self {
+        Self {
+            filters: Vec::new(),
+            exceptions: Vec::new(),
+            regex_manager: RegexManager::default(),
+        }
+    }
+}
+
+impl<T: FilterOption> OptionFilters<T> {
+    pub(crate) fn from_filter_lists(filter_lists: &[String]) -> Self {
+        let mut filters = Self::default();
+
+        for line in filter_lists
+            .iter()
+            .flat_map(|filter_list| filter_list.lines())
+        {
+            filters.add_filter(line.trim());
+        }
+
+        filters
+    }
+
+    fn add_filter(&mut self, line: &str) {
+        if line.starts_with('!') || line.contains("##") || line.contains("#@#") {
+            return;
+        }
+
+        let (pattern, value, other_options) = match split_option(line, T::NAME) {
+            Some(parts) => parts,
+            None => return,
+        };
+
+        let (pattern, is_exception) = match pattern.strip_prefix("@@") {
+            Some(pattern) => (pattern, true),
+            None => (pattern, false),
+        };
+
+        let network_filter_line = if other_options.is_empty() {
+            pattern.to_string()
+        } else {
+            format!("{}${}", pattern, other_options.join(","))
+        };
+
+        let network_filter =
+            match NetworkFilter::parse(&network_filter_line, false, ParseOptions::default()) {
+                Ok(network_filter) => network_filter,
+                Err(_err) => return,
+            };
+
+        if is_exception {
+            self.exceptions.push(OptionException {
+                network_filter,
+                value: (!value.is_empty()).then(|| value.to_string()),
+            });
+
+            return;
+        }
+
+        match T::parse(value, line) {
+            Some(option) => self.filters.push(OptionFilter {
+                network_filter,
+                value: value.to_string(),
+                option,
+            }),
+            None => log::debug!("Unsupported ${} filter: {}", T::NAME, line),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    /// Returns the options of the filters matching `request`, in the order they were added.
+    pub(crate) fn options_for_request(&mut self, request: &Request) -> Vec<T> {
+        if self.filters.is_empty() {
+            return Vec::new();
+        }
+
+        let regex_manager = &mut self.regex_manager;
+
+        let mut excepted_values = Vec::new();
+        for exception in &self.exceptions {
+            if exception.network_filter.matches(request, regex_manager) {
+                match &exception.value {
+                    Some(value) => excepted_values.push(value.as_str()),
+                    None => return Vec::new(),
+                }
+            }
+        }
+
+        self.filters
+            .iter()
+            .filter(|filter| !excepted_values.contains(&filter.value.as_str()))
+            .filter(|filter| filter.network_filter.matches(request, regex_manager))
+            .map(|filter| filter.option.clone())
+            .collect()
+    }
+}
+
+/// Splits `pattern$options` into its pattern, the value of the `name` option and its other
+/// options. As values may be regular expressions, they may contain `$` and escaped commas.
+fn split_option<'a>(line: &'a str, name: &str) -> Option<(&'a str, &'a str, Vec<&'a str>)> {
+    for (index, _) in line.match_indices('$') {
+        let options = split_options(&line[index + 1..]);
+
+        let value = options.iter().find_map(|option| {
+            option
+                .strip_prefix(name)
+                .and_then(|rest| rest.strip_prefix('=').or((rest.is_empty()).then_some("")))
+        });
+
+        if let Some(value) = value {
+            let other_options = options
+                .iter()
+                .filter(|option| **option != name && !option.starts_with(&format!("{}=", name)))
+                .copied()
+                .collect();
+
+            return Some((&line[..index], value, other_options));
+        }
+    }
+
+    None
+}
+
+fn split_options(options: &str) -> Vec<&str> {
+    let mut split = Vec::new();
+    let mut start = 0;
+    let mut is_escaped = false;
+
+    for (index, c) in options.char_indices() {
+        match c {
+            _ if is_escaped => is_escaped = false,
+            '\\' => is_escaped = true,
+            ',' => {
+                split.push(&options[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    split.push(&options[start..]);
+
+    split
+}
@@ -0,0 +1,476 @@
+//! Rewriting of the bodies of text responses, such as scripts or JSON documents, with `$replace`
+//! filters. JSON documents are then pruned with `$jsonprune` filters.
+//!
+//! Regular expressions need the whole body, so it is buffered as it is received. Bodies that are
+//! larger than the configured limit, or take too long to be received, are streamed as they are,
+//! and counted in the statistics.
+use super::charset::{self, DocumentEncoding};
+use crate::configuration::BodyRewritingConfig;
+use crate::json_prune_filters::JsonPruneRule;
+use crate::replace_filters::ReplaceRule;
+use crate::statistics::Statistics;
+use encoding_rs::Encoding;
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use hyper::body::{Bytes, Sender};
+use std::borrow::Cow;
+use std::io;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Returns whether a response with this `Content-Type` is text that `$replace` filters may
+/// rewrite. Documents go through the html rewriter instead.
+pub(crate) fn is_rewritable_content_type(content_type: &str) -> bool {
+    let mime_type = mime_type(content_type);
+
+    if mime_type == "text/html" {
+        return false;
+    }
+
+    mime_type.starts_with("text/")
+        || mime_type.ends_with("+json")
+        || mime_type.ends_with("+xml")
+        || matches!(
+            mime_type.as_str(),
+            "application/javascript"
+                | "application/x-javascript"
+                | "application/ecmascript"
+                | "application/json"
+                | "application/xml"
+        )
+}
+
+fn is_json_content_type(content_type: &str) -> bool {
+    let mime_type = mime_type(content_type);
+
+    mime_type == "application/json" || mime_type.ends_with("+json")
+}
+
+fn mime_type(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+pub(crate) struct BodyRewriter {
+    content_type: Option<String>,
+    rules: Vec<ReplaceRule>,
+    json_prune_rules: Vec<JsonPruneRule>,
+    config: BodyRewritingConfig,
+    statistics: Statistics,
+}
+
+impl BodyRewriter {
+    pub(crate) fn new(
+        content_type: Option<String>,
+        rules: Vec<ReplaceRule>,
+        json_prune_rules: Vec<JsonPruneRule>,
+        config: BodyRewritingConfig,
+        statistics: Statistics,
+    ) -> Self {
+        Self {
+            content_type,
+            rules,
+            json_prune_rules,
+            config,
+            statistics,
+        }
+    }
+
+    /// Reads the decoded `body` and writes it, rewritten when possible, to `sender`.
+    pub(crate) async fn rewrite(
+        self,
+        mut body: BoxStream<'static, io::Result<Bytes>>,
+        mut sender: Sender,
+    ) {
+        let deadline = Instant::now() + Duration::from_secs(self.config.timeout_secs);
+        let mut buffer = Vec::new();
+
+        loop {
+            let chunk = match tokio::time::timeout_at(deadline, body.next()).await {
+                Ok(Some(Ok(chunk))) => chunk,
+                Ok(None) => break,
+                // The body is incomplete, the client has to find out.
+                Ok(Some(Err(_err))) => {
+                    sender.abort();
+                    return;
+                }
+                Err(_elapsed) => {
+                    log::info!(
+                        "Filters were not applied to a body that took more than {}s to be received",
+                        self.config.timeout_secs
+                    );
+                    self.statistics.increment_skipped_body_rewrites();
+                    pass_through(buffer, body, sender).await;
+                    return;
+                }
+            };
+
+            if (buffer.len() + chunk.len()) as u64 > self.config.max_body_size {
+                log::info!(
+                    "Filters were not applied to a body larger than {} bytes",
+                    self.config.max_body_size
+                );
+                self.statistics.increment_skipped_body_rewrites();
+                buffer.extend_from_slice(&chunk);
+                pass_through(buffer, body, sender).await;
+                return;
+            }
+
+            buffer.extend_from_slice(&chunk);
+        }
+
+        let body = match self.rewrite_text(&buffer) {
+            Some(rewritten) => {
+                self.statistics.increment_modified_responses();
+                rewritten
+            }
+            None => buffer,
+        };
+
+        let _result = sender.send_data(Bytes::from(body)).await;
+    }
+
+    /// Applies the rules to `body`, returning `None` when nothing changed or when the body can't
+    /// be decoded.
+    fn rewrite_text(&self, body: &[u8]) -> Option<Vec<u8>> {
+        // Only the byte order mark is looked at, `<meta>` declarations are specific to html.
+        let bom = &body[..body.len().min(3)];
+        let encoding = match charset::detect(self.content_type.as_deref(), bom) {
+            DocumentEncoding::Detected(encoding) => encoding,
+            DocumentEncoding::Unknown => return None,
+        };
+
+        // Bodies are written back using the same encoding, which `encoding_rs` can't do for
+        // UTF-16.
+        if encoding.output_encoding() != encoding {
+            return None;
+        }
+
+        let bom_length = Encoding::for_bom(body)
+            .filter(|(bom_encoding, _)| *bom_encoding == encoding)
+            .map(|(_, bom_length)| bom_length)
+            .unwrap_or(0);
+        let (bom, body) = body.split_at(bom_length);
+
+        let mut text = encoding.decode_without_bom_handling_and_without_replacement(body)?;
+        let mut is_modified = false;
+
+        for rule in &self.rules {
+            if let Some(replaced) = rule.apply(&text) {
+                text = Cow::Owned(replaced);
+                is_modified = true;
+            }
+        }
+
+        if let Some(pruned) = self.prune_json(&text) {
+            text = Cow::Owned(pruned);
+            is_modified = true;
+        }
+
+        if !is_modified {
+            return None;
+        }
+
+        let (encoded, _encoding, _had_unmappable_characters) = encoding.encode(&text);
+
+        let mut rewritten = bom.to_vec();
+        rewritten.extend_from_slice(&encoded);
+
+        Some(rewritten)
+    }
+
+    /// Deletes the properties matched by the `$jsonprune` rules, returning `None` when the body
+    /// isn't JSON, has numbers that can't be written back as they are, or when nothing was
+    /// deleted.
+    fn prune_json(&self, text: &str) -> Option<String> {
+        if self.json_prune_rules.is_empty()
+            || !self
+                .content_type
+                .as_deref()
+                .map(is_json_content_type)
+                .unwrap_or(false)
+        {
+            return None;
+        }
+
+        let mut value = serde_json::from_str::<serde_json::Value>(text).ok()?;
+
+        // Writing such numbers back would change them, the document is better left alone.
+        if has_lossy_numbers(text) {
+            return None;
+        }
+
+        let mut is_pruned = false;
+
+        for rule in &self.json_prune_rules {
+            if rule.apply(&mut value) {
+                self.statistics
+                    .increment_json_prune_filter_hits(rule.filter());
+                is_pruned = true;
+            }
+        }
+
+        if !is_pruned {
+            return None;
+        }
+
+        serde_json::to_string(&value).ok()
+    }
+}
+
+/// Returns whether `text` has numbers that `serde_json` can't write back with the same value,
+/// such as integers out of the `i64` and `u64` ranges or decimals with too many digits.
+fn has_lossy_numbers(text: &str) -> bool {
+    let mut is_in_string = false;
+    let mut is_escaped = false;
+    let mut number_start = None;
+
+    for (index, character) in text.char_indices() {
+        if let Some(start) = number_start {
+            if matches!(character, '0'..='9' | '-' | '+' | '.' | 'e' | 'E') {
+                continue;
+            }
+
+            number_start = None;
+
+            if !is_lossless_number(&text[start..index]) {
+                return true;
+            }
+        }
+
+        if is_in_string {
+            match character {
+                _ if is_escaped => is_escaped = false,
+                '\\' => is_escaped = true,
+                '"' => is_in_string = false,
+                _ => {}
+            }
+        } else {
+            match character {
+                '"' => is_in_string = true,
+                '0'..='9' | '-' => number_start = Some(index),
+                _ => {}
+            }
+        }
+    }
+
+    number_start
+        .map(|start| !is_lossless_number(&text[start..]))
+        .unwrap_or(false)
+}
+
+fn is_lossless_number(number: &str) -> bool {
+    if !number.contains(['.', 'e', 'E']) {
+        return number.parse::<i64>().is_ok() || number.parse::<u64>().is_ok();
+    }
+
+    let written = match number
+        .parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+    {
+        Some(written) => written.to_string(),
+        None => return false,
+    };
+
+    match (normalize_decimal(number), normalize_decimal(&written)) {
+        (Some(number), Some(written)) => number == written,
+        _ => false,
+    }
+}
+
+/// Splits a decimal number into its significant digits and exponent, so that `1.50` and `15e-1`
+/// compare equal.
+fn normalize_decimal(number: &str) -> Option<(bool, String, i64)> {
+    let (is_negative, number) = match number.strip_prefix('-') {
+        Some(number) => (true, number),
+        None => (false, number),
+    };
+
+    let (mantissa, exponent) = match number.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, exponent.parse::<i64>().ok()?),
+        None => (number, 0),
+    };
+
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{integer}{fraction}");
+    let digits = digits.trim_start_matches('0');
+    let trimmed = digits.trim_end_matches('0');
+
+    if trimmed.is_empty() {
+        return Some((false, String::new(), 0));
+    }
+
+    let exponent = exponent - fraction.len() as i64 + (digits.len() - trimmed.len()) as i64;
+
+    Some((is_negative, trimmed.to_string(), exponent))
+}
+
+async fn pass_through(
+    buffer: Vec<u8>,
+    mut body: BoxStream<'static, io::Result<Bytes>>,
+    mut sender: Sender,
+) {
+    if sender.send_data(Bytes::from(buffer)).await.is_err() {
+        return;
+    }
+
+    while let Some(chunk) = body.next().await {
+        match chunk {
+            Ok(chunk) => {
+                // The other end is broken, let's abort immediately.
+                if let Err(_err) = sender.send_data(chunk).await {
+                    break;
+                }
+            }
+            Err(_err) => {
+                sender.abort();
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::option_filters::FilterOption;
+
+    fn json_rewriter(paths: &str) -> BodyRewriter {
+        BodyRewriter::new(
+            Some("application/json; charset=utf-8".to_string()),
+            Vec::new(),
+            vec![JsonPruneRule::parse(paths, "filter").unwrap()],
+            BodyRewritingConfig::default(),
+            Statistics::new(),
+        )
+    }
+
+    /// Streams `chunks` through `rewriter`, returning the body sent to the client.
+    async fn rewrite_body(
+        rewriter: BodyRewriter,
+        chunks: BoxStream<'static, io::Result<Bytes>>,
+    ) -> String {
+        let (sender, body) = hyper::Body::channel();
+        tokio::spawn(rewriter.rewrite(chunks, sender));
+
+        String::from_utf8(hyper::body::to_bytes(body).await.unwrap().to_vec()).unwrap()
+    }
+
+    fn rewrite(rewriter: &BodyRewriter, body: &str) -> Option<String> {
+        rewriter
+            .rewrite_text(body.as_bytes())
+            .map(|rewritten| String::from_utf8(rewritten).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_rewrite() {
+        let statistics = Statistics::new();
+        let mut rewriter = json_rewriter("ad");
+        rewriter.statistics = statistics.clone();
+
+        let chunks = futures_util::stream::iter([
+            Ok(Bytes::from_static(br#"{"id": 1, "#)),
+            Ok(Bytes::from_static(br#""ad": 1}"#)),
+        ]);
+
+        assert_eq!(rewrite_body(rewriter, chunks.boxed()).await, r#"{"id":1}"#);
+        assert_eq!(*statistics.modified_responses.lock().unwrap(), 1);
+        assert_eq!(*statistics.skipped_body_rewrites.lock().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_skips_large_bodies() {
+        let statistics = Statistics::new();
+        let mut rewriter = json_rewriter("ad");
+        rewriter.statistics = statistics.clone();
+        rewriter.config.max_body_size = 10;
+
+        let chunks = futures_util::stream::iter([
+            Ok(Bytes::from_static(br#"{"id": 1, "#)),
+            Ok(Bytes::from_static(br#""ad": 1}"#)),
+        ]);
+
+        assert_eq!(
+            rewrite_body(rewriter, chunks.boxed()).await,
+            r#"{"id": 1, "ad": 1}"#
+        );
+        assert_eq!(*statistics.modified_responses.lock().unwrap(), 0);
+        assert_eq!(*statistics.skipped_body_rewrites.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_skips_slow_bodies() {
+        let statistics = Statistics::new();
+        let mut rewriter = json_rewriter("ad");
+        rewriter.statistics = statistics.clone();
+        rewriter.config.timeout_secs = 0;
+
+        let chunks = futures_util::stream::once(async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok(Bytes::from_static(br#"{"id": 1, "ad": 1}"#))
+        });
+
+        assert_eq!(
+            rewrite_body(rewriter, chunks.boxed()).await,
+            r#"{"id": 1, "ad": 1}"#
+        );
+        assert_eq!(*statistics.modified_responses.lock().unwrap(), 0);
+        assert_eq!(*statistics.skipped_body_rewrites.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_prune_json_keeps_key_order() {
+        let rewriter = json_rewriter("b ..ad");
+
+        assert_eq!(
+            rewrite(
+                &rewriter,
+                r#"{"z": 1, "b": 2, "y": {"x": 1, "ad": 2, "a": 3}, "a": [{"c": 1, "ad": 1, "b": 2}]}"#
+            )
+            .as_deref(),
+            Some(r#"{"z":1,"y":{"x":1,"a":3},"a":[{"c":1,"b":2}]}"#)
+        );
+        assert_eq!(rewrite(&rewriter, r#"{"z": 1, "a": 2}"#), None);
+    }
+
+    #[test]
+    fn test_prune_json_skips_lossy_numbers() {
+        let rewriter = json_rewriter("ad");
+
+        assert_eq!(
+            rewrite(&rewriter, r#"{"id": 18446744073709551616, "ad": 1}"#),
+            None
+        );
+        assert_eq!(
+            rewrite(&rewriter, r#"{"x": 0.10000000000000000001, "ad": 1}"#),
+            None
+        );
+        assert_eq!(
+            rewrite(
+                &rewriter,
+                r#"{"id": -9223372036854775808, "x": 1.50, "y": 2e3, "s": "1e999", "ad": 1}"#
+            )
+            .as_deref(),
+            Some(r#"{"id":-9223372036854775808,"x":1.5,"y":2000.0,"s":"1e999"}"#)
+        );
+    }
+
+    #[test]
+    fn test_has_lossy_numbers() {
+        assert!(!has_lossy_numbers(
+            r#"[0, -1, 1.5, 1e-7, 18446744073709551615]"#
+        ));
+        assert!(!has_lossy_numbers(
+            r#"{"a\"1e999": "-18446744073709551616"}"#
+        ));
+        assert!(has_lossy_numbers("18446744073709551616"));
+        assert!(has_lossy_numbers("[-9223372036854775809]"));
+        assert!(has_lossy_numbers("[0.30000000000000000001]"));
+        assert!(has_lossy_numbers("[1e400]"));
+    }
+}
@@ -0,0 +1,186 @@
+//! Detection of the character encoding of HTML documents, loosely following the
+//! [HTML standard](https://html.spec.whatwg.org/multipage/parsing.html#determining-the-character-encoding).
+use encoding_rs::Encoding;
+use once_cell::sync::Lazy;
+use regex::bytes::Regex;
+
+/// Number of bytes of a document that are looked at to find a `<meta>` charset declaration.
+pub(crate) const PRESCAN_LENGTH: usize = 1024;
+
+static CONTENT_TYPE_CHARSET_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"(?i)charset\s*=\s*["']?([^"';\s]+)"#).unwrap());
+
+// Matches both `<meta charset="...">` and `<meta http-equiv="Content-Type" content="...; charset=...">`.
+// The label has to end within the prescanned bytes, so that a declaration cut off by the end of the
+// prescan isn't read as a shorter label.
+static META_CHARSET_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?i)<meta\s[^>]*?charset\s*=\s*["']?\s*([a-z0-9_:.+-]+)["'\s;/>]"#).unwrap()
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DocumentEncoding {
+    Detected(&'static Encoding),
+    /// The document declares an encoding we don't know about.
+    Unknown,
+}
+
+/// Detects the encoding of a document out of its byte order mark, the charset parameter of its
+/// `Content-Type` header or a `<meta>` tag found in its first bytes, in that order.
+///
+/// Documents without any declaration are assumed to be UTF-8.
+pub(crate) fn detect(content_type: Option<&str>, document_start: &[u8]) -> DocumentEncoding {
+    if let Some((encoding, _bom_length)) = Encoding::for_bom(document_start) {
+        return DocumentEncoding::Detected(encoding);
+    }
+
+    if let Some(label) = content_type.and_then(|content_type| {
+        CONTENT_TYPE_CHARSET_RE
+            .captures(content_type.as_bytes())
+            .and_then(|captures| captures.get(1))
+    }) {
+        return from_label(label.as_bytes(), false);
+    }
+
+    let prescanned = &document_start[..document_start.len().min(PRESCAN_LENGTH)];
+
+    if let Some(label) = META_CHARSET_RE
+        .captures(prescanned)
+        .and_then(|captures| captures.get(1))
+    {
+        return from_label(label.as_bytes(), true);
+    }
+
+    DocumentEncoding::Detected(encoding_rs::UTF_8)
+}
+
+fn from_label(label: &[u8], from_meta_tag: bool) -> DocumentEncoding {
+    match Encoding::for_label_no_replacement(label) {
+        // A document can't declare itself as UTF-16 from within its own content, as it would
+        // not be possible to read the declaration in the first place.
+        Some(encoding)
+            if from_meta_tag
+                && (encoding == encoding_rs::UTF_16LE || encoding == encoding_rs::UTF_16BE) =>
+        {
+            DocumentEncoding::Detected(encoding_rs::UTF_8)
+        }
+        Some(encoding) => DocumentEncoding::Detected(encoding),
+        None => DocumentEncoding::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UTF_8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+    fn detected(encoding: &'static Encoding) -> DocumentEncoding {
+        DocumentEncoding::Detected(encoding)
+    }
+
+    /// Returns a document whose `<meta>` declaration starts at `offset`.
+    fn with_meta_at(offset: usize, meta: &str) -> Vec<u8> {
+        let mut document = b"<!doctype html><html><head>".to_vec();
+        document.resize(offset, b' ');
+        document.extend_from_slice(meta.as_bytes());
+        document.extend_from_slice(b"</head></html>");
+        document
+    }
+
+    #[test]
+    fn test_precedence() {
+        let document = [UTF_8_BOM, b"<meta charset=\"windows-1252\">"].concat();
+
+        assert_eq!(
+            detect(Some("text/html; charset=iso-8859-2"), &document),
+            detected(encoding_rs::UTF_8)
+        );
+        assert_eq!(
+            detect(Some("text/html; charset=iso-8859-2"), &document[3..]),
+            detected(encoding_rs::ISO_8859_2)
+        );
+        assert_eq!(
+            detect(Some("text/html"), &document[3..]),
+            detected(encoding_rs::WINDOWS_1252)
+        );
+        assert_eq!(detect(None, b"<html></html>"), detected(encoding_rs::UTF_8));
+        assert_eq!(
+            detect(Some("text/html"), b"\xFF\xFE<\x00"),
+            detected(encoding_rs::UTF_16LE)
+        );
+    }
+
+    #[test]
+    fn test_content_type() {
+        assert_eq!(
+            detect(Some("text/html; charset=\"Shift_JIS\""), b""),
+            detected(encoding_rs::SHIFT_JIS)
+        );
+        assert_eq!(
+            detect(Some("text/html;CHARSET=utf-16"), b""),
+            detected(encoding_rs::UTF_16LE)
+        );
+        assert_eq!(
+            detect(Some("text/html; charset=x-unknown"), b""),
+            DocumentEncoding::Unknown
+        );
+    }
+
+    #[test]
+    fn test_meta_tags() {
+        assert_eq!(
+            detect(None, b"<head><META Charset=euc-jp></head>"),
+            detected(encoding_rs::EUC_JP)
+        );
+        assert_eq!(
+            detect(
+                None,
+                b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=koi8-r\">"
+            ),
+            detected(encoding_rs::KOI8_R)
+        );
+        assert_eq!(
+            detect(None, b"<meta charset='x-unknown'>"),
+            DocumentEncoding::Unknown
+        );
+    }
+
+    #[test]
+    fn test_utf_16_meta_tags() {
+        assert_eq!(
+            detect(None, b"<meta charset=\"utf-16\">"),
+            detected(encoding_rs::UTF_8)
+        );
+        assert_eq!(
+            detect(None, b"<meta charset=\"UTF-16BE\">"),
+            detected(encoding_rs::UTF_8)
+        );
+    }
+
+    #[test]
+    fn test_prescan_length() {
+        let meta = "<meta charset=\"windows-1252\">";
+
+        assert_eq!(
+            detect(None, &with_meta_at(PRESCAN_LENGTH - meta.len(), meta)),
+            detected(encoding_rs::WINDOWS_1252)
+        );
+        assert_eq!(
+            detect(None, &with_meta_at(PRESCAN_LENGTH, meta)),
+            detected(encoding_rs::UTF_8)
+        );
+    }
+
+    #[test]
+    fn test_meta_tag_split_by_prescan() {
+        // Only `<meta charset="windows-1` is prescanned, which must not be read as a label.
+        let document = with_meta_at(PRESCAN_LENGTH - 24, "<meta charset=\"windows-1252\">");
+
+        assert_eq!(detect(None, &document), detected(encoding_rs::UTF_8));
+
+        // Nor should `<meta charset=iso-8859-1` be read as ISO-8859-1.
+        let document = with_meta_at(PRESCAN_LENGTH - 24, "<meta charset=iso-8859-15>");
+
+        assert_eq!(detect(None, &document), detected(encoding_rs::UTF_8));
+    }
+}
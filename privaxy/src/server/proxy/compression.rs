@@ -0,0 +1,373 @@
+//! Content coding helpers. Responses that are not rewritten are streamed to clients exactly as
+//! they were received, compressed bytes included. Responses that go through the html rewriter
+//! are decoded first and compressed again once they have been rewritten.
+use async_compression::tokio::bufread::{
+    BrotliDecoder, BrotliEncoder, GzipDecoder, GzipEncoder, ZlibDecoder,
+};
+use async_compression::Level;
+use futures_util::stream::BoxStream;
+use futures_util::{Stream, StreamExt, TryStreamExt};
+use http::header::{HeaderMap, HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING};
+use hyper::body::Bytes;
+use hyper::Body;
+use std::io;
+use tokio::io::AsyncBufRead;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+// Brotli's default quality (11) is far too slow for on the fly compression.
+const BROTLI_QUALITY: i32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ContentEncoding {
+    Identity,
+    Gzip,
+    Deflate,
+    Brotli,
+}
+
+impl ContentEncoding {
+    fn from_token(token: &str) -> Option<Self> {
+        match token.trim().to_ascii_lowercase().as_str() {
+            "identity" => Some(Self::Identity),
+            "gzip" | "x-gzip" => Some(Self::Gzip),
+            "deflate" => Some(Self::Deflate),
+            "br" => Some(Self::Brotli),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Identity => "identity",
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+            Self::Brotli => "br",
+        }
+    }
+
+    /// Returns the coding of a response body, or `None` when it makes use of a coding we are not
+    /// able to decode (such as `zstd`) or of several stacked codings.
+    pub(crate) fn from_response_headers(headers: &HeaderMap) -> Option<Self> {
+        let mut values = headers.get_all(CONTENT_ENCODING).iter();
+
+        match (values.next(), values.next()) {
+            (None, _) => Some(Self::Identity),
+            (Some(value), None) => {
+                let value = value.to_str().ok()?;
+
+                if value.contains(',') {
+                    None
+                } else {
+                    Self::from_token(value)
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Picks the coding to use for a body we produce, out of a client `Accept-Encoding`
+    /// header. The coding with the highest quality value is picked, brotli being preferred over
+    /// gzip when they have the same.
+    pub(crate) fn negotiate(request_headers: &HeaderMap) -> Self {
+        let accepted = accepted_encodings(request_headers);
+        let quality = |encoding: Self| {
+            accepted
+                .iter()
+                .find(|accepted| accepted.encoding == encoding)
+                .map(|accepted| accepted.quality)
+        };
+
+        [Self::Brotli, Self::Gzip]
+            .into_iter()
+            .filter_map(|encoding| Some((encoding, quality(encoding)?)))
+            .fold(
+                None,
+                |best: Option<(Self, f32)>, (encoding, quality)| match best {
+                    Some((_, best_quality)) if best_quality >= quality => best,
+                    _ => Some((encoding, quality)),
+                },
+            )
+            .map(|(encoding, _quality)| encoding)
+            .unwrap_or(Self::Identity)
+    }
+
+    pub(crate) fn header_value(&self) -> HeaderValue {
+        HeaderValue::from_static(self.as_str())
+    }
+}
+
+/// A coding accepted by a client.
+#[derive(Debug, PartialEq)]
+struct AcceptedEncoding {
+    encoding: ContentEncoding,
+    quality: f32,
+    /// The quality value as it was sent, when it was.
+    quality_value: Option<String>,
+}
+
+/// Returns the codings of an `Accept-Encoding` header we know of, leaving out the ones the client
+/// refuses with `q=0`.
+fn accepted_encodings(headers: &HeaderMap) -> Vec<AcceptedEncoding> {
+    let mut accepted: Vec<AcceptedEncoding> = Vec::new();
+
+    for item in headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+    {
+        let mut parts = item.split(';');
+
+        let encoding = match parts.next().and_then(ContentEncoding::from_token) {
+            Some(encoding) => encoding,
+            None => continue,
+        };
+
+        let quality_value = parts
+            .find_map(|parameter| parameter.trim().strip_prefix("q="))
+            .map(|quality| quality.trim().to_string());
+        let quality = match &quality_value {
+            Some(quality_value) => match quality_value.parse::<f32>() {
+                Ok(quality) => quality,
+                Err(_) => continue,
+            },
+            None => 1.0,
+        };
+
+        // `gzip;q=0` means that the client explicitly refuses the coding.
+        if quality <= 0.0
+            || accepted
+                .iter()
+                .any(|accepted| accepted.encoding == encoding)
+        {
+            continue;
+        }
+
+        accepted.push(AcceptedEncoding {
+            encoding,
+            quality,
+            quality_value,
+        });
+    }
+
+    accepted
+}
+
+/// Restricts the `Accept-Encoding` header of a request to the codings we are able to decode, so
+/// that any response that has to be rewritten can be decompressed. The quality values of the
+/// client are kept.
+pub(crate) fn restrict_accept_encoding(request_headers: &mut HeaderMap) {
+    let accepted = accepted_encodings(request_headers)
+        .into_iter()
+        .filter(|accepted| accepted.encoding != ContentEncoding::Identity)
+        .map(|accepted| match accepted.quality_value {
+            Some(quality_value) => format!("{};q={}", accepted.encoding.as_str(), quality_value),
+            None => accepted.encoding.as_str().to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    // Without the header, servers may pick any coding, including ones we can't decode.
+    let accepted = if accepted.is_empty() {
+        HeaderValue::from_static("identity")
+    } else {
+        match HeaderValue::from_str(&accepted) {
+            Ok(accepted) => accepted,
+            Err(_) => HeaderValue::from_static("identity"),
+        }
+    };
+
+    request_headers.remove(ACCEPT_ENCODING);
+    request_headers.insert(ACCEPT_ENCODING, accepted);
+}
+
+/// Decodes a body stream compressed with `encoding`.
+pub(crate) fn decode<S>(
+    stream: S,
+    encoding: ContentEncoding,
+) -> BoxStream<'static, io::Result<Bytes>>
+where
+    S: Stream<Item = io::Result<Bytes>> + Send + 'static,
+{
+    let reader = StreamReader::new(stream);
+
+    match encoding {
+        ContentEncoding::Identity => ReaderStream::new(reader).boxed(),
+        ContentEncoding::Gzip => {
+            let mut decoder = GzipDecoder::new(reader);
+            decoder.multiple_members(true);
+            ReaderStream::new(decoder).boxed()
+        }
+        // The `deflate` content coding is the zlib format (RFC 9110, section 8.4.1.2).
+        ContentEncoding::Deflate => ReaderStream::new(ZlibDecoder::new(reader)).boxed(),
+        ContentEncoding::Brotli => ReaderStream::new(BrotliDecoder::new(reader)).boxed(),
+    }
+}
+
+/// Compresses a body with `encoding`. Only gzip and brotli are ever picked by
+/// [`ContentEncoding::negotiate`], any other coding leaves the body untouched.
+pub(crate) fn encode(body: Body, encoding: ContentEncoding) -> Body {
+    match encoding {
+        ContentEncoding::Gzip => {
+            Body::wrap_stream(ReaderStream::new(GzipEncoder::new(body_reader(body))))
+        }
+        ContentEncoding::Brotli => Body::wrap_stream(ReaderStream::new(
+            BrotliEncoder::with_quality(body_reader(body), Level::Precise(BROTLI_QUALITY)),
+        )),
+        ContentEncoding::Identity | ContentEncoding::Deflate => body,
+    }
+}
+
+fn body_reader(body: Body) -> impl AsyncBufRead + Send {
+    StreamReader::new(body.map_err(io::Error::other))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_compression::tokio::bufread::ZlibEncoder;
+
+    fn headers(name: http::header::HeaderName, values: &[&'static str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(name.clone(), HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    fn negotiate(accept_encoding: &'static str) -> ContentEncoding {
+        ContentEncoding::negotiate(&headers(ACCEPT_ENCODING, &[accept_encoding]))
+    }
+
+    fn restrict(values: &[&'static str]) -> String {
+        let mut headers = headers(ACCEPT_ENCODING, values);
+        restrict_accept_encoding(&mut headers);
+
+        let values = headers.get_all(ACCEPT_ENCODING).iter().collect::<Vec<_>>();
+        assert_eq!(values.len(), 1);
+        values[0].to_str().unwrap().to_string()
+    }
+
+    async fn collect(stream: BoxStream<'static, io::Result<Bytes>>) -> Vec<u8> {
+        stream
+            .try_fold(Vec::new(), |mut body, chunk| async move {
+                body.extend_from_slice(&chunk);
+                Ok(body)
+            })
+            .await
+            .unwrap()
+    }
+
+    fn chunks(body: Vec<u8>) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+        // Small chunks, so that codings are decoded across chunk boundaries.
+        futures_util::stream::iter(
+            body.chunks(7)
+                .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn document() -> Vec<u8> {
+        "<html><body>Privaxy</body></html>\n"
+            .repeat(100)
+            .into_bytes()
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("gzip, deflate, br"), ContentEncoding::Brotli);
+        assert_eq!(negotiate("gzip, deflate"), ContentEncoding::Gzip);
+        assert_eq!(negotiate("deflate, zstd"), ContentEncoding::Identity);
+        assert_eq!(negotiate("br;q=0, gzip"), ContentEncoding::Gzip);
+        assert_eq!(negotiate("br;q=0.5, gzip;q=0.8"), ContentEncoding::Gzip);
+        assert_eq!(negotiate("gzip;q=0.8, br;q=0.8"), ContentEncoding::Brotli);
+        assert_eq!(negotiate("GZIP;Q=1"), ContentEncoding::Gzip);
+        assert_eq!(negotiate("br;q=0.0, gzip;q=0"), ContentEncoding::Identity);
+        assert_eq!(
+            ContentEncoding::negotiate(&HeaderMap::new()),
+            ContentEncoding::Identity
+        );
+    }
+
+    #[test]
+    fn test_restrict_accept_encoding() {
+        assert_eq!(restrict(&["gzip, deflate, br, zstd"]), "gzip, deflate, br");
+        assert_eq!(
+            restrict(&["br;q=1.0, gzip;q=0.8, *;q=0.1"]),
+            "br;q=1.0, gzip;q=0.8"
+        );
+        assert_eq!(restrict(&["br;q=0, gzip"]), "gzip");
+        assert_eq!(restrict(&["gzip", "br;q=0.5"]), "gzip, br;q=0.5");
+        assert_eq!(restrict(&["br;q=0", "zstd"]), "identity");
+        assert_eq!(restrict(&["identity"]), "identity");
+        assert_eq!(restrict(&[]), "identity");
+    }
+
+    #[test]
+    fn test_from_response_headers() {
+        let from = |values: &[&'static str]| {
+            ContentEncoding::from_response_headers(&headers(CONTENT_ENCODING, values))
+        };
+
+        assert_eq!(from(&[]), Some(ContentEncoding::Identity));
+        assert_eq!(from(&["gzip"]), Some(ContentEncoding::Gzip));
+        assert_eq!(from(&["x-gzip"]), Some(ContentEncoding::Gzip));
+        assert_eq!(from(&["Br"]), Some(ContentEncoding::Brotli));
+        assert_eq!(from(&["deflate"]), Some(ContentEncoding::Deflate));
+        assert_eq!(from(&["zstd"]), None);
+        assert_eq!(from(&["gzip, br"]), None);
+        assert_eq!(from(&["gzip", "br"]), None);
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        for encoding in [ContentEncoding::Gzip, ContentEncoding::Brotli] {
+            let encoded = encode(Body::from(document()), encoding);
+            let encoded = collect(encoded.map_err(io::Error::other).boxed()).await;
+            assert_ne!(encoded, document());
+
+            let decoded = collect(decode(chunks(encoded), encoding)).await;
+            assert_eq!(decoded, document(), "{:?}", encoding);
+        }
+
+        for encoding in [ContentEncoding::Identity, ContentEncoding::Deflate] {
+            let body = collect(
+                encode(Body::from(document()), encoding)
+                    .map_err(io::Error::other)
+                    .boxed(),
+            )
+            .await;
+            assert_eq!(body, document());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_decode_deflate() {
+        let encoded = collect(
+            ReaderStream::new(ZlibEncoder::new(StreamReader::new(chunks(document())))).boxed(),
+        )
+        .await;
+
+        assert_eq!(
+            collect(decode(chunks(encoded), ContentEncoding::Deflate)).await,
+            document()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decode_identity() {
+        assert_eq!(
+            collect(decode(chunks(document()), ContentEncoding::Identity)).await,
+            document()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decode_corrupted() {
+        let result = decode(chunks(b"not gzip".to_vec()), ContentEncoding::Gzip)
+            .try_collect::<Vec<_>>()
+            .await;
+
+        assert!(result.is_err());
+    }
+}
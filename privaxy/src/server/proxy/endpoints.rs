@@ -0,0 +1,38 @@
+//! Helpers shared by the endpoints the proxy answers itself on every origin. Any page can reach
+//! them, so their inputs are treated as untrusted.
+use http::header::{HeaderValue, CACHE_CONTROL};
+use http::StatusCode;
+use hyper::body::HttpBody;
+use hyper::{Body, Response};
+
+/// Reads `body`, giving up as soon as it gets longer than `limit` rather than buffering it whole:
+/// chunked bodies don't tell their length upfront.
+pub(super) async fn read_body(mut body: Body, limit: u64) -> Result<Vec<u8>, StatusCode> {
+    if body.size_hint().lower() > limit {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    let mut bytes = Vec::new();
+
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_err| StatusCode::BAD_REQUEST)?;
+
+        if (bytes.len() + chunk.len()) as u64 > limit {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes)
+}
+
+pub(super) fn get_response(status_code: StatusCode, body: Body) -> Response<Body> {
+    let mut response = Response::new(body);
+    *response.status_mut() = status_code;
+    response
+        .headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+    response
+}
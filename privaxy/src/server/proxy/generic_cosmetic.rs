@@ -0,0 +1,97 @@
+//! Generic cosmetic filtering of elements inserted by scripts after the page has loaded.
+//!
+//! The html rewriter only sees the ids and classes of the initial document. A small runtime is
+//! injected into pages to watch DOM mutations; it sends newly seen ids and classes to an endpoint
+//! the proxy answers itself, on the page's own origin, and applies the selectors it gets back.
+use super::endpoints::{get_response, read_body};
+use crate::blocker::AdblockRequester;
+use http::header::{HeaderValue, CONTENT_TYPE};
+use http::{Method, StatusCode, Uri};
+use hyper::{Body, Request, Response};
+use serde::Deserialize;
+use std::collections::HashSet;
+
+/// Path intercepted by the proxy on every origin, requests to it never reach servers.
+pub(crate) const ENDPOINT_PATH: &str = "/__privaxy__/generic-cosmetic-filters";
+
+// Ids and classes are sent in batches by the runtime, this is plenty.
+const MAX_REQUEST_BODY_LENGTH: u64 = 256 * 1024;
+
+#[derive(Deserialize)]
+struct GenericCosmeticRequest {
+    url: String,
+    #[serde(default)]
+    ids: Vec<String>,
+    #[serde(default)]
+    classes: Vec<String>,
+}
+
+pub(crate) fn is_endpoint_request(request: &Request<Body>) -> bool {
+    request.uri().path() == ENDPOINT_PATH
+}
+
+/// Returns the runtime script, seeded with the ids and classes the rewriter has already seen so
+/// that they are not requested a second time.
+pub(crate) fn runtime_script(ids: &HashSet<String>, classes: &HashSet<String>) -> String {
+    include_str!("../../resources/generic_cosmetic_filtering.js")
+        .replace("#{endpoint}#", ENDPOINT_PATH)
+        .replace("#{ids}#", &to_script_safe_json(ids))
+        .replace("#{classes}#", &to_script_safe_json(classes))
+}
+
+// Ids and classes come from the page, they must not be able to close the script element.
+fn to_script_safe_json(values: &HashSet<String>) -> String {
+    serde_json::to_string(values)
+        .unwrap()
+        .replace('<', "\\u003c")
+        .replace('>', "\\u003e")
+}
+
+pub(crate) async fn serve(
+    request: Request<Body>,
+    uri: Uri,
+    adblock_requester: AdblockRequester,
+) -> Response<Body> {
+    if request.method() != Method::POST {
+        return get_response(StatusCode::METHOD_NOT_ALLOWED, Body::empty());
+    }
+
+    let body = match read_body(request.into_body(), MAX_REQUEST_BODY_LENGTH).await {
+        Ok(body) => body,
+        Err(status_code) => return get_response(status_code, Body::empty()),
+    };
+
+    let generic_cosmetic_request: GenericCosmeticRequest = match serde_json::from_slice(&body) {
+        Ok(generic_cosmetic_request) => generic_cosmetic_request,
+        Err(_err) => return get_response(StatusCode::BAD_REQUEST, Body::empty()),
+    };
+
+    // Pages may only ask about themselves, exceptions of other websites are none of their
+    // business.
+    let is_same_host = generic_cosmetic_request
+        .url
+        .parse::<Uri>()
+        .map(|page_uri| page_uri.host().is_some() && page_uri.host() == uri.host())
+        .unwrap_or(false);
+    if !is_same_host {
+        return get_response(StatusCode::FORBIDDEN, Body::empty());
+    }
+
+    let selectors = adblock_requester
+        .get_hidden_class_id_selectors(
+            generic_cosmetic_request.url,
+            generic_cosmetic_request.ids,
+            generic_cosmetic_request.classes,
+        )
+        .await;
+
+    let mut response = get_response(
+        StatusCode::OK,
+        Body::from(serde_json::to_string(&selectors).unwrap()),
+    );
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+    response
+}
@@ -0,0 +1,391 @@
+//! HTML filters, such as `example.com##^script:has-text(adsbygoogle)`, compiled into lol_html
+//! handlers removing matching elements from documents.
+//!
+//! Elements only matched by a selector are removed as they are parsed. `:has-text()` and
+//! `:min-text-length()` are supported on elements whose content is raw text, such as scripts:
+//! their start tag and content are held back until their end tag, at which point they are either
+//! dropped or written out as they were.
+use lol_html::html_content::{ContentType, Element};
+use lol_html::{doc_text, DocumentContentHandlers, ElementContentHandlers, Selector};
+use regex::Regex;
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+const HAS_TEXT_OPERATOR: &str = ":has-text(";
+const MIN_TEXT_LENGTH_OPERATOR: &str = ":min-text-length(";
+
+/// Elements whose content can't contain other elements.
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style", "textarea", "title"];
+
+#[derive(Clone)]
+enum TextCondition {
+    HasText(Regex),
+    MinTextLength(usize),
+}
+
+impl TextCondition {
+    fn matches(&self, text: &str) -> bool {
+        match self {
+            Self::HasText(regex) => regex.is_match(text),
+            Self::MinTextLength(length) => text.chars().count() >= *length,
+        }
+    }
+}
+
+struct HtmlFilter {
+    selector: Selector,
+    condition: Option<TextCondition>,
+}
+
+/// Element held back until its end tag, to find out whether its text matches a condition.
+struct PendingElement {
+    start_tag: String,
+    text: String,
+    conditions: Vec<TextCondition>,
+}
+
+pub(crate) type ElementHandlers = Vec<(Cow<'static, Selector>, ElementContentHandlers<'static>)>;
+
+/// Builds the handlers removing the elements matched by `selectors`. `elements_removed` is set
+/// as soon as an element has been removed.
+pub(crate) fn build_handlers(
+    selectors: &[String],
+    elements_removed: Arc<AtomicBool>,
+) -> (ElementHandlers, Vec<DocumentContentHandlers<'static>>) {
+    let (plain_filters, conditional_filters): (Vec<_>, Vec<_>) = selectors
+        .iter()
+        .filter_map(|selector| match compile(selector) {
+            Some(filter) => Some(filter),
+            None => {
+                log::debug!("Unsupported html filter: {}", selector);
+                None
+            }
+        })
+        .partition(|filter| filter.condition.is_none());
+
+    let mut element_handlers: ElementHandlers = Vec::new();
+    let mut document_handlers = Vec::new();
+
+    // Plain filters come first, so that conditional ones know when an element is already gone.
+    for filter in plain_filters {
+        let elements_removed = Arc::clone(&elements_removed);
+
+        element_handlers.push((
+            Cow::Owned(filter.selector),
+            ElementContentHandlers::default().element(move |element| {
+                element.remove();
+                elements_removed.store(true, Ordering::Relaxed);
+                Ok(())
+            }),
+        ));
+    }
+
+    if conditional_filters.is_empty() {
+        return (element_handlers, document_handlers);
+    }
+
+    let pending_element: Arc<Mutex<Option<PendingElement>>> = Arc::new(Mutex::new(None));
+
+    for filter in conditional_filters {
+        let pending_element = Arc::clone(&pending_element);
+        let elements_removed = Arc::clone(&elements_removed);
+        let condition = filter.condition.unwrap();
+
+        element_handlers.push((
+            Cow::Owned(filter.selector),
+            ElementContentHandlers::default().element(move |element| {
+                let mut pending = pending_element.lock().unwrap();
+
+                // Several filters may match the same element.
+                if let Some(pending) = pending.as_mut() {
+                    pending.conditions.push(condition.clone());
+                    return Ok(());
+                }
+
+                if element.removed() {
+                    return Ok(());
+                }
+
+                *pending = Some(PendingElement {
+                    start_tag: serialize_start_tag(element),
+                    text: String::new(),
+                    conditions: vec![condition.clone()],
+                });
+
+                // The end tag is left as it was, it is only removed along with the element.
+                element.start_tag().remove();
+
+                let pending_element = Arc::clone(&pending_element);
+                let elements_removed = Arc::clone(&elements_removed);
+
+                if let Some(handlers) = element.end_tag_handlers() {
+                    handlers.push(Box::new(move |end| {
+                        let pending = match pending_element.lock().unwrap().take() {
+                            Some(pending) => pending,
+                            None => return Ok(()),
+                        };
+
+                        let is_matching = pending
+                            .conditions
+                            .iter()
+                            .any(|condition| condition.matches(&pending.text));
+
+                        if is_matching {
+                            end.remove();
+                            elements_removed.store(true, Ordering::Relaxed);
+                        } else {
+                            end.before(
+                                &format!("{}{}", pending.start_tag, pending.text),
+                                ContentType::Html,
+                            );
+                        }
+
+                        Ok(())
+                    }));
+                }
+
+                Ok(())
+            }),
+        ));
+    }
+
+    document_handlers.push(doc_text!(move |chunk| {
+        if let Some(pending) = pending_element.lock().unwrap().as_mut() {
+            pending.text.push_str(chunk.as_str());
+            chunk.remove();
+        }
+
+        Ok(())
+    }));
+
+    (element_handlers, document_handlers)
+}
+
+fn compile(selector: &str) -> Option<HtmlFilter> {
+    let (css, condition) =
+        if let Some((css, argument)) = split_operator(selector, HAS_TEXT_OPERATOR) {
+            (css, Some(TextCondition::HasText(text_regex(argument)?)))
+        } else if let Some((css, argument)) = split_operator(selector, MIN_TEXT_LENGTH_OPERATOR) {
+            (
+                css,
+                Some(TextCondition::MinTextLength(argument.trim().parse().ok()?)),
+            )
+        } else {
+            (selector, None)
+        };
+
+    if condition.is_some() && !targets_raw_text_element(css) {
+        return None;
+    }
+
+    Some(HtmlFilter {
+        selector: css.parse().ok()?,
+        condition,
+    })
+}
+
+/// Splits `selector:operator(argument)` into its css part and the argument of the operator,
+/// which has to be the last part of the selector.
+fn split_operator<'a>(selector: &'a str, operator: &str) -> Option<(&'a str, &'a str)> {
+    let (css, rest) = selector.split_once(operator)?;
+    let argument = rest.strip_suffix(')')?;
+
+    Some((css, argument))
+}
+
+fn text_regex(argument: &str) -> Option<Regex> {
+    let argument = argument.trim();
+
+    if let Some((pattern, flags)) = argument
+        .strip_prefix('/')
+        .and_then(|argument| argument.rsplit_once('/'))
+    {
+        let flags = flags
+            .chars()
+            .filter(|flag| matches!(flag, 'i' | 'm' | 's'))
+            .collect::<String>();
+
+        return if flags.is_empty() {
+            Regex::new(pattern).ok()
+        } else {
+            Regex::new(&format!("(?{flags}){pattern}")).ok()
+        };
+    }
+
+    Regex::new(&regex::escape(argument)).ok()
+}
+
+fn targets_raw_text_element(css: &str) -> bool {
+    let last_compound = css
+        .rsplit(|c: char| c.is_whitespace() || matches!(c, '>' | '+' | '~'))
+        .next()
+        .unwrap_or_default();
+
+    let tag_name = last_compound
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    RAW_TEXT_ELEMENTS.contains(&tag_name.as_str())
+}
+
+/// Writes the start tag of `element` back. Attribute values are the raw ones, character
+/// references included, only quotes have to be escaped for values which were not double quoted.
+fn serialize_start_tag(element: &Element) -> String {
+    let mut start_tag = format!("<{}", element.tag_name_preserve_case());
+
+    for attribute in element.attributes() {
+        let value = attribute.value();
+
+        if value.is_empty() {
+            start_tag.push_str(&format!(" {}", attribute.name_preserve_case()));
+        } else {
+            start_tag.push_str(&format!(
+                r#" {}="{}""#,
+                attribute.name_preserve_case(),
+                value.replace('"', "&quot;")
+            ));
+        }
+    }
+
+    start_tag.push('>');
+    start_tag
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lol_html::{rewrite_str, RewriteStrSettings};
+
+    /// Rewrites `html` with the filters of `selectors`, returning it along with whether an element
+    /// was removed.
+    fn rewrite(selectors: &[&str], html: &str) -> (String, bool) {
+        let selectors: Vec<String> = selectors
+            .iter()
+            .map(|selector| selector.to_string())
+            .collect();
+        let elements_removed = Arc::new(AtomicBool::new(false));
+        let (element_content_handlers, document_content_handlers) =
+            build_handlers(&selectors, Arc::clone(&elements_removed));
+
+        let rewritten = rewrite_str(
+            html,
+            RewriteStrSettings {
+                element_content_handlers,
+                document_content_handlers,
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        (rewritten, elements_removed.load(Ordering::Relaxed))
+    }
+
+    #[test]
+    fn test_plain_filters() {
+        assert_eq!(
+            rewrite(
+                &[r#"script[src*="ads"]"#],
+                r#"<p>a</p><script src="/ads.js"></script><script src="/app.js"></script>"#
+            ),
+            (
+                r#"<p>a</p><script src="/app.js"></script>"#.to_string(),
+                true
+            )
+        );
+    }
+
+    #[test]
+    fn test_matching_conditions() {
+        let html = "<head><script>window.adsbygoogle = [];</script><script>app();</script></head>";
+        let expected = "<head><script>app();</script></head>".to_string();
+
+        assert_eq!(
+            rewrite(&["script:has-text(adsbygoogle)"], html),
+            (expected.clone(), true)
+        );
+        assert_eq!(
+            rewrite(&["script:has-text(/ADS?BY/i)"], html),
+            (expected.clone(), true)
+        );
+        assert_eq!(
+            rewrite(&["script:min-text-length(15)"], html),
+            (expected.clone(), true)
+        );
+        // Several filters matching the same element.
+        assert_eq!(
+            rewrite(
+                &["script:has-text(nothing)", "script:has-text(adsbygoogle)"],
+                html
+            ),
+            (expected, true)
+        );
+    }
+
+    #[test]
+    fn test_unmatched_conditions() {
+        let html = concat!(
+            "<!doctype html><html><head>",
+            r#"<SCRIPT async src="/app.js?a=1&amp;b=2" data-Value="1 < 2 &lt; 3">"#,
+            "if (a < b && c > d) { app(\"</p>\"); }",
+            "</SCRIPT >",
+            "<style media=\"print\">p > a { color: red; }</style>",
+            "</head><body><p>&amp;</p></body></html>"
+        );
+
+        assert_eq!(
+            rewrite(
+                &[
+                    "script:has-text(adsbygoogle)",
+                    "script:min-text-length(1000)",
+                    "style:has-text(banner)"
+                ],
+                html
+            ),
+            (html.to_string(), false)
+        );
+    }
+
+    #[test]
+    fn test_reemitted_attributes() {
+        // `&` and `<` are written back as they were, quotes of single quoted values are escaped.
+        assert_eq!(
+            rewrite(
+                &["script:has-text(adsbygoogle)"],
+                r#"<script data-a="a & b < c" data-b='say "hi"' defer>app();</script>"#
+            ),
+            (
+                r#"<script data-a="a & b < c" data-b="say &quot;hi&quot;" defer>app();</script>"#
+                    .to_string(),
+                false
+            )
+        );
+    }
+
+    #[test]
+    fn test_removed_elements_are_not_held_back() {
+        assert_eq!(
+            rewrite(
+                &["script:has-text(app)", "script[src]"],
+                r#"<script src="/ads.js">app();</script><script>app();</script><p>a</p>"#
+            ),
+            ("<p>a</p>".to_string(), true)
+        );
+    }
+
+    #[test]
+    fn test_unsupported_filters() {
+        assert!(compile("div:has-text(ad)").is_none());
+        assert!(compile("script:min-text-length(a)").is_none());
+        assert!(compile("script:has-text(/(/)").is_none());
+        assert!(compile("div > script:has-text(ad)").is_some());
+
+        let html = "<div>ad</div>";
+        assert_eq!(
+            rewrite(&["div:has-text(ad)"], html),
+            (html.to_string(), false)
+        );
+    }
+}
@@ -1,10 +1,17 @@
+use super::charset::{self, DocumentEncoding};
+use super::generic_cosmetic;
+use super::html_filters;
+use super::procedural_cosmetic;
+use super::subscribe;
 use crate::{blocker::AdblockRequester, statistics::Statistics};
 use crossbeam_channel::Receiver;
+use encoding_rs::Encoding;
 use hyper::body::Bytes;
-use lol_html::{element, HtmlRewriter, Settings};
+use lol_html::{element, AsciiCompatibleEncoding, HtmlRewriter, Settings};
 use regex::Regex;
 use std::collections::HashSet;
 use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc;
 
@@ -17,10 +24,13 @@ struct AdblockProperties {
     url: String,
     ids: HashSet<String>,
     classes: HashSet<String>,
+    elements_removed: bool,
 }
 
 pub struct Rewriter {
     url: String,
+    content_type: Option<String>,
+    html_filters: Vec<String>,
     adblock_requester: AdblockRequester,
     receiver: Receiver<Bytes>,
     body_sender: hyper::body::Sender,
@@ -31,6 +41,8 @@ pub struct Rewriter {
 impl Rewriter {
     pub(crate) fn new(
         url: String,
+        content_type: Option<String>,
+        html_filters: Vec<String>,
         adblock_requester: AdblockRequester,
         receiver: Receiver<Bytes>,
         body_sender: hyper::body::Sender,
@@ -38,6 +50,8 @@ impl Rewriter {
     ) -> Self {
         Self {
             url,
+            content_type,
+            html_filters,
             body_sender,
             statistics,
             adblock_requester,
@@ -52,18 +66,58 @@ impl Rewriter {
         let adblock_requester = self.adblock_requester.clone();
         let statistics = self.statistics.clone();
 
-        let internal_body_sender = Arc::new(Mutex::new(internal_body_sender));
+        let mut receiver = self.receiver.into_iter();
 
-        let classes = Arc::new(Mutex::new(HashSet::new()));
-        let ids = Arc::new(Mutex::new(HashSet::new()));
+        // The encoding is detected out of the start of the document, which has to be buffered
+        // before the rewriter can be set up.
+        let mut document_start = Vec::new();
+        while document_start.len() < charset::PRESCAN_LENGTH {
+            match receiver.next() {
+                Some(chunk) => document_start.extend_from_slice(&chunk),
+                None => break,
+            }
+        }
+
+        let encoding = match charset::detect(self.content_type.as_deref(), &document_start) {
+            DocumentEncoding::Detected(encoding) => AsciiCompatibleEncoding::new(encoding),
+            DocumentEncoding::Unknown => None,
+        };
 
         tokio::spawn(Self::write_body(
             internal_body_receiver,
             body_sender,
             adblock_requester,
             statistics,
+            encoding.map_or(encoding_rs::UTF_8, Into::into),
         ));
 
+        // lol_html is only able to process documents with an ascii compatible encoding. Others,
+        // such as UTF-16 documents, are passed through untouched rather than risking corrupting
+        // them.
+        let encoding = match encoding {
+            Some(encoding) => encoding,
+            None => {
+                log::debug!("Unsupported document encoding, not rewriting: {}", self.url);
+
+                let _ = internal_body_sender.send((Bytes::from(document_start), None));
+                for chunk in receiver {
+                    if internal_body_sender.send((chunk, None)).is_err() {
+                        break;
+                    }
+                }
+                return;
+            }
+        };
+
+        let internal_body_sender = Arc::new(Mutex::new(internal_body_sender));
+
+        let classes = Arc::new(Mutex::new(HashSet::new()));
+        let ids = Arc::new(Mutex::new(HashSet::new()));
+
+        let elements_removed = Arc::new(AtomicBool::new(false));
+        let (html_filters_handlers, html_filters_document_handlers) =
+            html_filters::build_handlers(&self.html_filters, Arc::clone(&elements_removed));
+
         let re = Regex::new(r"\s+").unwrap();
         let classes_clone = Arc::clone(&classes);
         let ids_clone = Arc::clone(&ids);
@@ -89,6 +143,15 @@ impl Rewriter {
                         }
                         Ok(())
                     }),
+                    element!(subscribe::SUBSCRIBE_LINKS_SELECTOR, |element| {
+                        if let Some(url) = element
+                            .get_attribute("href")
+                            .and_then(|href| subscribe::get_subscribe_page_url(&href))
+                        {
+                            element.set_attribute("href", &url)?;
+                        }
+                        Ok(())
+                    }),
                     element!("html, body", |element| {
                         if let Some(handlers) = element.end_tag_handlers() {
                             handlers.push(Box::new(move |end| {
@@ -98,7 +161,12 @@ impl Rewriter {
                         }
                         Ok(())
                     }),
-                ],
+                ]
+                .into_iter()
+                .chain(html_filters_handlers)
+                .collect(),
+                document_content_handlers: html_filters_document_handlers,
+                encoding,
                 ..Settings::default()
             },
             move |c: &[u8]| {
@@ -109,7 +177,8 @@ impl Rewriter {
             },
         );
 
-        for message in self.receiver {
+        rewriter.write(&document_start).unwrap();
+        for message in receiver {
             rewriter.write(&message).unwrap();
         }
         rewriter.end().unwrap();
@@ -120,6 +189,7 @@ impl Rewriter {
                 ids: ids.lock().unwrap().clone(),
                 classes: classes.lock().unwrap().clone(),
                 url: self.url,
+                elements_removed: elements_removed.load(Ordering::Relaxed),
             }),
         ));
     }
@@ -129,19 +199,20 @@ impl Rewriter {
         mut body_sender: hyper::body::Sender,
         adblock_requester: AdblockRequester,
         statistics: Statistics,
+        encoding: &'static Encoding,
     ) {
         while let Some((bytes, adblock_properties)) = receiver.recv().await {
             if let Err(_err) = body_sender.send_data(bytes).await {
                 break;
             }
             if let Some(adblock_properties) = adblock_properties {
-                let mut response_has_been_modified = false;
+                let mut response_has_been_modified = adblock_properties.elements_removed;
 
                 let blocker_result = adblock_requester
                     .get_cosmetic_response(
                         adblock_properties.url,
-                        adblock_properties.ids.into_iter().collect(),
-                        adblock_properties.classes.into_iter().collect(),
+                        adblock_properties.ids.iter().cloned().collect(),
+                        adblock_properties.classes.iter().cloned().collect(),
                     )
                     .await;
 
@@ -183,11 +254,46 @@ impl Rewriter {
                     .unwrap();
                 }
 
+                if !blocker_result.procedural_selectors.is_empty() {
+                    response_has_been_modified = true;
+                    write!(
+                        to_append_to_response,
+                        r#"
+<!-- Privaxy proxy -->
+<script type="application/javascript">{}</script>
+<!-- privaxy proxy -->
+"#,
+                        procedural_cosmetic::runtime_script(&blocker_result.procedural_selectors)
+                    )
+                    .unwrap();
+                }
+
+                // Elements inserted by scripts later on are handled by the generic cosmetic
+                // filtering runtime.
+                if !blocker_result.generichide {
+                    write!(
+                        to_append_to_response,
+                        r#"
+<!-- Privaxy proxy -->
+<script type="application/javascript">{}</script>
+<!-- privaxy proxy -->
+"#,
+                        generic_cosmetic::runtime_script(
+                            &adblock_properties.ids,
+                            &adblock_properties.classes
+                        )
+                    )
+                    .unwrap();
+                }
+
                 if response_has_been_modified {
                     statistics.increment_modified_responses();
                 }
 
-                let bytes = Bytes::copy_from_slice(to_append_to_response.as_bytes());
+                // What we append has to match the encoding of the rest of the document.
+                let (encoded, _encoding, _had_unmappable_characters) =
+                    encoding.encode(&to_append_to_response);
+                let bytes = Bytes::copy_from_slice(&encoded);
 
                 if let Err(_err) = body_sender.send_data(bytes).await {
                     break;
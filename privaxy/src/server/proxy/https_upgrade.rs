@@ -0,0 +1,181 @@
+//! Upgrade of plain HTTP requests to HTTPS.
+//!
+//! Navigations are redirected to their `https://` equivalent, so that browsers know the page
+//! they display is secure, while subresources are fetched over TLS transparently. Hosts that
+//! fail to answer over HTTPS are remembered and reached over plain HTTP for a while, unless
+//! they are in the HSTS preload list.
+use crate::blocker::AdblockRequester;
+use crate::configuration::HttpsUpgradeConfig;
+use http::uri::{Scheme, Uri};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Time given to a host to answer over HTTPS before a navigation falls back to plain HTTP.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of hosts remembered, either way, before starting over.
+const MAX_REMEMBERED_HOSTS: usize = 10_000;
+
+#[derive(Debug)]
+pub(crate) enum Upgrade {
+    /// The request goes over plain HTTP.
+    None,
+    /// The navigation is redirected to its HTTPS equivalent.
+    Redirect(Uri),
+    /// The subresource is fetched over HTTPS, plain HTTP being used instead when it fails and
+    /// `may_fall_back` is set.
+    Fetch { https_uri: Uri, may_fall_back: bool },
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct HttpsUpgrader {
+    config: HttpsUpgradeConfig,
+    /// Hosts known to answer over HTTPS.
+    secure_hosts: Mutex<HashSet<String>>,
+    /// Hosts that failed to answer over HTTPS, with when they last did.
+    failed_hosts: Mutex<HashMap<String, Instant>>,
+}
+
+impl HttpsUpgrader {
+    pub(crate) fn new(config: HttpsUpgradeConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Returns how a request to `uri` is to be upgraded.
+    pub(crate) async fn upgrade(
+        &self,
+        uri: &Uri,
+        is_navigation: bool,
+        adblock_requester: &AdblockRequester,
+        client: &reqwest::Client,
+    ) -> Upgrade {
+        if !self.config.enabled || uri.scheme() != Some(&Scheme::HTTP) {
+            return Upgrade::None;
+        }
+
+        let (host, https_uri) = match (uri.host(), https_uri(uri)) {
+            (Some(host), Some(https_uri)) => (host.to_ascii_lowercase(), https_uri),
+            _ => return Upgrade::None,
+        };
+
+        let is_preloaded = adblock_requester.is_hsts_preloaded(host.clone()).await;
+
+        if !is_preloaded && self.has_failed_recently(&host) {
+            return Upgrade::None;
+        }
+
+        if !is_navigation {
+            return Upgrade::Fetch {
+                https_uri,
+                may_fall_back: !is_preloaded,
+            };
+        }
+
+        // Browsers can't fall back to plain HTTP once redirected, hosts have to be known to
+        // answer over HTTPS first.
+        if is_preloaded
+            || self.secure_hosts.lock().unwrap().contains(&host)
+            || self.probe(&host, &https_uri, client).await
+        {
+            Upgrade::Redirect(https_uri)
+        } else {
+            Upgrade::None
+        }
+    }
+
+    pub(crate) fn record_success(&self, host: &str) {
+        let mut secure_hosts = self.secure_hosts.lock().unwrap();
+        if secure_hosts.len() >= MAX_REMEMBERED_HOSTS {
+            secure_hosts.clear();
+        }
+        secure_hosts.insert(host.to_ascii_lowercase());
+    }
+
+    pub(crate) fn record_failure(&self, host: &str) {
+        let host = host.to_ascii_lowercase();
+
+        log::debug!("HTTPS is not available for {}, using plain HTTP", host);
+
+        self.secure_hosts.lock().unwrap().remove(&host);
+
+        let mut failed_hosts = self.failed_hosts.lock().unwrap();
+        if failed_hosts.len() >= MAX_REMEMBERED_HOSTS {
+            failed_hosts.clear();
+        }
+        failed_hosts.insert(host, Instant::now());
+    }
+
+    /// Sites redirecting from HTTPS back to plain HTTP would otherwise end up in a redirect
+    /// loop. `uri` is a request made over HTTPS, `location` where its response redirects to.
+    pub(crate) fn check_redirect(&self, uri: &Uri, location: &str) {
+        if !self.config.enabled {
+            return;
+        }
+
+        let (host, location) = match (uri.host(), location.parse::<Uri>()) {
+            (Some(host), Ok(location)) => (host, location),
+            _ => return,
+        };
+
+        if location.scheme() == Some(&Scheme::HTTP)
+            && location
+                .host()
+                .map(|location_host| location_host.eq_ignore_ascii_case(host))
+                .unwrap_or(false)
+        {
+            self.record_failure(host);
+        }
+    }
+
+    fn has_failed_recently(&self, host: &str) -> bool {
+        let failure_memory = Duration::from_secs(self.config.failure_memory_secs);
+
+        self.failed_hosts
+            .lock()
+            .unwrap()
+            .get(host)
+            .map(|failed_at| failed_at.elapsed() < failure_memory)
+            .unwrap_or(false)
+    }
+
+    async fn probe(&self, host: &str, https_uri: &Uri, client: &reqwest::Client) -> bool {
+        match client
+            .head(https_uri.to_string())
+            .timeout(PROBE_TIMEOUT)
+            .send()
+            .await
+        {
+            Ok(_response) => {
+                self.record_success(host);
+                true
+            }
+            Err(_err) => {
+                self.record_failure(host);
+                false
+            }
+        }
+    }
+}
+
+/// Returns the `https://` equivalent of `uri`. Urls with a port other than 80 are not upgraded,
+/// as there is no telling which port HTTPS would be served on.
+fn https_uri(uri: &Uri) -> Option<Uri> {
+    if uri.port_u16().map(|port| port != 80).unwrap_or(false) {
+        return None;
+    }
+
+    Uri::builder()
+        .scheme(Scheme::HTTPS)
+        .authority(uri.host()?)
+        .path_and_query(
+            uri.path_and_query()
+                .map(|path_and_query| path_and_query.as_str())
+                .unwrap_or("/"),
+        )
+        .build()
+        .ok()
+}
@@ -1,26 +1,19 @@
-use super::{exclusions::LocalExclusionStore, serve::serve};
-use crate::{blocker::AdblockRequester, cert::CertCache, statistics::Statistics, Event};
+use super::{serve::serve, state::ProxyState};
+use crate::cert::ALPN_H2;
 use http::uri::{Authority, Scheme};
 use hyper::{
-    client::HttpConnector, http, server::conn::Http, service::service_fn, upgrade::Upgraded, Body,
-    Method, Request, Response,
+    http, server::conn::Http, service::service_fn, upgrade::Upgraded, Body, Method, Request,
+    Response,
 };
-use hyper_rustls::HttpsConnector;
 use std::{net::IpAddr, sync::Arc};
-use tokio::{net::TcpStream, sync::broadcast};
+use tokio::net::TcpStream;
 use tokio_rustls::TlsAcceptor;
 
-#[allow(clippy::too_many_arguments)]
 pub(crate) async fn serve_mitm_session(
-    adblock_requester: AdblockRequester,
-    hyper_client: hyper::Client<HttpsConnector<HttpConnector>>,
-    client: reqwest::Client,
+    proxy_state: Arc<ProxyState>,
     req: Request<Body>,
-    cert_cache: CertCache,
-    broadcast_tx: broadcast::Sender<Event>,
-    statistics: Statistics,
     client_ip_address: IpAddr,
-    local_exclusion_store: LocalExclusionStore,
+    web_gui_url: Arc<str>,
 ) -> Result<Response<Body>, hyper::Error> {
     let authority = match req.uri().authority().cloned() {
         Some(authority) => authority,
@@ -44,13 +37,19 @@ pub(crate) async fn serve_mitm_session(
         //
         // When HTTP method is CONNECT we should return an empty body
         // then we can eventually upgrade the connection and talk a new protocol.
-        let server_configuration =
-            Arc::new(cert_cache.get(authority.clone()).await.server_configuration);
+        let server_configuration = Arc::new(
+            proxy_state
+                .cert_cache
+                .get(authority.clone())
+                .await
+                .server_configuration,
+        );
 
         tokio::task::spawn(async move {
             match hyper::upgrade::on(req).await {
                 Ok(mut upgraded) => {
-                    let is_host_blacklisted = local_exclusion_store.contains(authority.host());
+                    let is_host_blacklisted =
+                        proxy_state.local_exclusion_store.contains(authority.host());
 
                     if is_host_blacklisted {
                         let _result = tunnel(&mut upgraded, &authority).await;
@@ -58,27 +57,32 @@ pub(crate) async fn serve_mitm_session(
                         return;
                     }
 
-                    let http = Http::new();
+                    let mut http = Http::new();
 
                     match TlsAcceptor::from(server_configuration)
                         .accept(upgraded)
                         .await
                     {
                         Ok(tls_stream) => {
+                            // Serve the protocol negotiated through ALPN. Over HTTP/2, websockets
+                            // are bootstrapped using extended CONNECT requests (RFC 8441).
+                            if tls_stream.get_ref().1.alpn_protocol() == Some(ALPN_H2) {
+                                http.http2_only(true).http2_enable_connect_protocol();
+                            } else {
+                                http.http1_only(true);
+                            }
+
                             let _result = http
                                 .serve_connection(
                                     tls_stream,
                                     service_fn(move |req| {
                                         serve(
-                                            adblock_requester.clone(),
+                                            proxy_state.clone(),
                                             req,
-                                            hyper_client.clone(),
-                                            client.clone(),
                                             authority.clone(),
                                             Scheme::HTTPS,
-                                            broadcast_tx.clone(),
-                                            statistics.clone(),
                                             client_ip_address,
+                                            web_gui_url.clone(),
                                         )
                                     }),
                                 )
@@ -105,15 +109,12 @@ pub(crate) async fn serve_mitm_session(
         // The request is not of method `CONNECT`. Therefore,
         // this request is for an HTTP resource.
         serve(
-            adblock_requester,
+            proxy_state,
             req,
-            hyper_client.clone(),
-            client.clone(),
             authority,
             Scheme::HTTP,
-            broadcast_tx,
-            statistics,
             client_ip_address,
+            web_gui_url,
         )
         .await
     }
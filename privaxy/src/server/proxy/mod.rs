@@ -1,5 +1,19 @@
 pub(crate) mod mitm;
 pub(crate) mod serve;
+pub(crate) use https_upgrade::HttpsUpgrader;
 pub(crate) use mitm::serve_mitm_session;
+pub(crate) use privacy::{PrivacyAction, PrivacyPolicy};
+pub(crate) use state::ProxyState;
+mod body_rewriter;
+mod charset;
+mod compression;
+mod endpoints;
 pub(crate) mod exclusions;
+mod generic_cosmetic;
+mod html_filters;
 pub(crate) mod html_rewriter;
+mod https_upgrade;
+mod privacy;
+mod procedural_cosmetic;
+mod state;
+mod subscribe;
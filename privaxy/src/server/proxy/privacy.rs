@@ -0,0 +1,482 @@
+//! Header hardening applied to proxied requests and their responses, following the `[privacy]`
+//! section of the configuration.
+use super::serve::get_request_type;
+use crate::configuration::PrivacyConfig;
+use crate::statistics::Statistics;
+use http::header::HeaderValue;
+use http::{HeaderMap, Uri};
+
+/// Headers browsers or carriers add to identify users.
+const TRACKING_HEADERS: &[&str] = &[
+    "x-acr",
+    "x-amobee-1",
+    "x-att-deviceid",
+    "x-client-data",
+    "x-msisdn",
+    "x-nokia-msisdn",
+    "x-uidh",
+    "x-up-subno",
+    "x-vf-acr",
+];
+
+/// Client hints revealing the actual browser, which would defeat `User-Agent` normalization.
+const HIGH_ENTROPY_CLIENT_HINTS: &[&str] = &[
+    "sec-ch-ua",
+    "sec-ch-ua-arch",
+    "sec-ch-ua-bitness",
+    "sec-ch-ua-full-version",
+    "sec-ch-ua-full-version-list",
+    "sec-ch-ua-model",
+    "sec-ch-ua-platform",
+    "sec-ch-ua-platform-version",
+    "sec-ch-ua-wow64",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PrivacyAction {
+    RefererTrimmed,
+    GlobalPrivacyControlSent,
+    DoNotTrackSent,
+    TrackingHeaderRemoved,
+    UserAgentNormalized,
+    AcceptLanguageNormalized,
+    ThirdPartyCookieBlocked,
+}
+
+impl PrivacyAction {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::RefererTrimmed => "referer_trimmed",
+            Self::GlobalPrivacyControlSent => "global_privacy_control_sent",
+            Self::DoNotTrackSent => "do_not_track_sent",
+            Self::TrackingHeaderRemoved => "tracking_header_removed",
+            Self::UserAgentNormalized => "user_agent_normalized",
+            Self::AcceptLanguageNormalized => "accept_language_normalized",
+            Self::ThirdPartyCookieBlocked => "third_party_cookie_blocked",
+        }
+    }
+}
+
+/// What was found out about a request, to harden its response accordingly.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct RequestContext {
+    is_exempted: bool,
+    is_third_party: bool,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PrivacyPolicy {
+    config: PrivacyConfig,
+    user_agent: Option<HeaderValue>,
+    accept_language: Option<HeaderValue>,
+}
+
+impl PrivacyPolicy {
+    pub(crate) fn new(config: PrivacyConfig) -> Self {
+        let header_value = |name: &str, value: &Option<String>| {
+            let value = value.as_deref()?;
+
+            match HeaderValue::from_str(value) {
+                Ok(value) => Some(value),
+                Err(_err) => {
+                    log::warn!("Ignoring invalid {} privacy setting: {}", name, value);
+                    None
+                }
+            }
+        };
+
+        Self {
+            user_agent: header_value("user_agent", &config.user_agent),
+            accept_language: header_value("accept_language", &config.accept_language),
+            config,
+        }
+    }
+
+    /// Hardens the headers of a request to `uri` before it is forwarded.
+    pub(crate) fn harden_request(
+        &self,
+        uri: &Uri,
+        headers: &mut HeaderMap,
+        statistics: &Statistics,
+    ) -> RequestContext {
+        let host = uri.host().unwrap_or_default().to_ascii_lowercase();
+        let initiator = initiator_host(headers);
+
+        let is_exempted = self.is_exempted(&host)
+            || initiator
+                .as_deref()
+                .map(|initiator| self.is_exempted(initiator))
+                .unwrap_or(false);
+
+        if is_exempted {
+            return RequestContext {
+                is_exempted,
+                is_third_party: false,
+            };
+        }
+
+        let is_cross_site = is_cross_site(headers, &host, initiator.as_deref());
+        // Navigating to another site makes it the first party, unlike embedding it in a frame.
+        let is_third_party = is_cross_site && !is_navigation(headers);
+
+        let record = |action: PrivacyAction| statistics.increment_privacy_action(action);
+
+        if self.config.trim_cross_site_referer && is_cross_site {
+            if let Some(origin) = headers
+                .get(http::header::REFERER)
+                .and_then(|referer| referer.to_str().ok())
+                .and_then(referer_origin)
+            {
+                headers.insert(http::header::REFERER, origin);
+                record(PrivacyAction::RefererTrimmed);
+            }
+        }
+
+        if self.config.global_privacy_control {
+            headers.insert("sec-gpc", HeaderValue::from_static("1"));
+            record(PrivacyAction::GlobalPrivacyControlSent);
+        }
+
+        if self.config.do_not_track {
+            headers.insert("dnt", HeaderValue::from_static("1"));
+            record(PrivacyAction::DoNotTrackSent);
+        }
+
+        if self.config.strip_tracking_headers {
+            for header in TRACKING_HEADERS {
+                if headers.remove(*header).is_some() {
+                    record(PrivacyAction::TrackingHeaderRemoved);
+                }
+            }
+        }
+
+        if let Some(user_agent) = &self.user_agent {
+            headers.insert(http::header::USER_AGENT, user_agent.clone());
+            for header in HIGH_ENTROPY_CLIENT_HINTS {
+                headers.remove(*header);
+            }
+            record(PrivacyAction::UserAgentNormalized);
+        }
+
+        if let Some(accept_language) = &self.accept_language {
+            headers.insert(http::header::ACCEPT_LANGUAGE, accept_language.clone());
+            record(PrivacyAction::AcceptLanguageNormalized);
+        }
+
+        if self.config.block_third_party_cookies
+            && is_third_party
+            && headers.remove(http::header::COOKIE).is_some()
+        {
+            record(PrivacyAction::ThirdPartyCookieBlocked);
+        }
+
+        RequestContext {
+            is_exempted,
+            is_third_party,
+        }
+    }
+
+    /// Hardens the headers of the response to a request hardened with
+    /// [`PrivacyPolicy::harden_request`].
+    pub(crate) fn harden_response(
+        &self,
+        context: RequestContext,
+        headers: &mut HeaderMap,
+        statistics: &Statistics,
+    ) {
+        if context.is_exempted {
+            return;
+        }
+
+        if self.config.block_third_party_cookies
+            && context.is_third_party
+            && headers.contains_key(http::header::SET_COOKIE)
+        {
+            headers.remove(http::header::SET_COOKIE);
+            statistics.increment_privacy_action(PrivacyAction::ThirdPartyCookieBlocked);
+        }
+    }
+
+    fn is_exempted(&self, host: &str) -> bool {
+        self.config.exemptions.iter().any(|exemption| {
+            host == exemption
+                || host
+                    .strip_suffix(exemption.as_str())
+                    .map(|subdomain| subdomain.ends_with('.'))
+                    .unwrap_or(false)
+        })
+    }
+}
+
+/// Returns the host of the page a request comes from.
+fn initiator_host(headers: &HeaderMap) -> Option<String> {
+    [http::header::ORIGIN, http::header::REFERER]
+        .iter()
+        .filter_map(|header| headers.get(header)?.to_str().ok())
+        .find_map(|value| {
+            url::Url::parse(value)
+                .ok()?
+                .host_str()
+                .map(str::to_ascii_lowercase)
+        })
+}
+
+/// Browsers tell whether a request is cross-site through `Sec-Fetch-Site`, otherwise the sites
+/// of the request and of its initiator are compared.
+fn is_cross_site(headers: &HeaderMap, host: &str, initiator: Option<&str>) -> bool {
+    if let Some(sec_fetch_site) = headers
+        .get("sec-fetch-site")
+        .and_then(|value| value.to_str().ok())
+    {
+        return sec_fetch_site == "cross-site";
+    }
+
+    match initiator {
+        Some(initiator) => registrable_domain(initiator) != registrable_domain(host),
+        None => false,
+    }
+}
+
+/// Frames are navigated to as well, only top-level documents are navigations here.
+fn is_navigation(headers: &HeaderMap) -> bool {
+    get_request_type(headers) == "document"
+}
+
+fn registrable_domain(host: &str) -> &str {
+    psl::domain_str(host).unwrap_or(host)
+}
+
+/// Returns the origin of `referer`, unless it already is one.
+fn referer_origin(referer: &str) -> Option<HeaderValue> {
+    let url = url::Url::parse(referer).ok()?;
+    let origin = format!("{}/", url.origin().ascii_serialization());
+
+    if origin == referer || url.origin().ascii_serialization() == "null" {
+        return None;
+    }
+
+    HeaderValue::from_str(&origin).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    http::header::HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    fn cookie_policy() -> PrivacyPolicy {
+        PrivacyPolicy::new(PrivacyConfig {
+            block_third_party_cookies: true,
+            ..Default::default()
+        })
+    }
+
+    /// Hardens a request to `uri` and its response, which sets a cookie, returning whether the
+    /// cookies were left alone.
+    fn are_cookies_kept(
+        policy: &PrivacyPolicy,
+        uri: &'static str,
+        pairs: &[(&'static str, &'static str)],
+    ) -> bool {
+        let statistics = Statistics::new();
+        let mut request_headers = headers(pairs);
+        request_headers.insert(http::header::COOKIE, HeaderValue::from_static("id=1"));
+
+        let context =
+            policy.harden_request(&Uri::from_static(uri), &mut request_headers, &statistics);
+
+        let mut response_headers = headers(&[("set-cookie", "id=2")]);
+        policy.harden_response(context, &mut response_headers, &statistics);
+
+        let is_cookie_kept = request_headers.contains_key(http::header::COOKIE);
+        assert_eq!(
+            is_cookie_kept,
+            response_headers.contains_key(http::header::SET_COOKIE)
+        );
+
+        is_cookie_kept
+    }
+
+    #[test]
+    fn test_is_exempted() {
+        let policy = PrivacyPolicy::new(PrivacyConfig {
+            exemptions: ["example.com".to_string()].into(),
+            ..Default::default()
+        });
+
+        assert!(policy.is_exempted("example.com"));
+        assert!(policy.is_exempted("www.example.com"));
+        assert!(policy.is_exempted("a.b.example.com"));
+        assert!(!policy.is_exempted("badexample.com"));
+        assert!(!policy.is_exempted("example.com.evil.net"));
+        assert!(!policy.is_exempted("com"));
+    }
+
+    #[test]
+    fn test_is_cross_site() {
+        let cross_site = headers(&[("sec-fetch-site", "cross-site")]);
+        let same_site = headers(&[("sec-fetch-site", "same-site")]);
+        let none = HeaderMap::new();
+
+        // `Sec-Fetch-Site` wins over the initiator.
+        assert!(is_cross_site(&cross_site, "a.com", Some("a.com")));
+        assert!(!is_cross_site(&same_site, "a.com", Some("b.com")));
+
+        assert!(is_cross_site(&none, "tracker.net", Some("www.example.com")));
+        assert!(!is_cross_site(
+            &none,
+            "cdn.example.com",
+            Some("www.example.com")
+        ));
+        assert!(is_cross_site(&none, "a.github.io", Some("b.github.io")));
+        assert!(!is_cross_site(&none, "tracker.net", None));
+    }
+
+    #[test]
+    fn test_initiator_host() {
+        assert_eq!(
+            initiator_host(&headers(&[
+                ("origin", "https://Example.com"),
+                ("referer", "https://other.com/page")
+            ])),
+            Some("example.com".to_string())
+        );
+        assert_eq!(
+            initiator_host(&headers(&[
+                ("origin", "null"),
+                ("referer", "https://other.com/page")
+            ])),
+            Some("other.com".to_string())
+        );
+        assert_eq!(initiator_host(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_referer_origin() {
+        assert_eq!(
+            referer_origin("https://example.com/path?query=1"),
+            Some(HeaderValue::from_static("https://example.com/"))
+        );
+        assert_eq!(
+            referer_origin("http://example.com:8080/path"),
+            Some(HeaderValue::from_static("http://example.com:8080/"))
+        );
+        assert_eq!(referer_origin("https://example.com/"), None);
+        assert_eq!(referer_origin("data:text/plain,a"), None);
+        assert_eq!(referer_origin("not a url"), None);
+    }
+
+    #[test]
+    fn test_third_party_cookies() {
+        let policy = cookie_policy();
+
+        assert!(!are_cookies_kept(
+            &policy,
+            "https://tracker.net/pixel",
+            &[
+                ("sec-fetch-site", "cross-site"),
+                ("sec-fetch-mode", "no-cors"),
+                ("sec-fetch-dest", "image")
+            ]
+        ));
+        assert!(!are_cookies_kept(
+            &policy,
+            "https://tracker.net/pixel",
+            &[("referer", "https://example.com/")]
+        ));
+        assert!(are_cookies_kept(
+            &policy,
+            "https://cdn.example.com/app.js",
+            &[
+                ("sec-fetch-site", "same-site"),
+                ("sec-fetch-dest", "script")
+            ]
+        ));
+        assert!(are_cookies_kept(
+            &policy,
+            "https://other.com/",
+            &[
+                ("sec-fetch-site", "cross-site"),
+                ("sec-fetch-mode", "navigate"),
+                ("sec-fetch-dest", "document")
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_third_party_frame_cookies() {
+        assert!(!are_cookies_kept(
+            &cookie_policy(),
+            "https://tracker.net/frame",
+            &[
+                ("sec-fetch-site", "cross-site"),
+                ("sec-fetch-mode", "navigate"),
+                ("sec-fetch-dest", "iframe")
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_exempted_cookies() {
+        let policy = PrivacyPolicy::new(PrivacyConfig {
+            block_third_party_cookies: true,
+            exemptions: ["example.com".to_string()].into(),
+            ..Default::default()
+        });
+
+        assert!(are_cookies_kept(
+            &policy,
+            "https://tracker.net/pixel",
+            &[
+                ("sec-fetch-site", "cross-site"),
+                ("referer", "https://www.example.com/")
+            ]
+        ));
+        assert!(are_cookies_kept(
+            &policy,
+            "https://login.example.com/",
+            &[("sec-fetch-site", "cross-site")]
+        ));
+    }
+
+    #[test]
+    fn test_referer_trimming() {
+        let policy = PrivacyPolicy::new(PrivacyConfig {
+            trim_cross_site_referer: true,
+            ..Default::default()
+        });
+        let statistics = Statistics::new();
+
+        let mut cross_site = headers(&[
+            ("sec-fetch-site", "cross-site"),
+            ("referer", "https://example.com/private/page"),
+        ]);
+        policy.harden_request(
+            &Uri::from_static("https://tracker.net/"),
+            &mut cross_site,
+            &statistics,
+        );
+        assert_eq!(cross_site["referer"], "https://example.com/");
+
+        let mut same_site = headers(&[
+            ("sec-fetch-site", "same-origin"),
+            ("referer", "https://example.com/private/page"),
+        ]);
+        policy.harden_request(
+            &Uri::from_static("https://example.com/api"),
+            &mut same_site,
+            &statistics,
+        );
+        assert_eq!(same_site["referer"], "https://example.com/private/page");
+    }
+}
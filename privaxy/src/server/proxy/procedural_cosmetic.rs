@@ -0,0 +1,100 @@
+//! Procedural cosmetic filtering. Procedural filters can't be expressed with CSS, they are
+//! evaluated by a script injected into pages, which reports the filters it applied back to an
+//! endpoint the proxy answers itself.
+use super::endpoints::{get_response, read_body};
+use crate::statistics::Statistics;
+use http::{Method, StatusCode};
+use hyper::{Body, Request, Response};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::sync::Mutex;
+use uluru::LRUCache;
+
+/// Path intercepted by the proxy on every origin, requests to it never reach servers.
+pub(crate) const ENDPOINT_PATH: &str = "/__privaxy__/procedural-filters-applied";
+
+const MAX_REQUEST_BODY_LENGTH: u64 = 1024;
+
+/// Pages the runtime got injected into and which may still report applied filters, along with the
+/// number of filters they can still report. Only the most recent pages are remembered.
+static REPORTING_PAGES: Lazy<Mutex<LRUCache<ReportingPage, 1_000>>> =
+    Lazy::new(|| Mutex::new(LRUCache::default()));
+
+struct ReportingPage {
+    nonce: String,
+    remaining: u64,
+}
+
+#[derive(Deserialize)]
+struct AppliedReport {
+    nonce: String,
+    applied: u64,
+}
+
+pub(crate) fn is_endpoint_request(request: &Request<Body>) -> bool {
+    request.uri().path() == ENDPOINT_PATH
+}
+
+/// Returns the runtime script for a page given `selectors`. Reports are only counted for the
+/// nonce the script is issued with, and up to the number of selectors it got.
+pub(crate) fn runtime_script(selectors: &[String]) -> String {
+    let nonce = get_nonce();
+
+    REPORTING_PAGES.lock().unwrap().insert(ReportingPage {
+        nonce: nonce.clone(),
+        remaining: selectors.len() as u64,
+    });
+
+    // Selectors come from filter lists, they must not be able to close the script element.
+    let selectors = serde_json::to_string(selectors)
+        .unwrap()
+        .replace('<', "\\u003c")
+        .replace('>', "\\u003e");
+
+    include_str!("../../resources/procedural_cosmetic_filtering.js")
+        .replace("#{endpoint}#", ENDPOINT_PATH)
+        .replace("#{nonce}#", &nonce)
+        .replace("#{selectors}#", &selectors)
+}
+
+fn get_nonce() -> String {
+    let mut bytes = [0; 16];
+    openssl::rand::rand_bytes(&mut bytes).unwrap();
+
+    hex::encode(bytes)
+}
+
+pub(crate) async fn serve(request: Request<Body>, statistics: Statistics) -> Response<Body> {
+    if request.method() != Method::POST {
+        return get_response(StatusCode::METHOD_NOT_ALLOWED, Body::empty());
+    }
+
+    let body = match read_body(request.into_body(), MAX_REQUEST_BODY_LENGTH).await {
+        Ok(body) => body,
+        Err(status_code) => return get_response(status_code, Body::empty()),
+    };
+
+    let report = match serde_json::from_slice::<AppliedReport>(&body) {
+        Ok(report) => report,
+        Err(_err) => return get_response(StatusCode::BAD_REQUEST, Body::empty()),
+    };
+
+    let applied = REPORTING_PAGES
+        .lock()
+        .unwrap()
+        .find(|page| page.nonce == report.nonce)
+        .map(|page| {
+            let applied = report.applied.min(page.remaining);
+            page.remaining -= applied;
+            applied
+        });
+
+    match applied {
+        Some(applied) => {
+            statistics.add_procedural_filters_applied(applied);
+
+            get_response(StatusCode::NO_CONTENT, Body::empty())
+        }
+        None => get_response(StatusCode::FORBIDDEN, Body::empty()),
+    }
+}
@@ -1,29 +1,45 @@
+use super::body_rewriter::{self, BodyRewriter};
+use super::compression::{self, ContentEncoding};
+use super::generic_cosmetic;
 use super::html_rewriter::Rewriter;
-use crate::blocker::AdblockRequester;
-use crate::statistics::Statistics;
+use super::https_upgrade::Upgrade;
+use super::procedural_cosmetic;
+use super::state::ProxyState;
+use super::subscribe;
 use crate::web_gui::events::Event;
 use adblock::blocker::BlockerResult;
+use base64::{engine::general_purpose, Engine};
+use futures_util::{StreamExt, TryStreamExt};
+use http::header::HeaderValue;
 use http::uri::{Authority, Scheme};
-use http::{StatusCode, Uri};
+use http::{HeaderMap, Method, StatusCode, Uri, Version};
 use hyper::body::Bytes;
 use hyper::client::HttpConnector;
+use hyper::ext::Protocol;
 use hyper::{http, Body, Request, Response};
 use hyper_rustls::HttpsConnector;
 use std::net::IpAddr;
-use tokio::sync::broadcast;
+use std::sync::Arc;
 
-#[allow(clippy::too_many_arguments)]
 pub(crate) async fn serve(
-    adblock_requester: AdblockRequester,
+    proxy_state: Arc<ProxyState>,
     request: Request<Body>,
-    hyper_client: hyper::Client<HttpsConnector<HttpConnector>>,
-    client: reqwest::Client,
     authority: Authority,
     scheme: Scheme,
-    broadcast_sender: broadcast::Sender<Event>,
-    statistics: Statistics,
     client_ip_address: IpAddr,
+    web_gui_url: Arc<str>,
 ) -> Result<Response<Body>, hyper::Error> {
+    let ProxyState {
+        adblock_requester,
+        hyper_client,
+        client,
+        broadcast_sender,
+        statistics,
+        body_rewriting,
+        privacy_policy,
+        https_upgrader,
+        ..
+    } = &*proxy_state;
     let scheme_string = scheme.to_string();
 
     let uri = match http::uri::Builder::new()
@@ -42,22 +58,38 @@ pub(crate) async fn serve(
     };
 
     if request.headers().contains_key(http::header::UPGRADE) {
-        return Ok(perform_two_ends_upgrade(request, uri, hyper_client).await);
+        return Ok(perform_two_ends_upgrade(request, uri, hyper_client.clone()).await);
+    }
+
+    if request.method() == Method::CONNECT && request.extensions().get::<Protocol>().is_some() {
+        return Ok(perform_extended_connect(request, uri, hyper_client.clone()).await);
+    }
+
+    if generic_cosmetic::is_endpoint_request(&request) {
+        return Ok(generic_cosmetic::serve(request, uri, adblock_requester.clone()).await);
+    }
+
+    if procedural_cosmetic::is_endpoint_request(&request) {
+        return Ok(procedural_cosmetic::serve(request, statistics.clone()).await);
+    }
+
+    if let Some(location) = subscribe::get_subscription_redirect(&request, &uri, &web_gui_url) {
+        if let Some(response) = get_redirect_response(&location) {
+            return Ok(response);
+        }
     }
 
     let (mut parts, body) = request.into_parts();
     parts.uri = uri.clone();
 
-    let (sender, new_body) = Body::channel();
-
     let req = Request::from_parts(parts, body);
 
     log::debug!("{} {}", req.method(), req.uri());
 
     statistics.increment_top_clients(client_ip_address);
 
-    let (is_request_blocked, blocker_result) = adblock_requester
-        .is_network_url_blocked(
+    let network_blocker_result = adblock_requester
+        .check_network_url(
             uri.to_string(),
             match req.headers().get(http::header::REFERER) {
                 Some(referer) => referer.to_str().unwrap().to_string(),
@@ -68,11 +100,15 @@ pub(crate) async fn serve(
         )
         .await;
 
+    let is_request_blocked = network_blocker_result.enforced.matched;
+    let would_be_blocked = network_blocker_result.observed.matched;
+
     let _result = broadcast_sender.send(Event {
         now: chrono::Utc::now(),
         method: req.method().to_string(),
         url: req.uri().to_string(),
         is_request_blocked,
+        would_be_blocked,
     });
 
     if is_request_blocked {
@@ -86,21 +122,115 @@ pub(crate) async fn serve(
 
         log::debug!("Blocked request: {}", uri);
 
-        return Ok(get_blocked_by_privaxy_response(blocker_result));
+        return Ok(get_blocked_by_privaxy_response(
+            network_blocker_result.enforced,
+        ));
+    }
+
+    if would_be_blocked {
+        statistics
+            .increment_would_be_blocked_requests(network_blocker_result.observed.filter.as_deref());
+
+        log::debug!("Request would be blocked: {}", uri);
+    }
+
+    let is_navigation =
+        req.method() == Method::GET && get_request_type(req.headers()) == "document";
+
+    // Navigations are redirected to their cleaned url, skipping the tracker hop of wrapped
+    // links altogether.
+    if is_navigation {
+        if let Some(cleaned_url) = adblock_requester.get_cleaned_url(uri.to_string()).await {
+            log::debug!("Cleaned url: {} -> {}", uri, cleaned_url.url());
+
+            if let Some(response) = get_redirect_response(cleaned_url.url()) {
+                return Ok(response);
+            }
+        }
     }
 
-    let mut new_response = Response::new(new_body);
+    // Only requests without a body can be sent again over plain HTTP.
+    let upgrade = if req.method() == Method::GET || req.method() == Method::HEAD {
+        https_upgrader
+            .upgrade(&uri, is_navigation, adblock_requester, client)
+            .await
+    } else {
+        Upgrade::None
+    };
+
+    let upstream_uri = match &upgrade {
+        Upgrade::Redirect(https_uri) => {
+            if let Some(response) = get_redirect_response(&https_uri.to_string()) {
+                return Ok(response);
+            }
+            uri.clone()
+        }
+        Upgrade::Fetch { https_uri, .. } => https_uri.clone(),
+        Upgrade::None => uri.clone(),
+    };
+
+    let response_rules = adblock_requester
+        .get_response_rules(
+            uri.to_string(),
+            match req.headers().get(http::header::REFERER) {
+                Some(referer) => referer.to_str().unwrap_or_default().to_string(),
+                None => uri.to_string(),
+            },
+            get_request_type(req.headers()).to_string(),
+        )
+        .await;
+
+    // Coding used for the bodies we rewrite, compressed bodies we don't touch are sent as is.
+    let client_encoding = ContentEncoding::negotiate(req.headers());
+    let is_head_request = req.method() == Method::HEAD;
 
     let mut request_headers = req.headers().clone();
     request_headers.remove(http::header::CONNECTION);
     request_headers.remove(http::header::HOST);
-    let mut response = match client
-        .request(req.method().clone(), req.uri().to_string())
-        .headers(request_headers)
+    if req.version() == Version::HTTP_2 {
+        merge_cookie_headers(&mut request_headers);
+    }
+    compression::restrict_accept_encoding(&mut request_headers);
+    for header in &response_rules.removed_request_headers {
+        request_headers.remove(header.as_str());
+    }
+    let privacy_context = privacy_policy.harden_request(&uri, &mut request_headers, statistics);
+
+    let method = req.method().clone();
+
+    let upstream_response = client
+        .request(method.clone(), upstream_uri.to_string())
+        .headers(request_headers.clone())
         .body(req.into_body())
         .send()
-        .await
-    {
+        .await;
+
+    let upstream_response = match (upstream_response, &upgrade) {
+        (
+            Err(_err),
+            Upgrade::Fetch {
+                may_fall_back: true,
+                ..
+            },
+        ) => {
+            https_upgrader.record_failure(uri.host().unwrap_or_default());
+
+            client
+                .request(method, uri.to_string())
+                .headers(request_headers)
+                .send()
+                .await
+        }
+        (upstream_response, Upgrade::Fetch { .. }) => {
+            if upstream_response.is_ok() {
+                https_upgrader.record_success(uri.host().unwrap_or_default());
+            }
+            upstream_response
+        }
+        (upstream_response, _) => upstream_response,
+    };
+
+    let response = match upstream_response {
         Ok(response) => response,
         Err(err) => {
             log::error!("Failed to send request: {}", err.to_string());
@@ -110,46 +240,205 @@ pub(crate) async fn serve(
 
     statistics.increment_proxied_requests();
 
+    if upstream_uri.scheme() == Some(&Scheme::HTTPS) && response.status().is_redirection() {
+        if let Some(location) = response
+            .headers()
+            .get(http::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+        {
+            https_upgrader.check_redirect(&upstream_uri, location);
+        }
+    }
+
+    let mut new_response = Response::new(Body::empty());
+    *new_response.status_mut() = response.status();
     *new_response.headers_mut() = response.headers().clone();
 
-    let (mut parts, new_new_body) = new_response.into_parts();
-    parts.status = response.status();
+    let headers = new_response.headers_mut();
+    for header in &response_rules.removed_response_headers {
+        headers.remove(header.as_str());
+    }
+    privacy_policy.harden_response(privacy_context, headers, statistics);
+    // Policies add up, the website's own policy is enforced as well.
+    if let Some(content_security_policy) = response_rules
+        .content_security_policy
+        .and_then(|policy| HeaderValue::from_str(&policy).ok())
+    {
+        headers.append(
+            http::header::CONTENT_SECURITY_POLICY,
+            content_security_policy,
+        );
+    }
 
-    let new_response = Response::from_parts(parts, new_new_body);
+    let content_type = response
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .map(String::from);
 
-    if let Some(content_type) = response.headers().get(http::header::CONTENT_TYPE) {
-        if let Ok(value) = content_type.to_str() {
-            if value.contains("text/html") {
-                let (sender_rewriter, receiver_rewriter) = crossbeam_channel::unbounded::<Bytes>();
+    let is_html = content_type
+        .as_deref()
+        .map(|content_type| content_type.contains("text/html"))
+        .unwrap_or(false);
 
-                let rewriter = Rewriter::new(
-                    uri.to_string(),
-                    adblock_requester,
-                    receiver_rewriter,
-                    sender,
-                    statistics,
-                );
+    let has_body_rules =
+        !response_rules.replace_rules.is_empty() || !response_rules.json_prune_rules.is_empty();
+
+    let is_rewritable_text = has_body_rules
+        && content_type
+            .as_deref()
+            .map(body_rewriter::is_rewritable_content_type)
+            .unwrap_or(false);
+
+    let has_body = !is_head_request
+        && response.status() != StatusCode::NO_CONTENT
+        && response.status() != StatusCode::NOT_MODIFIED;
+
+    match ContentEncoding::from_response_headers(response.headers()) {
+        // Documents using a coding we can't decode are passed through untouched.
+        Some(upstream_encoding) if is_html && has_body => {
+            set_rewritten_body_headers(new_response.headers_mut(), client_encoding);
+
+            let (sender, rewritten_body) = Body::channel();
+            let (sender_rewriter, receiver_rewriter) = crossbeam_channel::unbounded::<Bytes>();
+
+            let html_filters = adblock_requester.get_html_filters(uri.to_string()).await;
 
-                tokio::task::spawn_blocking(|| rewriter.rewrite());
+            let rewriter = Rewriter::new(
+                uri.to_string(),
+                content_type,
+                html_filters,
+                adblock_requester.clone(),
+                receiver_rewriter,
+                sender,
+                statistics.clone(),
+            );
 
-                while let Ok(Some(chunk)) = response.chunk().await {
+            tokio::task::spawn_blocking(|| rewriter.rewrite());
+
+            tokio::spawn(async move {
+                let mut decoded_body = compression::decode(
+                    response.bytes_stream().map_err(std::io::Error::other),
+                    upstream_encoding,
+                );
+
+                while let Some(Ok(chunk)) = decoded_body.next().await {
                     if let Err(_err) = sender_rewriter.send(chunk) {
                         break;
                     }
                 }
+            });
 
-                return Ok(new_response);
-            }
+            *new_response.body_mut() = compression::encode(rewritten_body, client_encoding);
+        }
+        Some(upstream_encoding) if is_rewritable_text && has_body => {
+            set_rewritten_body_headers(new_response.headers_mut(), client_encoding);
+
+            let (sender, rewritten_body) = Body::channel();
+
+            let body_rewriter = BodyRewriter::new(
+                content_type,
+                response_rules.replace_rules,
+                response_rules.json_prune_rules,
+                *body_rewriting,
+                statistics.clone(),
+            );
+
+            let decoded_body = compression::decode(
+                response.bytes_stream().map_err(std::io::Error::other),
+                upstream_encoding,
+            );
+            tokio::spawn(body_rewriter.rewrite(decoded_body, sender));
+
+            *new_response.body_mut() = compression::encode(rewritten_body, client_encoding);
+        }
+        _ => {
+            let (sender, body) = Body::channel();
+            tokio::spawn(write_proxied_body(response, sender));
+
+            *new_response.body_mut() = body;
         }
+    }
 
-        tokio::spawn(write_proxied_body(response, sender));
+    Ok(new_response)
+}
 
-        return Ok(new_response);
+/// Rewritten bodies are sent with a length that isn't known upfront, compressed with the coding
+/// negotiated with the client.
+fn set_rewritten_body_headers(headers: &mut HeaderMap, client_encoding: ContentEncoding) {
+    headers.remove(http::header::CONTENT_LENGTH);
+    headers.remove(http::header::CONTENT_ENCODING);
+    if client_encoding != ContentEncoding::Identity {
+        headers.insert(
+            http::header::CONTENT_ENCODING,
+            client_encoding.header_value(),
+        );
     }
+    // The coding depends on the client's `Accept-Encoding`, caches must not hand the body to
+    // clients accepting other codings.
+    add_vary(headers, http::header::ACCEPT_ENCODING);
+    weaken_etag(headers);
+}
 
-    tokio::spawn(write_proxied_body(response, sender));
+/// Turns a strong `ETag` into a weak one, as the body no longer is byte for byte the one it was
+/// computed for. Conditional requests keep working, since weak tags are compared weakly.
+fn weaken_etag(headers: &mut HeaderMap) {
+    let etag = match headers.get(http::header::ETAG) {
+        Some(etag) if !etag.as_bytes().starts_with(b"W/") => etag,
+        _ => return,
+    };
 
-    Ok(new_response)
+    let mut weak_etag = b"W/".to_vec();
+    weak_etag.extend_from_slice(etag.as_bytes());
+
+    match HeaderValue::from_bytes(&weak_etag) {
+        Ok(weak_etag) => {
+            headers.insert(http::header::ETAG, weak_etag);
+        }
+        Err(_) => {
+            headers.remove(http::header::ETAG);
+        }
+    }
+}
+
+/// Appends `header_name` to the `Vary` header, keeping the values already present.
+fn add_vary(headers: &mut HeaderMap, header_name: http::header::HeaderName) {
+    let is_already_varying = headers
+        .get_all(http::header::VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|value| value == "*" || value.eq_ignore_ascii_case(header_name.as_str()));
+
+    if !is_already_varying {
+        headers.append(http::header::VARY, HeaderValue::from(header_name));
+    }
+}
+
+/// Returns the `adblock` request type of a request, out of its `Sec-Fetch-Dest` header or, for
+/// older browsers, its `Accept` header.
+pub(super) fn get_request_type(headers: &HeaderMap) -> &'static str {
+    let header_value = |name| {
+        headers
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+            .unwrap_or_default()
+    };
+
+    match header_value("sec-fetch-dest") {
+        "document" => "document",
+        "iframe" | "frame" | "embed" | "object" => "subdocument",
+        "script" | "worker" | "sharedworker" | "serviceworker" => "script",
+        "style" => "stylesheet",
+        "image" => "image",
+        "font" => "font",
+        "audio" | "video" | "track" => "media",
+        "" if header_value(http::header::ACCEPT.as_str()).contains("text/html") => "document",
+        // Requests made with `fetch()` or `XMLHttpRequest`.
+        "empty" => "xmlhttprequest",
+        _ => "other",
+    }
 }
 
 fn get_informative_error_response(reason: &str) -> Response<Body> {
@@ -186,6 +475,20 @@ fn get_blocked_by_privaxy_response(blocker_result: BlockerResult) -> Response<Bo
     response
 }
 
+fn get_redirect_response(location: &str) -> Option<Response<Body>> {
+    let location = HeaderValue::from_str(location).ok()?;
+
+    let mut response = get_empty_response(http::StatusCode::TEMPORARY_REDIRECT);
+    let headers = response.headers_mut();
+    headers.insert(http::header::LOCATION, location);
+    headers.insert(
+        http::header::CACHE_CONTROL,
+        HeaderValue::from_static("no-store"),
+    );
+
+    Some(response)
+}
+
 fn get_empty_response(status_code: http::StatusCode) -> Response<Body> {
     let mut response = Response::new(Body::empty());
     *response.status_mut() = status_code;
@@ -193,6 +496,26 @@ fn get_empty_response(status_code: http::StatusCode) -> Response<Body> {
     response
 }
 
+/// HTTP/2 clients may split cookies into several `cookie` fields (RFC 9113, section 8.2.3).
+/// They have to be concatenated back into a single field as the upstream connection may
+/// be using HTTP/1.1.
+fn merge_cookie_headers(headers: &mut HeaderMap) {
+    if headers.get_all(http::header::COOKIE).iter().count() < 2 {
+        return;
+    }
+
+    let cookies = headers
+        .get_all(http::header::COOKIE)
+        .iter()
+        .filter_map(|cookie| cookie.to_str().ok())
+        .collect::<Vec<_>>()
+        .join("; ");
+
+    if let Ok(cookies) = HeaderValue::from_str(&cookies) {
+        headers.insert(http::header::COOKIE, cookies);
+    }
+}
+
 async fn write_proxied_body(mut response: reqwest::Response, mut sender: hyper::body::Sender) {
     while let Ok(Some(chunk)) = response.chunk().await {
         // The other end is broken, let's abort immediately.
@@ -249,3 +572,114 @@ async fn perform_two_ends_upgrade(
 
     new_response
 }
+
+/// Websockets over HTTP/2 are opened with an extended CONNECT request (RFC 8441) instead of an
+/// `Upgrade` header. As the upstream server may only speak HTTP/1.1, we translate the request into
+/// a regular websocket handshake and connect both ends through the upgraded streams.
+async fn perform_extended_connect(
+    request: Request<Body>,
+    uri: Uri,
+    hyper_client: hyper::Client<HttpsConnector<HttpConnector>>,
+) -> Response<Body> {
+    let protocol = match request.extensions().get::<Protocol>() {
+        Some(protocol) => protocol.as_str().to_string(),
+        None => return get_empty_response(http::StatusCode::BAD_REQUEST),
+    };
+
+    let protocol = match HeaderValue::from_str(&protocol) {
+        Ok(protocol) => protocol,
+        Err(_err) => return get_empty_response(http::StatusCode::BAD_REQUEST),
+    };
+
+    let mut websocket_key = [0u8; 16];
+    if openssl::rand::rand_bytes(&mut websocket_key).is_err() {
+        return get_empty_response(http::StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    let websocket_key = general_purpose::STANDARD.encode(websocket_key);
+
+    let mut new_request = Request::new(Body::empty());
+    *new_request.headers_mut() = request.headers().clone();
+    *new_request.uri_mut() = uri;
+
+    let headers = new_request.headers_mut();
+    headers.insert(
+        http::header::CONNECTION,
+        HeaderValue::from_static("upgrade"),
+    );
+    headers.insert(http::header::UPGRADE, protocol);
+    headers.insert(
+        http::header::SEC_WEBSOCKET_KEY,
+        HeaderValue::from_str(&websocket_key).unwrap(),
+    );
+
+    let response = match hyper_client.request(new_request).await {
+        Ok(response) => response,
+        Err(_err) => return get_empty_response(http::StatusCode::BAD_GATEWAY),
+    };
+
+    if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+        return get_empty_response(response.status());
+    }
+
+    // A successful extended CONNECT is acknowledged with a 2xx status and carries none of the
+    // HTTP/1.1 handshake headers.
+    let mut new_response = get_empty_response(StatusCode::OK);
+    *new_response.headers_mut() = response.headers().clone();
+    for header in [
+        http::header::CONNECTION,
+        http::header::UPGRADE,
+        http::header::SEC_WEBSOCKET_ACCEPT,
+    ] {
+        new_response.headers_mut().remove(header);
+    }
+
+    tokio::spawn(async move {
+        match tokio::join!(hyper::upgrade::on(request), hyper::upgrade::on(response)) {
+            (Ok(mut upgraded_client), Ok(mut upgraded_server)) => {
+                let _result =
+                    tokio::io::copy_bidirectional(&mut upgraded_client, &mut upgraded_server).await;
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                log::debug!("Unable to upgrade: {}", e)
+            }
+        }
+    });
+
+    new_response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_rewritten_body_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(http::header::CONTENT_LENGTH, HeaderValue::from_static("42"));
+        headers.insert(
+            http::header::CONTENT_ENCODING,
+            HeaderValue::from_static("gzip"),
+        );
+        headers.insert(http::header::ETAG, HeaderValue::from_static("\"abc\""));
+        headers.insert(http::header::VARY, HeaderValue::from_static("Origin"));
+
+        set_rewritten_body_headers(&mut headers, ContentEncoding::Brotli);
+
+        assert!(headers.get(http::header::CONTENT_LENGTH).is_none());
+        assert_eq!(headers[http::header::CONTENT_ENCODING], "br");
+        assert_eq!(headers[http::header::ETAG], "W/\"abc\"");
+        assert_eq!(
+            headers
+                .get_all(http::header::VARY)
+                .iter()
+                .collect::<Vec<_>>(),
+            ["Origin", "accept-encoding"]
+        );
+
+        set_rewritten_body_headers(&mut headers, ContentEncoding::Identity);
+
+        assert!(headers.get(http::header::CONTENT_ENCODING).is_none());
+        assert_eq!(headers[http::header::ETAG], "W/\"abc\"");
+        assert_eq!(headers.get_all(http::header::VARY).iter().count(), 2);
+    }
+}
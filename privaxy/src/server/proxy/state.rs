@@ -0,0 +1,25 @@
+use super::{
+    exclusions::LocalExclusionStore, https_upgrade::HttpsUpgrader, privacy::PrivacyPolicy,
+};
+use crate::{
+    blocker::AdblockRequester, cert::CertCache, configuration::BodyRewritingConfig,
+    statistics::Statistics, web_gui::events::Event,
+};
+use hyper::client::HttpConnector;
+use hyper_rustls::HttpsConnector;
+use tokio::sync::broadcast;
+
+/// State shared by every connection to the proxy, built once when the proxy starts.
+pub(crate) struct ProxyState {
+    pub(crate) adblock_requester: AdblockRequester,
+    /// Only used to perform upgrades.
+    pub(crate) hyper_client: hyper::Client<HttpsConnector<HttpConnector>>,
+    pub(crate) client: reqwest::Client,
+    pub(crate) cert_cache: CertCache,
+    pub(crate) broadcast_sender: broadcast::Sender<Event>,
+    pub(crate) statistics: Statistics,
+    pub(crate) local_exclusion_store: LocalExclusionStore,
+    pub(crate) body_rewriting: BodyRewritingConfig,
+    pub(crate) privacy_policy: PrivacyPolicy,
+    pub(crate) https_upgrader: HttpsUpgrader,
+}
@@ -0,0 +1,49 @@
+//! Subscription links published by filter list websites, `abp:subscribe?location=...&title=...`.
+//!
+//! Browsers don't know what to do with the `abp:` scheme, anchors using it are rewritten to the
+//! subscription page adblockers use instead. Navigations to that page are redirected to the
+//! subscription page of the web interface, where the user confirms adding the list.
+use http::Uri;
+use hyper::{Body, Request};
+
+/// Hosts of the pages adblockers use to subscribe to lists, through `?location=` URLs.
+const SUBSCRIBE_HOSTS: [&str; 1] = ["subscribe.adblockplus.org"];
+
+/// Page `abp:subscribe` links are rewritten to.
+const SUBSCRIBE_PAGE_URL: &str = "https://subscribe.adblockplus.org/";
+
+/// Selector of the anchors to rewrite.
+pub(super) const SUBSCRIBE_LINKS_SELECTOR: &str = r#"a[href^="abp:subscribe"]"#;
+
+/// Returns the subscription page URL to use in place of an `abp:subscribe` link.
+pub(super) fn get_subscribe_page_url(href: &str) -> Option<String> {
+    let query = href.strip_prefix("abp:subscribe?")?;
+
+    Some(format!("{SUBSCRIBE_PAGE_URL}?{query}"))
+}
+
+/// Returns where to redirect `request` to when it is a subscription, the web interface at
+/// `web_gui_url` taking over.
+pub(super) fn get_subscription_redirect(
+    request: &Request<Body>,
+    uri: &Uri,
+    web_gui_url: &str,
+) -> Option<String> {
+    if request.method() != http::Method::GET || !SUBSCRIBE_HOSTS.contains(&uri.host()?) {
+        return None;
+    }
+
+    let query = uri.query()?;
+
+    if !url::form_urlencoded::parse(query.as_bytes()).any(|(key, _value)| key == "location") {
+        return None;
+    }
+
+    log::debug!("Intercepted filter subscription: {}", uri);
+
+    Some(format!(
+        "{}/subscribe?{}",
+        web_gui_url.trim_end_matches('/'),
+        query
+    ))
+}
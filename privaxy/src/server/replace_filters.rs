@@ -0,0 +1,235 @@
+//! `$replace` filters, such as `||example.com/ads.js$script,replace=/showAds/noop/g`, which
+//! rewrite the bodies of text responses.
+use crate::option_filters::{FilterOption, OptionFilters};
+use regex::Regex;
+
+pub(crate) type ReplaceFilters = OptionFilters<ReplaceRule>;
+
+/// A regular expression replacement, applied to a response body.
+#[derive(Debug, Clone)]
+pub struct ReplaceRule {
+    regex: Regex,
+    replacement: String,
+    is_global: bool,
+}
+
+impl FilterOption for ReplaceRule {
+    const NAME: &'static str = "replace";
+
+    /// Parses `/regex/replacement/flags`, with JavaScript semantics: unless the `g` flag is set,
+    /// only the first match is replaced.
+    fn parse(value: &str, _line: &str) -> Option<Self> {
+        let value = value.strip_prefix('/')?;
+        let (pattern, rest) = split_unescaped_slash(value)?;
+        let (replacement, flags) = split_unescaped_slash(rest)?;
+
+        let mut inline_flags = String::new();
+        let mut is_global = false;
+        for flag in flags.chars() {
+            match flag {
+                'g' => is_global = true,
+                'i' | 'm' | 's' => inline_flags.push(flag),
+                _ => return None,
+            }
+        }
+
+        let pattern = unescape_separators(&pattern);
+        let regex = if inline_flags.is_empty() {
+            Regex::new(&pattern)
+        } else {
+            Regex::new(&format!("(?{inline_flags}){pattern}"))
+        }
+        .ok()?;
+
+        Some(Self {
+            regex,
+            replacement: to_regex_replacement(&unescape_separators(&replacement)),
+            is_global,
+        })
+    }
+}
+
+impl ReplaceRule {
+    /// Applies the rule to `text`, returning `None` when nothing matched.
+    pub(crate) fn apply(&self, text: &str) -> Option<String> {
+        if !self.regex.is_match(text) {
+            return None;
+        }
+
+        let replaced = if self.is_global {
+            self.regex.replace_all(text, self.replacement.as_str())
+        } else {
+            self.regex.replace(text, self.replacement.as_str())
+        };
+
+        Some(replaced.into_owned())
+    }
+}
+
+/// Splits `text` on its first slash which is not escaped.
+fn split_unescaped_slash(text: &str) -> Option<(String, &str)> {
+    let mut is_escaped = false;
+
+    for (index, c) in text.char_indices() {
+        match c {
+            _ if is_escaped => is_escaped = false,
+            '\\' => is_escaped = true,
+            '/' => return Some((text[..index].to_string(), &text[index + 1..])),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Removes the backslashes escaping slashes and commas, leaving other escape sequences, such as
+/// `\\` or `\d`, to the regular expression.
+fn unescape_separators(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some(escaped @ ('/' | ',')) => unescaped.push(escaped),
+            Some(escaped) => {
+                unescaped.push(c);
+                unescaped.push(escaped);
+            }
+            None => unescaped.push(c),
+        }
+    }
+
+    unescaped
+}
+
+/// Converts a JavaScript replacement string, in which `$1` or `$&` refer to captured groups, to
+/// the syntax of the `regex` crate.
+fn to_regex_replacement(replacement: &str) -> String {
+    let mut converted = String::new();
+    let mut chars = replacement.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            converted.push(c);
+            continue;
+        }
+
+        match chars.peek() {
+            Some('$') => {
+                chars.next();
+                converted.push_str("$$");
+            }
+            Some('&') => {
+                chars.next();
+                converted.push_str("${0}");
+            }
+            Some(digit) if digit.is_ascii_digit() => {
+                let mut group = String::new();
+                while let Some(digit) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                    group.push(*digit);
+                    chars.next();
+                }
+                converted.push_str(&format!("${{{group}}}"));
+            }
+            _ => converted.push_str("$$"),
+        }
+    }
+
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use adblock::request::Request;
+
+    fn replace(value: &str, text: &str) -> Option<String> {
+        ReplaceRule::parse(value, "").unwrap().apply(text)
+    }
+
+    #[test]
+    fn test_first_match_only_without_global_flag() {
+        assert_eq!(replace("/a/b/", "aaa"), Some("baa".to_string()));
+        assert_eq!(replace("/a/b/g", "aaa"), Some("bbb".to_string()));
+        assert_eq!(replace("/c/b/g", "aaa"), None);
+    }
+
+    #[test]
+    fn test_replacement_references() {
+        assert_eq!(replace("/foo/[$&]/", "a foo"), Some("a [foo]".to_string()));
+        assert_eq!(replace("/foo/$$1/", "foo"), Some("$1".to_string()));
+        assert_eq!(replace("/foo/$x/", "foo"), Some("$x".to_string()));
+        assert_eq!(
+            replace("/(\\w+)@(\\w+)/$2 at $1/", "me@host"),
+            Some("host at me".to_string())
+        );
+        assert_eq!(
+            replace(
+                "/(a)(b)(c)(d)(e)(f)(g)(h)(i)(j)(k)/$11$10$1/",
+                "abcdefghijk"
+            ),
+            Some("kja".to_string())
+        );
+    }
+
+    #[test]
+    fn test_escaped_separators() {
+        assert_eq!(
+            replace(
+                "/https:\\/\\/ads\\.example\\//https:\\/\\/example.com\\//",
+                "https://ads.example/ad.js"
+            ),
+            Some("https://example.com/ad.js".to_string())
+        );
+        assert_eq!(replace("/a\\,b/c\\,d/", "a,b"), Some("c,d".to_string()));
+        assert_eq!(replace("/\\d+/0/g", "1 22"), Some("0 0".to_string()));
+    }
+
+    #[test]
+    fn test_escaped_backslash_before_slash() {
+        // `\\` is an escaped backslash, the slash which follows it ends the pattern.
+        assert_eq!(replace("/a\\\\/b/g", "a\\x a\\"), Some("bx b".to_string()));
+        // `\\\/` is an escaped backslash followed by an escaped slash.
+        assert_eq!(replace("/a\\\\\\/b/c/", "a\\/b"), Some("c".to_string()));
+    }
+
+    #[test]
+    fn test_flags() {
+        assert_eq!(replace("/FOO/bar/i", "foo"), Some("bar".to_string()));
+        assert_eq!(replace("/^b/x/m", "a\nb"), Some("a\nx".to_string()));
+        assert_eq!(replace("/a.b/x/s", "a\nb"), Some("x".to_string()));
+        assert_eq!(replace("/a.b/x/", "a\nb"), None);
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        assert!(ReplaceRule::parse("a/b/", "").is_none());
+        assert!(ReplaceRule::parse("/a/b", "").is_none());
+        assert!(ReplaceRule::parse("/a/b/y", "").is_none());
+        assert!(ReplaceRule::parse("/(a/b/", "").is_none());
+    }
+
+    #[test]
+    fn test_filters_with_escaped_commas() {
+        let mut filters = ReplaceFilters::from_filter_lists(&[
+            "||example.com/ads.js$script,replace=/a\\,b/c/g".to_string(),
+        ]);
+        assert_eq!(filters.len(), 1);
+
+        let request = Request::new(
+            "https://example.com/ads.js",
+            "https://example.com",
+            "script",
+        )
+        .unwrap();
+        let rules = filters.options_for_request(&request);
+
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].apply("a,b a,b"), Some("c c".to_string()));
+    }
+}
@@ -1,6 +1,7 @@
+use crate::proxy::PrivacyAction;
 use serde::Serialize;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     net::IpAddr,
     sync::{Arc, Mutex},
 };
@@ -12,20 +13,40 @@ const ENTRIES_PER_STATISTICS_TABLE: u8 = 50;
 pub struct SerializableStatistics {
     pub proxied_requests: u64,
     pub blocked_requests: u64,
+    /// Requests matched by observed filter lists, which were let through.
+    pub would_be_blocked_requests: u64,
     pub modified_responses: u64,
+    /// Responses that `$replace` and `$jsonprune` filters were not applied to, as their body was
+    /// too large or too slow to be received.
+    pub skipped_body_rewrites: u64,
+    pub procedural_filters_applied: u64,
     #[serde(with = "tuple_vec_map")]
     pub top_blocked_paths: Vec<(String, u64)>,
     #[serde(with = "tuple_vec_map")]
     pub top_clients: Vec<(String, u64)>,
+    /// Responses pruned by each `$jsonprune` filter.
+    #[serde(with = "tuple_vec_map")]
+    pub top_json_prune_filters: Vec<(String, u64)>,
+    /// Requests each observed filter would have blocked.
+    #[serde(with = "tuple_vec_map")]
+    pub top_observed_filters: Vec<(String, u64)>,
+    /// Header hardening actions taken on requests and responses.
+    pub privacy_actions: BTreeMap<&'static str, u64>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Statistics {
     pub proxied_requests: Arc<Mutex<u64>>,
     pub blocked_requests: Arc<Mutex<u64>>,
+    pub would_be_blocked_requests: Arc<Mutex<u64>>,
     pub modified_responses: Arc<Mutex<u64>>,
+    pub skipped_body_rewrites: Arc<Mutex<u64>>,
+    pub procedural_filters_applied: Arc<Mutex<u64>>,
     pub top_blocked_paths: Arc<Mutex<LRUCache<(String, u64), 1_000>>>,
     pub top_clients: Arc<Mutex<HashMap<IpAddr, u64>>>,
+    pub json_prune_filter_hits: Arc<Mutex<HashMap<String, u64>>>,
+    pub observed_filter_hits: Arc<Mutex<HashMap<String, u64>>>,
+    pub privacy_actions: Arc<Mutex<BTreeMap<&'static str, u64>>>,
 }
 
 impl Default for Statistics {
@@ -39,9 +60,15 @@ impl Statistics {
         Self {
             proxied_requests: Arc::new(Mutex::new(0)),
             blocked_requests: Arc::new(Mutex::new(0)),
+            would_be_blocked_requests: Arc::new(Mutex::new(0)),
             modified_responses: Arc::new(Mutex::new(0)),
+            skipped_body_rewrites: Arc::new(Mutex::new(0)),
+            procedural_filters_applied: Arc::new(Mutex::new(0)),
             top_blocked_paths: Arc::new(Mutex::new(LRUCache::default())),
             top_clients: Arc::new(Mutex::new(HashMap::new())),
+            json_prune_filter_hits: Arc::new(Mutex::new(HashMap::new())),
+            observed_filter_hits: Arc::new(Mutex::new(HashMap::new())),
+            privacy_actions: Arc::new(Mutex::new(BTreeMap::new())),
         }
     }
 
@@ -62,6 +89,24 @@ impl Statistics {
         *self.top_clients.lock().unwrap().entry(client).or_insert(0) += 1;
     }
 
+    pub fn increment_json_prune_filter_hits(&self, filter: &str) {
+        *self
+            .json_prune_filter_hits
+            .lock()
+            .unwrap()
+            .entry(filter.to_string())
+            .or_insert(0) += 1;
+    }
+
+    pub(crate) fn increment_privacy_action(&self, action: PrivacyAction) {
+        *self
+            .privacy_actions
+            .lock()
+            .unwrap()
+            .entry(action.as_str())
+            .or_insert(0) += 1;
+    }
+
     pub fn increment_proxied_requests(&self) -> u64 {
         let mut proxied_requests = self.proxied_requests.lock().unwrap();
 
@@ -76,6 +121,22 @@ impl Statistics {
         *blocked_requests
     }
 
+    pub fn increment_would_be_blocked_requests(&self, filter: Option<&str>) -> u64 {
+        if let Some(filter) = filter {
+            *self
+                .observed_filter_hits
+                .lock()
+                .unwrap()
+                .entry(filter.to_string())
+                .or_insert(0) += 1;
+        }
+
+        let mut would_be_blocked_requests = self.would_be_blocked_requests.lock().unwrap();
+
+        *would_be_blocked_requests += 1;
+        *would_be_blocked_requests
+    }
+
     pub fn increment_modified_responses(&self) -> u64 {
         let mut modified_responses = self.modified_responses.lock().unwrap();
 
@@ -83,11 +144,28 @@ impl Statistics {
         *modified_responses
     }
 
+    pub fn increment_skipped_body_rewrites(&self) -> u64 {
+        let mut skipped_body_rewrites = self.skipped_body_rewrites.lock().unwrap();
+
+        *skipped_body_rewrites += 1;
+        *skipped_body_rewrites
+    }
+
+    pub fn add_procedural_filters_applied(&self, count: u64) -> u64 {
+        let mut procedural_filters_applied = self.procedural_filters_applied.lock().unwrap();
+
+        *procedural_filters_applied += count;
+        *procedural_filters_applied
+    }
+
     pub fn get_serialized(&self) -> SerializableStatistics {
         SerializableStatistics {
             proxied_requests: *self.proxied_requests.lock().unwrap(),
             blocked_requests: *self.blocked_requests.lock().unwrap(),
+            would_be_blocked_requests: *self.would_be_blocked_requests.lock().unwrap(),
             modified_responses: *self.modified_responses.lock().unwrap(),
+            skipped_body_rewrites: *self.skipped_body_rewrites.lock().unwrap(),
+            procedural_filters_applied: *self.procedural_filters_applied.lock().unwrap(),
             top_blocked_paths: {
                 let top_blocked_paths = self.top_blocked_paths.lock().unwrap();
                 let mut top_blocked_paths_iterator = top_blocked_paths.iter();
@@ -122,6 +200,35 @@ impl Statistics {
 
                 top_clients
             },
+            top_json_prune_filters: {
+                let mut top_json_prune_filters = self
+                    .json_prune_filter_hits
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(filter, count)| (filter.clone(), *count))
+                    .collect::<Vec<_>>();
+
+                top_json_prune_filters.sort_by_key(|(_filter, count)| std::cmp::Reverse(*count));
+                top_json_prune_filters.truncate(ENTRIES_PER_STATISTICS_TABLE as usize);
+
+                top_json_prune_filters
+            },
+            top_observed_filters: {
+                let mut top_observed_filters = self
+                    .observed_filter_hits
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(filter, count)| (filter.clone(), *count))
+                    .collect::<Vec<_>>();
+
+                top_observed_filters.sort_by_key(|(_filter, count)| std::cmp::Reverse(*count));
+                top_observed_filters.truncate(ENTRIES_PER_STATISTICS_TABLE as usize);
+
+                top_observed_filters
+            },
+            privacy_actions: self.privacy_actions.lock().unwrap().clone(),
         }
     }
 }
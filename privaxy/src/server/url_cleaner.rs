@@ -0,0 +1,405 @@
+//! Link cleaning with [ClearURLs](https://docs.clearurls.xyz/latest/specs/rules/) rulesets:
+//! tracking query parameters are stripped and redirection wrappers, such as Google's `/url?q=`,
+//! are unwrapped.
+//!
+//! Providers marked as `completeProvider` are ignored, blocking requests is left to filter lists.
+//! Referral marketing parameters are kept, as ClearURLs does by default.
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct RawRuleset {
+    /// Providers are applied in the order of the file, as several of them may match a url.
+    #[serde(with = "tuple_vec_map")]
+    providers: Vec<(String, RawProvider)>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawProvider {
+    url_pattern: String,
+    #[serde(default)]
+    complete_provider: bool,
+    #[serde(default)]
+    rules: Vec<String>,
+    #[serde(default)]
+    raw_rules: Vec<String>,
+    #[serde(default)]
+    exceptions: Vec<String>,
+    #[serde(default)]
+    redirections: Vec<String>,
+}
+
+struct Provider {
+    url_pattern: Regex,
+    /// Names of the query parameters to remove.
+    rules: Vec<Regex>,
+    /// Patterns removed from the whole url.
+    raw_rules: Vec<Regex>,
+    exceptions: Vec<Regex>,
+    /// Patterns whose first group is the url being redirected to.
+    redirections: Vec<Regex>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CleanedUrl {
+    /// The url wraps a redirection to another url, the tracker can be skipped.
+    Unwrapped(String),
+    /// The url without its tracking parameters.
+    Cleaned(String),
+}
+
+impl CleanedUrl {
+    pub fn url(&self) -> &str {
+        match self {
+            Self::Unwrapped(url) | Self::Cleaned(url) => url,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct UrlCleaner {
+    providers: Vec<Provider>,
+}
+
+impl UrlCleaner {
+    /// Builds a cleaner out of the contents of ClearURLs rulesets. Rules that don't compile,
+    /// typically because they use look-around, are skipped.
+    pub(crate) fn from_rulesets(rulesets: &[String]) -> Self {
+        let mut providers = Vec::new();
+
+        for ruleset in rulesets {
+            let ruleset = match serde_json::from_str::<RawRuleset>(ruleset) {
+                Ok(ruleset) => ruleset,
+                Err(err) => {
+                    log::error!("Unable to parse ClearURLs ruleset: {}", err);
+                    continue;
+                }
+            };
+
+            providers.extend(
+                ruleset
+                    .providers
+                    .into_iter()
+                    .filter(|(_name, provider)| !provider.complete_provider)
+                    .filter_map(|(_name, provider)| Provider::compile(provider)),
+            );
+        }
+
+        Self { providers }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.providers.len()
+    }
+
+    /// Returns the cleaned version of `url`, if any rule applied to it.
+    pub(crate) fn clean(&self, url: &str) -> Option<CleanedUrl> {
+        let mut cleaned = url.to_string();
+
+        for provider in &self.providers {
+            if !provider.url_pattern.is_match(&cleaned)
+                || provider
+                    .exceptions
+                    .iter()
+                    .any(|exception| exception.is_match(&cleaned))
+            {
+                continue;
+            }
+
+            if let Some(target) = provider.redirection_target(&cleaned) {
+                return Some(CleanedUrl::Unwrapped(target));
+            }
+
+            for raw_rule in &provider.raw_rules {
+                cleaned = raw_rule.replace_all(&cleaned, "").into_owned();
+            }
+
+            if !provider.rules.is_empty() {
+                cleaned = provider.remove_parameters(&cleaned);
+            }
+        }
+
+        (cleaned != url).then_some(CleanedUrl::Cleaned(cleaned))
+    }
+}
+
+impl Provider {
+    fn compile(raw: RawProvider) -> Option<Self> {
+        let compile_all = |patterns: &[String]| {
+            patterns
+                .iter()
+                .filter_map(|pattern| compile_case_insensitive(pattern))
+                .collect::<Vec<_>>()
+        };
+
+        Some(Self {
+            url_pattern: compile_case_insensitive(&raw.url_pattern)?,
+            rules: raw
+                .rules
+                .iter()
+                .filter_map(|rule| compile_case_insensitive(&format!("^(?:{rule})$")))
+                .collect(),
+            raw_rules: compile_all(&raw.raw_rules),
+            exceptions: compile_all(&raw.exceptions),
+            redirections: compile_all(&raw.redirections),
+        })
+    }
+
+    fn redirection_target(&self, url: &str) -> Option<String> {
+        self.redirections.iter().find_map(|redirection| {
+            let target = redirection.captures(url)?.get(1)?.as_str();
+            let target = percent_decode(target);
+
+            // Anything else, such as `javascript:` urls, is left alone.
+            let parsed = url::Url::parse(&target).ok()?;
+            matches!(parsed.scheme(), "http" | "https").then_some(target)
+        })
+    }
+
+    /// Removes the query and fragment parameters matched by the rules, leaving the others
+    /// untouched.
+    fn remove_parameters(&self, url: &str) -> String {
+        let (url, fragment) = match url.split_once('#') {
+            Some((url, fragment)) => (url, Some(fragment)),
+            None => (url, None),
+        };
+        let (base, query) = match url.split_once('?') {
+            Some((base, query)) => (base, Some(query)),
+            None => (url, None),
+        };
+
+        let mut cleaned = base.to_string();
+
+        if let Some(query) = query.map(|query| self.filter_parameters(query)) {
+            if !query.is_empty() {
+                cleaned.push('?');
+                cleaned.push_str(&query);
+            }
+        }
+
+        if let Some(fragment) = fragment {
+            // Fragments are only parameters when they look like a query.
+            let fragment = if fragment.contains('=') {
+                self.filter_parameters(fragment)
+            } else {
+                fragment.to_string()
+            };
+
+            if !fragment.is_empty() {
+                cleaned.push('#');
+                cleaned.push_str(&fragment);
+            }
+        }
+
+        cleaned
+    }
+
+    fn filter_parameters(&self, parameters: &str) -> String {
+        parameters
+            .split('&')
+            .filter(|parameter| {
+                let name = parameter.split('=').next().unwrap_or_default();
+                let name = percent_decode(name);
+
+                !parameter.is_empty() && !self.rules.iter().any(|rule| rule.is_match(&name))
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+}
+
+fn compile_case_insensitive(pattern: &str) -> Option<Regex> {
+    match RegexBuilder::new(pattern).case_insensitive(true).build() {
+        Ok(regex) => Some(regex),
+        Err(_err) => {
+            log::debug!("Unsupported ClearURLs rule: {}", pattern);
+            None
+        }
+    }
+}
+
+fn percent_decode(text: &str) -> String {
+    percent_encoding::percent_decode_str(text)
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Providers of the ClearURLs ruleset, trimmed down to a few of their rules.
+    const RULESET: &str = r#"{
+        "providers": {
+            "globalRules": {
+                "urlPattern": ".*",
+                "completeProvider": false,
+                "rules": [
+                    "(?:%3F)?utm(?:_[a-z_]*)?",
+                    "(?:%3F)?fbclid",
+                    "(?:%3F)?gclid",
+                    "(?:%3F)?(?<=[?&])cmpid"
+                ],
+                "referralMarketing": ["(?:%3F)?ref_?"],
+                "rawRules": [],
+                "exceptions": [
+                    "^https?:\\/\\/localhost(?::\\d+)?(?:\\/.*)?$"
+                ],
+                "redirections": [],
+                "forceRedirection": false
+            },
+            "google": {
+                "urlPattern": "^https?:\\/\\/(?:[a-z0-9-]+\\.)*?google(?:\\.[a-z]{2,}){1,}",
+                "completeProvider": false,
+                "rules": ["ved", "bi[a-z]*", "ei", "gs_[a-z]*", "oq", "usg", "sa"],
+                "referralMarketing": ["referrer"],
+                "rawRules": [],
+                "exceptions": [
+                    "^https?:\\/\\/mail\\.google\\.com\\/mail\\/u\\/",
+                    "^https?:\\/\\/(?:docs|accounts)\\.google(?:\\.[a-z]{2,}){1,}"
+                ],
+                "redirections": [
+                    "^https?:\\/\\/[a-z0-9.-]*?google(?:\\.[a-z]{2,}){1,}\\/url\\?.*?(?:url|q)=(https?[^&]+)"
+                ],
+                "forceRedirection": true
+            },
+            "amazon": {
+                "urlPattern": "^https?:\\/\\/(?:[a-z0-9-]+\\.)*?amazon(?:\\.[a-z]{2,}){1,}",
+                "completeProvider": false,
+                "rules": ["p[fd]_rd_[a-z]*", "qid", "sr", "keywords", "th", "crid"],
+                "rawRules": ["\\/ref=[^/?]*"],
+                "exceptions": [
+                    "^https?:\\/\\/(?:[a-z0-9-]+\\.)*?amazon(?:\\.[a-z]{2,}){1,}\\/(?:hz\\/reviews-render\\/ajax\\/|message-us\\?|s\\?.*?(?:\\&|\\?)k=)"
+                ],
+                "redirections": [],
+                "forceRedirection": false
+            },
+            "doubleclick": {
+                "urlPattern": "^https?:\\/\\/(?:[a-z0-9-]+\\.)*?doubleclick(?:\\.[a-z]{2,}){1,}",
+                "completeProvider": true,
+                "rules": ["id"],
+                "redirections": [
+                    "^https?:\\/\\/(?:[a-z0-9-]+\\.)*?doubleclick(?:\\.[a-z]{2,}){1,}.*?adurl=(.*)"
+                ]
+            }
+        }
+    }"#;
+
+    fn clean(url: &str) -> Option<CleanedUrl> {
+        UrlCleaner::from_rulesets(&[RULESET.to_string()]).clean(url)
+    }
+
+    fn cleaned(url: &str) -> Option<CleanedUrl> {
+        Some(CleanedUrl::Cleaned(url.to_string()))
+    }
+
+    #[test]
+    fn test_providers() {
+        let url_cleaner = UrlCleaner::from_rulesets(&["{".to_string(), RULESET.to_string()]);
+
+        // Complete providers are left to filter lists.
+        assert_eq!(url_cleaner.len(), 3);
+        assert_eq!(
+            url_cleaner.clean("https://ad.doubleclick.net/ddm/clk?id=1&adurl=https://example.com"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rules() {
+        assert_eq!(
+            clean("https://example.com/page?utm_source=a&id=1&UTM_Medium=b&fbclid=c#top"),
+            cleaned("https://example.com/page?id=1#top")
+        );
+        assert_eq!(
+            clean("https://example.com/?gclid=a"),
+            cleaned("https://example.com/")
+        );
+        // Encoded parameter names, and parameters in fragments.
+        assert_eq!(
+            clean("https://example.com/?%75tm_source=a&q=utm_source#utm_campaign=b&tab=1"),
+            cleaned("https://example.com/?q=utm_source#tab=1")
+        );
+        // Referral marketing parameters are kept, rules using look-around are skipped.
+        assert_eq!(clean("https://example.com/?ref=a&cmpid=b"), None);
+        assert_eq!(clean("https://example.com/#utm_source"), None);
+    }
+
+    #[test]
+    fn test_provider_rules() {
+        assert_eq!(
+            clean("https://www.google.com/search?q=rust&ei=a&ved=b&oq=rust&gs_lcrp=c"),
+            cleaned("https://www.google.com/search?q=rust")
+        );
+        // Rules of a provider only apply to the urls it matches.
+        assert_eq!(clean("https://example.com/search?q=rust&ei=a"), None);
+    }
+
+    #[test]
+    fn test_raw_rules() {
+        assert_eq!(
+            clean("https://www.amazon.com/Some-Product/dp/B000000000/ref=sr_1_1?crid=a&keywords=b&qid=1&sr=8-1&th=1"),
+            cleaned("https://www.amazon.com/Some-Product/dp/B000000000")
+        );
+        assert_eq!(
+            clean("https://www.amazon.de/dp/B000000000/ref=cm_sw_r?psc=1"),
+            cleaned("https://www.amazon.de/dp/B000000000?psc=1")
+        );
+    }
+
+    #[test]
+    fn test_exceptions() {
+        assert_eq!(clean("http://localhost:8080/?utm_source=a"), None);
+        assert_eq!(
+            clean("https://www.amazon.com/s?i=aps&k=phone&crid=a&qid=1"),
+            None
+        );
+        // Exceptions only apply to the rules of their provider.
+        assert_eq!(
+            clean("https://mail.google.com/mail/u/0/?ved=a&utm_source=b"),
+            cleaned("https://mail.google.com/mail/u/0/?ved=a")
+        );
+    }
+
+    #[test]
+    fn test_redirections() {
+        assert_eq!(
+            clean("https://www.google.com/url?sa=t&url=https%3A%2F%2Fexample.com%2Fpage%3Fa%3D1&usg=b"),
+            Some(CleanedUrl::Unwrapped(
+                "https://example.com/page?a=1".to_string()
+            ))
+        );
+        assert_eq!(
+            clean("https://www.google.co.uk/url?q=http://example.com/&sa=U"),
+            Some(CleanedUrl::Unwrapped("http://example.com/".to_string()))
+        );
+        // Only http and https urls are redirected to.
+        assert_eq!(
+            clean("https://www.google.com/url?q=https:not%20a%20url&sa=t"),
+            cleaned("https://www.google.com/url?q=https:not%20a%20url")
+        );
+    }
+
+    #[test]
+    fn test_provider_order() {
+        let ruleset = r#"{
+            "providers": {
+                "zFirst": {
+                    "urlPattern": "^https:\\/\\/a\\.example",
+                    "rawRules": ["\\/tracking"]
+                },
+                "aSecond": {
+                    "urlPattern": "^https:\\/\\/a\\.example\\/page\\?",
+                    "redirections": ["[?&]to=([^&]+)"]
+                }
+            }
+        }"#;
+        let url_cleaner = UrlCleaner::from_rulesets(&[ruleset.to_string()]);
+
+        assert_eq!(
+            url_cleaner.clean("https://a.example/tracking/page?to=https%3A%2F%2Fb.example%2F"),
+            Some(CleanedUrl::Unwrapped("https://b.example/".to_string()))
+        );
+    }
+}
@@ -8,6 +8,9 @@ use warp::Filter as RouteFilter;
 #[derive(Deserialize)]
 pub struct BlockingEnabled(bool);
 
+#[derive(Deserialize)]
+pub struct BlockingObserve(bool);
+
 pub async fn get_blocking_enabled(
     blocking_disabled_store: BlockingDisabledStore,
 ) -> Result<impl warp::Reply, Infallible> {
@@ -23,6 +26,21 @@ pub async fn put_blocking_enabled(
     Ok(StatusCode::NO_CONTENT)
 }
 
+pub async fn get_blocking_observe(
+    blocking_disabled_store: BlockingDisabledStore,
+) -> Result<impl warp::Reply, Infallible> {
+    Ok(warp::reply::json(&blocking_disabled_store.is_observing()))
+}
+
+pub async fn put_blocking_observe(
+    blocking_observe: BlockingObserve,
+    blocking_disabled_store: BlockingDisabledStore,
+) -> Result<impl warp::Reply, Infallible> {
+    blocking_disabled_store.set_observing(blocking_observe.0);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
 pub(super) fn create_routes(
     blocking_disabled_store: BlockingDisabledStore,
 ) -> BoxedFilter<(impl warp::Reply,)> {
@@ -36,3 +54,17 @@ pub(super) fn create_routes(
             .and_then(self::put_blocking_enabled))
         .boxed()
 }
+
+pub(super) fn create_observe_routes(
+    blocking_disabled_store: BlockingDisabledStore,
+) -> BoxedFilter<(impl warp::Reply,)> {
+    let block_store = super::with_blocking_disabled_store(blocking_disabled_store);
+    warp::get()
+        .and(block_store.clone())
+        .and_then(self::get_blocking_observe)
+        .or(warp::put()
+            .and(warp::body::json())
+            .and(block_store)
+            .and_then(self::put_blocking_observe))
+        .boxed()
+}
@@ -1,5 +1,6 @@
 use super::get_error_response;
 use crate::configuration::Configuration;
+use crate::filter_lint::{self, EnabledRulesStore};
 use std::{convert::Infallible, sync::Arc};
 use tokio::sync::mpsc::Sender;
 use warp::filters::BoxedFilter;
@@ -20,12 +21,23 @@ async fn get_custom_filters() -> Result<Box<dyn warp::Reply>, Infallible> {
     Ok(Box::new(warp::reply::json(&custom_filters)))
 }
 
+async fn lint_custom_filters(
+    custom_filters: String,
+    enabled_rules: EnabledRulesStore,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let diagnostics = filter_lint::lint(&custom_filters, &enabled_rules);
+
+    Ok(Box::new(warp::reply::json(&diagnostics)))
+}
+
+/// Saves the custom filters, responding with their diagnostics.
 async fn put_custom_filters(
     custom_filters: String,
+    enabled_rules: EnabledRulesStore,
     configuration_updater_sender: Sender<Configuration>,
     configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
 ) -> Result<Box<dyn warp::Reply>, Infallible> {
-    let _guard = configuration_save_lock.lock().await;
+    let guard = configuration_save_lock.lock().await;
 
     let mut configuration = match Configuration::read_from_home().await {
         Ok(configuration) => configuration,
@@ -45,17 +57,30 @@ async fn put_custom_filters(
         .await
         .unwrap();
 
-    Ok(Box::new(StatusCode::ACCEPTED))
+    drop(guard);
+
+    let diagnostics = filter_lint::lint(&custom_filters, &enabled_rules);
+
+    Ok(Box::new(warp::reply::with_status(
+        warp::reply::json(&diagnostics),
+        StatusCode::ACCEPTED,
+    )))
 }
 
 pub(super) fn create_routes(
     configuration_updater_sender: Sender<Configuration>,
     configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
+    enabled_rules: EnabledRulesStore,
 ) -> BoxedFilter<(impl warp::Reply,)> {
-    warp::get()
-        .and_then(self::get_custom_filters)
+    warp::post()
+        .and(warp::path!("lint"))
+        .and(warp::body::json())
+        .and(super::with_enabled_rules(enabled_rules.clone()))
+        .and_then(self::lint_custom_filters)
+        .or(warp::get().and_then(self::get_custom_filters))
         .or(warp::put()
             .and(warp::body::json())
+            .and(super::with_enabled_rules(enabled_rules))
             .and(super::with_configuration_updater_sender(
                 configuration_updater_sender.clone(),
             ))
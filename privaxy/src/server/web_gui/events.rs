@@ -10,6 +10,8 @@ pub struct Event {
     pub method: String,
     pub url: String,
     pub is_request_blocked: bool,
+    /// Whether an observed filter list matched the request, which was let through.
+    pub would_be_blocked: bool,
 }
 
 pub(super) async fn events(websocket: WebSocket, events_sender: broadcast::Sender<Event>) {
@@ -0,0 +1,95 @@
+use super::get_error_response;
+use crate::configuration::{self, Configuration};
+use serde::Deserialize;
+use std::convert::Infallible;
+use warp::filters::BoxedFilter;
+use warp::http::{header, Response};
+use warp::Filter as RouteFilter;
+
+#[derive(Debug, Deserialize)]
+struct ExportRulesQuery {
+    /// Leaves out cosmetic rules, which only matter to blockers able to alter pages.
+    #[serde(default)]
+    network_only: bool,
+}
+
+fn get_text_response(body: String) -> Response<String> {
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(body)
+        .unwrap()
+}
+
+/// Returns the rules in effect, out of the stored copies of the filters: exporting doesn't fetch
+/// anything.
+async fn get_effective_rules(
+    network_only: bool,
+) -> Result<Vec<String>, configuration::ConfigurationError> {
+    let mut configuration = Configuration::read_from_home().await?;
+    let filters_content = configuration::get_stored_filters_content(&mut configuration).await;
+
+    // Every line of every list gets parsed, which takes a while.
+    tokio::task::spawn_blocking(move || {
+        configuration::get_effective_rules(&filters_content, network_only)
+    })
+    .await
+    .map_err(|err| configuration::ConfigurationError::FilterError(err.to_string()))
+}
+
+async fn export_rules(
+    export_rules_query: ExportRulesQuery,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    match get_effective_rules(export_rules_query.network_only).await {
+        Ok(rules) => Ok(Box::new(get_text_response(rules.join("\n")))),
+        Err(err) => {
+            log::error!("Failed to export rules: {err}");
+            Ok(Box::new(get_error_response(err)))
+        }
+    }
+}
+
+async fn get_hosts() -> Result<String, configuration::ConfigurationError> {
+    let rules = get_effective_rules(true).await?;
+
+    tokio::task::spawn_blocking(move || {
+        configuration::to_hosts(&configuration::get_blocked_domains(&rules))
+    })
+    .await
+    .map_err(|err| configuration::ConfigurationError::FilterError(err.to_string()))
+}
+
+async fn export_hosts() -> Result<Box<dyn warp::Reply>, Infallible> {
+    match get_hosts().await {
+        Ok(hosts) => Ok(Box::new(get_text_response(hosts))),
+        Err(err) => {
+            log::error!("Failed to export hosts: {err}");
+            Ok(Box::new(get_error_response(err)))
+        }
+    }
+}
+
+async fn export_custom_filters() -> Result<Box<dyn warp::Reply>, Infallible> {
+    match Configuration::read_from_home().await {
+        Ok(configuration) => Ok(Box::new(get_text_response(
+            configuration::to_custom_filters_list(&configuration.custom_filters),
+        ))),
+        Err(err) => {
+            log::error!("Failed to export custom filters: {err}");
+            Ok(Box::new(get_error_response(err)))
+        }
+    }
+}
+
+pub(super) fn create_routes() -> BoxedFilter<(impl warp::Reply,)> {
+    warp::get()
+        .and(warp::path!("rules"))
+        .and(warp::query::<ExportRulesQuery>())
+        .and_then(self::export_rules)
+        .or(warp::get()
+            .and(warp::path!("hosts"))
+            .and_then(self::export_hosts))
+        .or(warp::get()
+            .and(warp::path!("custom-filters.txt"))
+            .and_then(self::export_custom_filters))
+        .boxed()
+}
@@ -1,12 +1,257 @@
-use filterlists_api;
+use filterlists_api::FilterListsClient;
+use serde::{Deserialize, Serialize};
 use warp::Filter as RouteFilter;
 
+use std::collections::{HashMap, HashSet};
 use std::convert::Infallible;
 use warp::filters::BoxedFilter;
 
-async fn get_filters() -> Result<Box<dyn warp::Reply>, Infallible> {
+const DEFAULT_RESULTS_PER_PAGE: usize = 10;
+const MAX_RESULTS_PER_PAGE: usize = 100;
+
+/// Syntaxes the blocking engine handles, matched against the beginning of FilterLists syntax
+/// names. Hosts, dnsmasq and domain lists are converted into Adblock Plus syntax when added.
+const SUPPORTED_SYNTAXES: [&str; 6] = [
+    "adblock plus",
+    "ublock origin static",
+    "adguard",
+    "hosts",
+    "domains",
+    "dnsmasq",
+];
+
+#[derive(Debug, Deserialize)]
+struct SearchQuery {
+    /// Text looked for in the names and descriptions of the lists.
+    #[serde(default)]
+    query: String,
+    language_id: Option<u32>,
+    tag_id: Option<u32>,
+    syntax_id: Option<u32>,
+    license_id: Option<u32>,
+    maintainer_id: Option<u32>,
+    #[serde(default = "default_page")]
+    page: usize,
+    #[serde(default = "default_results_per_page")]
+    per_page: usize,
+    /// Ranks lists by how well their languages match `locale`, then by age, older lists coming
+    /// first. This is not a popularity ranking: FilterLists has no data about it.
+    #[serde(default)]
+    by_language: bool,
+    /// Languages of the user in `Accept-Language` format, that header being used when missing.
+    locale: Option<String>,
+}
+
+fn default_page() -> usize {
+    1
+}
+
+fn default_results_per_page() -> usize {
+    DEFAULT_RESULTS_PER_PAGE
+}
+
+/// How the lists of a [`SearchResponse`] are ordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum SearchRanking {
+    /// Lists matching the query by name come before the ones matching it by description only.
+    Text,
+    /// Lists are ranked by language match, then as with [`SearchRanking::Text`], then by age,
+    /// older lists coming first.
+    LanguageThenAge,
+}
+
+#[derive(Debug, Serialize)]
+struct SearchResponse {
+    filters: Vec<filterlists_api::Filter>,
+    /// Number of lists matching the search, across all pages.
+    total: usize,
+    page: usize,
+    per_page: usize,
+    ranking: SearchRanking,
+}
+
+fn is_supported_syntax(syntax: &filterlists_api::FilterListSyntax) -> bool {
+    let name = syntax.name.to_lowercase();
+
+    SUPPORTED_SYNTAXES
+        .iter()
+        .any(|supported_syntax| name.starts_with(supported_syntax))
+}
+
+/// Returns the language codes of an `Accept-Language` value, most preferred first.
+fn parse_locale(locale: &str) -> Vec<String> {
+    let mut languages: Vec<String> = Vec::new();
+
+    for language in locale.split(',') {
+        let language = language
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .split('-')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+
+        if !language.is_empty() && language != "*" && !languages.contains(&language) {
+            languages.push(language);
+        }
+    }
+
+    languages
+}
+
+/// Ranks `filter` by the most preferred of its languages. Lists without a language come after
+/// the ones matching a preferred language, and before lists for other languages.
+fn get_language_rank(
+    filter: &filterlists_api::Filter,
+    preferred_languages: &[String],
+    language_codes: &HashMap<u32, &str>,
+) -> usize {
+    if filter.language_ids.is_empty() {
+        return preferred_languages.len();
+    }
+
+    filter
+        .language_ids
+        .iter()
+        .filter_map(|language_id| language_codes.get(language_id))
+        .filter_map(|code| {
+            preferred_languages
+                .iter()
+                .position(|language| language == code)
+        })
+        .min()
+        .unwrap_or(preferred_languages.len() + 1)
+}
+
+/// Ranks `filter` by where `query` is found: in its name, in its description only, or nowhere.
+fn get_text_rank(filter: &filterlists_api::Filter, query: &str) -> Option<usize> {
+    if filter.name.to_lowercase().contains(query) {
+        return Some(0);
+    }
+
+    let description = filter.description.as_deref().unwrap_or_default();
+
+    description.to_lowercase().contains(query).then_some(1)
+}
+
+/// Returns the requested page of `results`, along with the page number and size, brought back
+/// within bounds.
+fn paginate<T>(
+    results: impl Iterator<Item = T>,
+    page: usize,
+    per_page: usize,
+) -> (Vec<T>, usize, usize) {
+    let page = page.max(1);
+    let per_page = per_page.clamp(1, MAX_RESULTS_PER_PAGE);
+
+    let results = results
+        .skip((page - 1).saturating_mul(per_page))
+        .take(per_page)
+        .collect();
+
+    (results, page, per_page)
+}
+
+async fn search(
+    search_query: SearchQuery,
+    accept_language: Option<String>,
+    filterlists_client: FilterListsClient,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    log::debug!("Searching filters: {:?}", search_query);
+
+    let (filters, syntaxes, languages) = match futures::try_join!(
+        filterlists_client.get_filters(),
+        filterlists_client.get_syntaxes(),
+        filterlists_client.get_languages(),
+    ) {
+        Ok(results) => results,
+        Err(err) => {
+            log::error!("Failed to search filters: {err}");
+            return Ok(Box::new(super::get_error_response(err)));
+        }
+    };
+
+    let supported_syntax_ids: HashSet<u32> = syntaxes
+        .iter()
+        .filter(|syntax| is_supported_syntax(syntax))
+        .map(|syntax| syntax.id)
+        .collect();
+    let query = search_query.query.trim().to_lowercase();
+
+    let mut results: Vec<(usize, filterlists_api::Filter)> = filters
+        .into_iter()
+        .filter(|filter| {
+            filter
+                .syntax_ids
+                .iter()
+                .any(|syntax_id| supported_syntax_ids.contains(syntax_id))
+                && search_query
+                    .syntax_id
+                    .is_none_or(|syntax_id| filter.syntax_ids.contains(&syntax_id))
+                && search_query
+                    .language_id
+                    .is_none_or(|language_id| filter.language_ids.contains(&language_id))
+                && search_query
+                    .tag_id
+                    .is_none_or(|tag_id| filter.tag_ids.contains(&tag_id))
+                && search_query
+                    .license_id
+                    .is_none_or(|license_id| filter.license_id == license_id)
+                && search_query
+                    .maintainer_id
+                    .is_none_or(|maintainer_id| filter.maintainer_ids.contains(&maintainer_id))
+        })
+        .filter_map(|filter| Some((get_text_rank(&filter, &query)?, filter)))
+        .collect();
+
+    let ranking = if search_query.by_language {
+        let locale = search_query.locale.or(accept_language).unwrap_or_default();
+        let preferred_languages = parse_locale(&locale);
+        let language_codes: HashMap<u32, &str> = languages
+            .iter()
+            .map(|language| (language.id, language.iso6391.as_str()))
+            .collect();
+
+        // FilterLists ids are assigned in the order lists were added, which gives their age.
+        results.sort_by_key(|(text_rank, filter)| {
+            (
+                get_language_rank(filter, &preferred_languages, &language_codes),
+                *text_rank,
+                filter.id,
+            )
+        });
+
+        SearchRanking::LanguageThenAge
+    } else {
+        results.sort_by_key(|(text_rank, _filter)| *text_rank);
+
+        SearchRanking::Text
+    };
+
+    let total = results.len();
+    let (filters, page, per_page) = paginate(
+        results.into_iter().map(|(_text_rank, filter)| filter),
+        search_query.page,
+        search_query.per_page,
+    );
+
+    Ok(Box::new(warp::reply::json(&SearchResponse {
+        filters,
+        total,
+        page,
+        per_page,
+        ranking,
+    })))
+}
+
+async fn get_filters(
+    filterlists_client: FilterListsClient,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
     log::debug!("Getting filters");
-    match filterlists_api::get_filters().await {
+    match filterlists_client.get_filters().await {
         Ok(filters) => Ok(Box::new(warp::reply::json(&filters))),
         Err(err) => {
             log::error!("Failed to get filters: {err}");
@@ -15,9 +260,15 @@ async fn get_filters() -> Result<Box<dyn warp::Reply>, Infallible> {
     }
 }
 
-async fn get_filter(id: u32) -> Result<Box<dyn warp::Reply>, Infallible> {
+async fn get_filter(
+    id: u32,
+    filterlists_client: FilterListsClient,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
     log::debug!("Getting filter {id}");
-    match filterlists_api::get_filter_information(filterlists_api::FilterArgs::U32(id)).await {
+    match filterlists_client
+        .get_filter_information(filterlists_api::FilterArgs::U32(id))
+        .await
+    {
         Ok(filter) => Ok(Box::new(warp::reply::json(&filter))),
         Err(err) => {
             log::error!("Failed to get filter: {err}");
@@ -26,54 +277,169 @@ async fn get_filter(id: u32) -> Result<Box<dyn warp::Reply>, Infallible> {
     }
 }
 
-async fn get_syntaxes() -> Result<Box<dyn warp::Reply>, Infallible> {
+async fn get_syntaxes(
+    filterlists_client: FilterListsClient,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
     log::debug!("Getting syntaxes");
-    match filterlists_api::get_syntaxes().await {
+    match filterlists_client.get_syntaxes().await {
         Ok(syntaxes) => Ok(Box::new(warp::reply::json(&syntaxes))),
         Err(err) => Ok(Box::new(super::get_error_response(err))),
     }
 }
 
-async fn get_languages() -> Result<Box<dyn warp::Reply>, Infallible> {
+async fn get_languages(
+    filterlists_client: FilterListsClient,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
     log::debug!("Getting languages");
-    match filterlists_api::get_languages().await {
+    match filterlists_client.get_languages().await {
         Ok(languages) => Ok(Box::new(warp::reply::json(&languages))),
         Err(err) => Ok(Box::new(super::get_error_response(err))),
     }
 }
 
-async fn get_tags() -> Result<Box<dyn warp::Reply>, Infallible> {
+async fn get_tags(
+    filterlists_client: FilterListsClient,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
     log::debug!("Getting tags");
-    match filterlists_api::get_tags().await {
+    match filterlists_client.get_tags().await {
         Ok(tags) => Ok(Box::new(warp::reply::json(&tags))),
         Err(err) => Ok(Box::new(super::get_error_response(err))),
     }
 }
 
-async fn get_licenses() -> Result<Box<dyn warp::Reply>, Infallible> {
+async fn get_licenses(
+    filterlists_client: FilterListsClient,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
     log::debug!("Getting licenses");
-    match filterlists_api::get_licenses().await {
+    match filterlists_client.get_licenses().await {
         Ok(licenses) => Ok(Box::new(warp::reply::json(&licenses))),
         Err(err) => Ok(Box::new(super::get_error_response(err))),
     }
 }
 
-pub(super) fn create_routes() -> BoxedFilter<(impl warp::Reply,)> {
-    warp::path("list")
+pub(super) fn create_routes(
+    filterlists_client: FilterListsClient,
+) -> BoxedFilter<(impl warp::Reply,)> {
+    warp::path("search")
         .and(warp::get())
-        .and_then(self::get_filters)
+        .and(warp::query::<SearchQuery>())
+        .and(warp::header::optional::<String>("accept-language"))
+        .and(super::with_filterlists_client(filterlists_client.clone()))
+        .and_then(self::search)
+        .or(warp::path("list")
+            .and(warp::get())
+            .and(super::with_filterlists_client(filterlists_client.clone()))
+            .and_then(self::get_filters))
         .or(warp::path!("list" / u32)
             .and(warp::get())
+            .and(super::with_filterlists_client(filterlists_client.clone()))
             .and_then(self::get_filter))
         .or(warp::path("syntaxes")
             .and(warp::get())
+            .and(super::with_filterlists_client(filterlists_client.clone()))
             .and_then(self::get_syntaxes))
         .or(warp::path("languages")
             .and(warp::get())
+            .and(super::with_filterlists_client(filterlists_client.clone()))
             .and_then(self::get_languages))
-        .or(warp::path("tags").and(warp::get()).and_then(self::get_tags))
+        .or(warp::path("tags")
+            .and(warp::get())
+            .and(super::with_filterlists_client(filterlists_client.clone()))
+            .and_then(self::get_tags))
         .or(warp::path("licenses")
             .and(warp::get())
+            .and(super::with_filterlists_client(filterlists_client.clone()))
             .and_then(self::get_licenses))
         .boxed()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(
+        id: u32,
+        name: &str,
+        description: Option<&str>,
+        language_ids: &[u32],
+    ) -> filterlists_api::Filter {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "name": name,
+            "description": description,
+            "licenseId": 1,
+            "syntaxIds": [1],
+            "languageIds": language_ids,
+            "tagIds": [],
+            "maintainerIds": [],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_locale() {
+        assert_eq!(
+            parse_locale("fr-CH, fr;q=0.9, en;q=0.8, de;q=0.7, *;q=0.5"),
+            vec!["fr", "en", "de"]
+        );
+        assert_eq!(parse_locale("EN-us,en-GB"), vec!["en"]);
+        assert!(parse_locale("").is_empty());
+        assert!(parse_locale("*").is_empty());
+    }
+
+    #[test]
+    fn test_get_language_rank() {
+        let preferred_languages = parse_locale("fr,en");
+        let language_codes = HashMap::from([(1, "en"), (2, "fr"), (3, "de")]);
+        let rank = |language_ids: &[u32]| {
+            get_language_rank(
+                &filter(1, "", None, language_ids),
+                &preferred_languages,
+                &language_codes,
+            )
+        };
+
+        assert_eq!(rank(&[2]), 0);
+        assert_eq!(rank(&[3, 1]), 1);
+        assert_eq!(rank(&[1, 2]), 0);
+        // Lists without a language come between matching and non matching lists.
+        assert_eq!(rank(&[]), 2);
+        assert_eq!(rank(&[3]), 3);
+        assert_eq!(rank(&[42]), 3);
+    }
+
+    #[test]
+    fn test_get_text_rank() {
+        let easylist = filter(1, "EasyList", Some("Removes ADS from webpages"), &[]);
+        let undescribed = filter(2, "Undescribed", None, &[]);
+
+        assert_eq!(get_text_rank(&easylist, "easy"), Some(0));
+        assert_eq!(get_text_rank(&easylist, "ads"), Some(1));
+        assert_eq!(get_text_rank(&easylist, "tracker"), None);
+        assert_eq!(get_text_rank(&easylist, ""), Some(0));
+        assert_eq!(get_text_rank(&undescribed, "ads"), None);
+    }
+
+    #[test]
+    fn test_paginate() {
+        let results = || 1..=25;
+
+        assert_eq!(paginate(results(), 1, 10), ((1..=10).collect(), 1, 10));
+        assert_eq!(paginate(results(), 3, 10), ((21..=25).collect(), 3, 10));
+        assert_eq!(paginate(results(), 4, 10), (Vec::new(), 4, 10));
+        assert_eq!(paginate(results(), 0, 10), ((1..=10).collect(), 1, 10));
+        assert_eq!(paginate(results(), 2, 0), (vec![2], 2, 1));
+        assert_eq!(
+            paginate(1..=200, 1, 1000),
+            (
+                (1..=MAX_RESULTS_PER_PAGE).collect(),
+                1,
+                MAX_RESULTS_PER_PAGE
+            )
+        );
+        assert_eq!(
+            paginate(results(), usize::MAX, usize::MAX),
+            (Vec::new(), usize::MAX, MAX_RESULTS_PER_PAGE)
+        );
+    }
+}
@@ -1,30 +1,190 @@
 use super::get_error_response;
-use crate::configuration::{calc_filter_filename, Configuration, Filter, FilterGroup};
+use crate::configuration::{
+    calc_filter_filename, get_filter_changes, Configuration, Filter, FilterConversion,
+    FilterFormat, FilterGroup, FilterUpdateSender,
+};
 use crate::web_gui::ApiError;
-use serde::Deserialize;
+use filterlists_api::FilterListsClient;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
 
 use std::{convert::Infallible, sync::Arc};
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::mpsc::Sender;
 use url::Url;
 use warp::http::Response;
+use warp::ws::{Message, WebSocket};
 use warp::Filter as RouteFilter;
 
 use warp::filters::BoxedFilter;
 #[derive(Debug, Deserialize)]
 pub struct FilterStatusChangeRequest {
     enabled: bool,
+    /// Left untouched when missing.
+    observe: Option<bool>,
     file_name: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct UpdateFiltersQuery {
+    /// Accepts a filter losing most of its rules, only applies to updates of a single filter.
+    #[serde(default)]
+    accept_shrinking: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct FilterResponse {
+    #[serde(flatten)]
+    filter: Filter,
+    /// Error the last update of the filter failed with, its last good version is in use.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    update_error: Option<String>,
+    /// How the filter was converted, when it isn't in Adblock Plus syntax.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conversion: Option<FilterConversion>,
+}
+
+/// A FilterLists list a filter depends upon.
+#[derive(Debug, Serialize)]
+struct FilterDependency {
+    filterlists_id: u32,
+    title: String,
+    url: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct AddFilterResponse {
+    /// Lists the filter depends upon which aren't configured, for the user to add them as well.
+    missing_dependencies: Vec<FilterDependency>,
+    /// Titles of the configured filters the filter includes, which it makes redundant.
+    included_filters: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PinFilterRequest {
+    version: String,
+}
+
 #[serde_as]
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
 pub struct FilterRequest {
     pub enabled: bool,
     pub title: String,
     pub group: FilterGroup,
+    /// URL of the filter, local filters can only be set in the configuration file
     #[serde_as(as = "DisplayFromStr")]
     pub url: Url,
+    #[serde(default)]
+    pub observe: bool,
+    #[serde(default)]
+    pub format: FilterFormat,
+    /// Identifier of the filter in FilterLists, its mirrors and segments are added along with it
+    #[serde(default)]
+    pub filterlists_id: Option<u32>,
+}
+
+/// Returns the URL of the first segment of a FilterLists list, mirrors aside.
+fn get_primary_view_url(filter_details: &filterlists_api::FilterDetails) -> Option<&str> {
+    filter_details
+        .view_urls
+        .iter()
+        .min_by_key(|view_url| (view_url.segment_number, view_url.primariness))
+        .map(|view_url| view_url.url.as_str())
+}
+
+/// Fills in the mirrors and segments of a filter added from FilterLists, and finds out how it
+/// relates to the configured filters.
+async fn resolve_filterlists_metadata(
+    filter: &mut Filter,
+    filterlists_id: u32,
+    configuration: &Configuration,
+    filterlists_client: &FilterListsClient,
+) -> AddFilterResponse {
+    let filter_details = match filterlists_client
+        .get_filter_information(filterlists_api::FilterArgs::U32(filterlists_id))
+        .await
+    {
+        Ok(filter_details) => filter_details,
+        Err(err) => {
+            log::warn!(
+                "Unable to get the details of filter {}: {}",
+                filterlists_id,
+                err
+            );
+            return AddFilterResponse::default();
+        }
+    };
+
+    filter.set_view_urls(&filter_details.view_urls);
+
+    let is_configured = |filterlists_id: u32, url: Option<&str>| {
+        configuration.filters.iter().any(|filter| {
+            filter.filterlists_id == Some(filterlists_id)
+                || Some(filter.url.as_str()) == url
+                || filter
+                    .mirrors
+                    .iter()
+                    .any(|mirror| Some(mirror.as_str()) == url)
+        })
+    };
+
+    let dependencies = futures::future::join_all(
+        filter_details
+            .dependency_filter_list_ids
+            .iter()
+            .filter(|dependency_id| !is_configured(**dependency_id, None))
+            .map(|dependency_id| {
+                filterlists_client
+                    .get_filter_information(filterlists_api::FilterArgs::U32(*dependency_id))
+            }),
+    )
+    .await;
+
+    let mut missing_dependencies = Vec::new();
+
+    for dependency in dependencies {
+        let dependency = match dependency {
+            Ok(dependency) => dependency,
+            Err(err) => {
+                log::warn!(
+                    "Unable to get a dependency of filter {}: {}",
+                    filterlists_id,
+                    err
+                );
+                continue;
+            }
+        };
+
+        match get_primary_view_url(&dependency) {
+            Some(url) if !is_configured(dependency.id, Some(url)) => {
+                missing_dependencies.push(FilterDependency {
+                    filterlists_id: dependency.id,
+                    title: dependency.name.clone(),
+                    url: url.to_string(),
+                })
+            }
+            _ => {}
+        }
+    }
+
+    let included_filters = configuration
+        .filters
+        .iter()
+        .filter(|filter| {
+            filter.filterlists_id.is_some_and(|filterlists_id| {
+                filter_details
+                    .includes_filter_list_ids
+                    .contains(&filterlists_id)
+            })
+        })
+        .map(|filter| filter.title.clone())
+        .collect();
+
+    AddFilterResponse {
+        missing_dependencies,
+        included_filters,
+    }
 }
 
 async fn change_filter_status(
@@ -42,7 +202,7 @@ async fn change_filter_status(
 
     for filter in filter_status_change_request {
         if let Err(err) = configuration
-            .set_filter_enabled_status(&filter.file_name, filter.enabled)
+            .set_filter_status(&filter.file_name, filter.enabled, filter.observe)
             .await
         {
             log::error!("Failed to change filter status: {err}");
@@ -71,7 +231,14 @@ async fn get_filters_configuration() -> Result<impl warp::Reply, Infallible> {
         }
     };
 
-    let filters = configuration.filters;
+    let mut filters = Vec::with_capacity(configuration.filters.len());
+    for filter in configuration.filters {
+        filters.push(FilterResponse {
+            update_error: filter.get_update_error().await,
+            conversion: filter.get_conversion().await,
+            filter,
+        });
+    }
     log::debug!("Filters: {:?}", filters);
     Ok(Response::builder()
         .header(http::header::CONTENT_TYPE, "application/json")
@@ -82,6 +249,7 @@ async fn get_filters_configuration() -> Result<impl warp::Reply, Infallible> {
 async fn add_filter(
     filter_request: FilterRequest,
     http_client: reqwest::Client,
+    filterlists_client: FilterListsClient,
     configuration_updater_sender: Sender<Configuration>,
     configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
 ) -> Result<impl warp::Reply, Infallible> {
@@ -94,13 +262,31 @@ async fn add_filter(
         }
     };
 
+    // Any website can reach the API, it must not be able to get local files read.
+    if !matches!(filter_request.url.scheme(), "http" | "https") {
+        log::warn!("Refusing to add filter with URL {}", filter_request.url);
+        return Ok(Response::builder()
+            .status(http::StatusCode::BAD_REQUEST)
+            .body(
+                serde_json::to_string(&ApiError {
+                    error: format!(
+                        "Only http and https filters can be added, local filters are set in the \
+                         configuration file: {}",
+                        filter_request.url
+                    ),
+                })
+                .unwrap(),
+            )
+            .unwrap());
+    }
+
     // Clone the URL to avoid moving the original value
     let filter_url = filter_request.url.clone();
-    if configuration
-        .filters
-        .iter()
-        .any(|filter| filter.url == filter_request.url)
-    {
+    if configuration.filters.iter().any(|filter| {
+        filter.url == filter_request.url
+            || (filter.filterlists_id.is_some()
+                && filter.filterlists_id == filter_request.filterlists_id)
+    }) {
         log::warn!("Filter with URL {} already exists", filter_request.url);
         return Ok(Response::builder()
             .status(http::StatusCode::CONFLICT)
@@ -120,6 +306,25 @@ async fn add_filter(
         title: filter_request.title.clone(),
         group: filter_request.group,
         file_name: calc_filter_filename(&filter_request.url.to_string()),
+        observe: filter_request.observe,
+        format: filter_request.format,
+        pinned_version: None,
+        mirrors: Vec::new(),
+        segments: Vec::new(),
+        filterlists_id: filter_request.filterlists_id,
+    };
+
+    let add_filter_response = match filter_request.filterlists_id {
+        Some(filterlists_id) => {
+            resolve_filterlists_metadata(
+                &mut new_filter,
+                filterlists_id,
+                &configuration,
+                &filterlists_client,
+            )
+            .await
+        }
+        None => AddFilterResponse::default(),
     };
 
     match configuration
@@ -155,7 +360,7 @@ async fn add_filter(
 
     Ok(Response::builder()
         .status(http::StatusCode::CREATED)
-        .body("".to_string())
+        .body(serde_json::to_string(&add_filter_response).unwrap())
         .unwrap())
 }
 
@@ -197,13 +402,306 @@ async fn delete_filter(
         .unwrap())
 }
 
+fn get_filter_not_found_response(file_name: &str) -> Response<String> {
+    Response::builder()
+        .status(http::StatusCode::NOT_FOUND)
+        .body(
+            serde_json::to_string(&ApiError {
+                error: format!("Filter {} not found", file_name),
+            })
+            .unwrap(),
+        )
+        .unwrap()
+}
+
+async fn get_changes(file_name: String) -> Result<impl warp::Reply, Infallible> {
+    let configuration = match Configuration::read_from_home().await {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            log::error!("Failed to get filter changes: {err}");
+            return Ok(get_error_response(err));
+        }
+    };
+
+    // Only configured filters have a history, this also keeps the file name from reaching
+    // outside of the filters directory.
+    if !configuration
+        .filters
+        .iter()
+        .any(|filter| filter.file_name == file_name)
+    {
+        return Ok(get_filter_not_found_response(&file_name));
+    }
+
+    match get_filter_changes(&file_name).await {
+        Ok(changes) => Ok(Response::builder()
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(&changes).unwrap())
+            .unwrap()),
+        Err(err) => {
+            log::error!("Failed to get filter changes: {err}");
+            Ok(get_error_response(err))
+        }
+    }
+}
+
+async fn pin_filter(
+    file_name: String,
+    pin_filter_request: PinFilterRequest,
+    configuration_updater_sender: Sender<Configuration>,
+    configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let _guard = configuration_save_lock.lock().await;
+
+    let mut configuration = match Configuration::read_from_home().await {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            log::error!("Failed to pin filter: {err}");
+            return Ok(get_error_response(err));
+        }
+    };
+
+    if !configuration
+        .filters
+        .iter()
+        .any(|filter| filter.file_name == file_name)
+    {
+        return Ok(get_filter_not_found_response(&file_name));
+    }
+
+    if let Err(err) = configuration
+        .pin_filter(&file_name, &pin_filter_request.version)
+        .await
+    {
+        log::error!("Failed to pin filter: {err}");
+        return Ok(get_error_response(err));
+    }
+
+    configuration_updater_sender
+        .send(configuration.clone())
+        .await
+        .unwrap();
+
+    Ok(Response::builder()
+        .status(http::StatusCode::ACCEPTED)
+        .body("".to_string())
+        .unwrap())
+}
+
+async fn unpin_filter(
+    file_name: String,
+    http_client: reqwest::Client,
+    filter_updates: FilterUpdateSender,
+    configuration_updater_sender: Sender<Configuration>,
+    configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let _guard = configuration_save_lock.lock().await;
+
+    let mut configuration = match Configuration::read_from_home().await {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            log::error!("Failed to unpin filter: {err}");
+            return Ok(get_error_response(err));
+        }
+    };
+
+    if !configuration
+        .filters
+        .iter()
+        .any(|filter| filter.file_name == file_name)
+    {
+        return Ok(get_filter_not_found_response(&file_name));
+    }
+
+    if let Err(err) = configuration
+        .unpin_filter(&file_name, &http_client, &filter_updates)
+        .await
+    {
+        log::error!("Failed to unpin filter: {err}");
+        return Ok(get_error_response(err));
+    }
+
+    configuration_updater_sender
+        .send(configuration.clone())
+        .await
+        .unwrap();
+
+    Ok(Response::builder()
+        .status(http::StatusCode::ACCEPTED)
+        .body("".to_string())
+        .unwrap())
+}
+
+/// Updates all the filters, or only `file_name`, reporting progress to the updates websocket.
+async fn update_filters(
+    file_name: Option<String>,
+    update_filters_query: UpdateFiltersQuery,
+    http_client: reqwest::Client,
+    filter_updates: FilterUpdateSender,
+    configuration_updater_sender: Sender<Configuration>,
+    configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let mut configuration = match Configuration::read_from_home().await {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            log::error!("Failed to update filters: {err}");
+            return Ok(get_error_response(err));
+        }
+    };
+
+    if let Some(file_name) = &file_name {
+        match configuration
+            .filters
+            .iter()
+            .find(|filter| &filter.file_name == file_name)
+        {
+            None => return Ok(get_filter_not_found_response(file_name)),
+            Some(filter) if filter.pinned_version.is_some() => {
+                return Ok(Response::builder()
+                    .status(http::StatusCode::CONFLICT)
+                    .body(
+                        serde_json::to_string(&ApiError {
+                            error: format!("Filter {} is pinned", file_name),
+                        })
+                        .unwrap(),
+                    )
+                    .unwrap())
+            }
+            Some(_) => {}
+        }
+    }
+
+    // Updates take a while, their progress is reported to the updates websocket.
+    tokio::spawn(async move {
+        let result = match &file_name {
+            Some(file_name) => {
+                configuration
+                    .update_filter(
+                        file_name,
+                        &http_client,
+                        &filter_updates,
+                        update_filters_query.accept_shrinking,
+                    )
+                    .await
+            }
+            None => {
+                configuration
+                    .update_filters(&http_client, &filter_updates)
+                    .await
+            }
+        };
+
+        if let Err(err) = result {
+            log::error!("Failed to update filters: {err}");
+        }
+
+        // The configuration may have changed during the update, the current one is applied.
+        let guard = configuration_save_lock.lock().await;
+        match Configuration::read_from_home().await {
+            Ok(configuration) => configuration_updater_sender
+                .send(configuration)
+                .await
+                .unwrap(),
+            Err(err) => log::error!("Failed to apply updated filters: {err}"),
+        }
+        drop(guard);
+    });
+
+    Ok(Response::builder()
+        .status(http::StatusCode::ACCEPTED)
+        .body("".to_string())
+        .unwrap())
+}
+
+async fn filter_updates(websocket: WebSocket, filter_updates: FilterUpdateSender) {
+    let mut filter_updates_receiver = filter_updates.subscribe();
+
+    let (mut tx, mut rx) = websocket.split();
+
+    // To handle Ping / Pong messages
+    tokio::spawn(async move { while let Some(_message) = rx.next().await {} });
+
+    loop {
+        let event = match filter_updates_receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(skipped)) => {
+                log::warn!("Skipped {} filter update events", skipped);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        let message = Message::text(serde_json::to_string(&event).unwrap());
+
+        if let Err(_err) = tx.send(message).await {
+            break;
+        }
+    }
+}
+
 pub(super) fn create_routes(
     configuration_updater_sender: Sender<Configuration>,
     configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
     http_client: reqwest::Client,
+    filterlists_client: FilterListsClient,
+    filter_updates: FilterUpdateSender,
 ) -> BoxedFilter<(impl warp::Reply,)> {
-    warp::get()
-        .and_then(self::get_filters_configuration)
+    let updates_route = warp::path!("updates")
+        .and(warp::ws())
+        .and(super::with_filter_updates(filter_updates.clone()))
+        .map(|ws: warp::ws::Ws, filter_updates: FilterUpdateSender| {
+            ws.on_upgrade(move |websocket| self::filter_updates(websocket, filter_updates))
+        });
+
+    let update_route = warp::post()
+        .and(
+            warp::path!("update")
+                .map(|| None)
+                .or(warp::path!(String / "update").map(Some))
+                .unify(),
+        )
+        .and(warp::query::<UpdateFiltersQuery>())
+        .and(super::with_http_client(http_client.clone()))
+        .and(super::with_filter_updates(filter_updates.clone()))
+        .and(super::with_configuration_updater_sender(
+            configuration_updater_sender.clone(),
+        ))
+        .and(super::with_configuration_save_lock(
+            configuration_save_lock.clone(),
+        ))
+        .and_then(self::update_filters);
+
+    let changes_route = warp::get()
+        .and(warp::path!(String / "changes"))
+        .and_then(self::get_changes);
+
+    let pin_route = warp::put()
+        .and(warp::path!(String / "pin"))
+        .and(warp::body::json())
+        .and(super::with_configuration_updater_sender(
+            configuration_updater_sender.clone(),
+        ))
+        .and(super::with_configuration_save_lock(
+            configuration_save_lock.clone(),
+        ))
+        .and_then(self::pin_filter)
+        .or(warp::delete()
+            .and(warp::path!(String / "pin"))
+            .and(super::with_http_client(http_client.clone()))
+            .and(super::with_filter_updates(filter_updates.clone()))
+            .and(super::with_configuration_updater_sender(
+                configuration_updater_sender.clone(),
+            ))
+            .and(super::with_configuration_save_lock(
+                configuration_save_lock.clone(),
+            ))
+            .and_then(self::unpin_filter));
+
+    updates_route
+        .or(update_route)
+        .or(changes_route)
+        .or(pin_route)
+        .or(warp::get().and_then(self::get_filters_configuration))
         .or(warp::put()
             .and(warp::body::json())
             .and(super::with_configuration_updater_sender(
@@ -216,6 +714,7 @@ pub(super) fn create_routes(
         .or(warp::post()
             .and(warp::body::json())
             .and(super::with_http_client(http_client.clone()))
+            .and(super::with_filterlists_client(filterlists_client))
             .and(super::with_configuration_updater_sender(
                 configuration_updater_sender.clone(),
             ))
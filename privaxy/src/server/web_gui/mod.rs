@@ -1,8 +1,14 @@
+use crate::filter_lint::EnabledRulesStore;
 use crate::proxy::exclusions::LocalExclusionStore;
 use crate::statistics::Statistics;
 use crate::WEBAPP_FRONTEND_DIR;
-use crate::{blocker::BlockingDisabledStore, configuration::Configuration};
+use crate::{
+    blocker::BlockingDisabledStore,
+    configuration::{get_filterlists_cache_directory, Configuration, FilterUpdateSender},
+};
+use filterlists_api::FilterListsClient;
 use serde::Serialize;
+use std::env;
 use std::sync::Arc;
 use tokio::sync::Notify;
 use tokio::sync::{broadcast, mpsc::Sender};
@@ -15,6 +21,7 @@ pub(crate) mod blocking_enabled;
 pub(crate) mod custom_filters;
 pub(crate) mod events;
 pub(crate) mod exclusions;
+mod export;
 mod filterlists;
 pub(crate) mod filters;
 pub(crate) mod settings;
@@ -24,15 +31,22 @@ pub(crate) mod statistics;
 pub(crate) struct ApiError {
     error: String,
 }
-pub(crate) fn get_frontend(
-    events_sender: broadcast::Sender<events::Event>,
-    statistics: Statistics,
-    blocking_disabled_store: &BlockingDisabledStore,
-    configuration_updater_sender: &Sender<Configuration>,
-    configuration_save_lock: &Arc<tokio::sync::Mutex<()>>,
-    local_exclusions_store: &LocalExclusionStore,
-    notify_reload: Arc<Notify>,
-) -> BoxedFilter<(impl warp::Reply,)> {
+
+/// State the routes of the web interface work with, kept across restarts of the web server.
+#[derive(Clone)]
+pub(crate) struct FrontendState {
+    pub(crate) events_sender: broadcast::Sender<events::Event>,
+    pub(crate) statistics: Statistics,
+    pub(crate) blocking_disabled_store: BlockingDisabledStore,
+    pub(crate) configuration_updater_sender: Sender<Configuration>,
+    pub(crate) configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
+    pub(crate) local_exclusions_store: LocalExclusionStore,
+    pub(crate) notify_reload: Arc<Notify>,
+    pub(crate) filter_updates: FilterUpdateSender,
+    pub(crate) enabled_rules: EnabledRulesStore,
+}
+
+pub(crate) fn get_frontend(frontend_state: FrontendState) -> BoxedFilter<(impl warp::Reply,)> {
     let static_files_routes = create_static_routes();
 
     let cors = warp::cors()
@@ -45,21 +59,31 @@ pub(crate) fn get_frontend(
         ]);
 
     let http_client = reqwest::Client::new();
+    let filterlists_client = create_filterlists_client();
 
-    let api_routes = create_api_routes(
-        events_sender,
-        statistics,
-        blocking_disabled_store,
-        configuration_updater_sender,
-        configuration_save_lock,
-        local_exclusions_store,
-        http_client,
-        notify_reload,
-    );
+    let api_routes = create_api_routes(frontend_state, http_client, filterlists_client);
 
     api_routes.or(static_files_routes).with(cors).boxed()
 }
 
+/// The API can be pointed at a mirror of FilterLists through `PRIVAXY_FILTERLISTS_API_URL`.
+fn create_filterlists_client() -> FilterListsClient {
+    let filterlists_client = match env::var("PRIVAXY_FILTERLISTS_API_URL") {
+        Ok(base_url) => FilterListsClient::new(base_url),
+        Err(_) => FilterListsClient::default(),
+    };
+
+    match get_filterlists_cache_directory() {
+        Ok(cache_directory) => {
+            filterlists_client.with_cache(cache_directory, filterlists_api::DEFAULT_CACHE_TTL)
+        }
+        Err(err) => {
+            log::warn!("Not caching FilterLists responses: {}", err);
+            filterlists_client
+        }
+    }
+}
+
 fn create_static_routes() -> BoxedFilter<(impl warp::Reply,)> {
     warp::get()
         .and(warp::path::tail())
@@ -84,15 +108,21 @@ fn create_static_routes() -> BoxedFilter<(impl warp::Reply,)> {
 }
 
 fn create_api_routes(
-    events_sender: broadcast::Sender<events::Event>,
-    statistics: Statistics,
-    blocking_disabled_store: &BlockingDisabledStore,
-    configuration_updater_sender: &Sender<Configuration>,
-    configuration_save_lock: &Arc<tokio::sync::Mutex<()>>,
-    local_exclusions_store: &LocalExclusionStore,
+    frontend_state: FrontendState,
     http_client: reqwest::Client,
-    notify_reload: Arc<Notify>,
+    filterlists_client: FilterListsClient,
 ) -> BoxedFilter<(impl Reply,)> {
+    let FrontendState {
+        events_sender,
+        statistics,
+        blocking_disabled_store,
+        configuration_updater_sender,
+        configuration_save_lock,
+        local_exclusions_store,
+        notify_reload,
+        filter_updates,
+        enabled_rules,
+    } = frontend_state;
     let def_headers =
         warp::filters::reply::default_header(http::header::CONTENT_TYPE, "application/json");
     let api_path = warp::path("api");
@@ -115,11 +145,14 @@ fn create_api_routes(
         configuration_updater_sender.clone(),
         configuration_save_lock.clone(),
         http_client.clone(),
+        filterlists_client.clone(),
+        filter_updates,
     ));
 
     let custom_filters_route = warp::path("custom-filters").and(custom_filters::create_routes(
         configuration_updater_sender.clone(),
         configuration_save_lock.clone(),
+        enabled_rules,
     ));
 
     let exclusions_route = warp::path("exclusions").and(exclusions::create_routes(
@@ -138,9 +171,16 @@ fn create_api_routes(
         blocking_enabled::create_routes(blocking_disabled_store.clone()),
     );
 
+    let blocking_observe_route = warp::path("blocking-observe").and(
+        blocking_enabled::create_observe_routes(blocking_disabled_store.clone()),
+    );
+
+    let export_route = warp::path("export").and(export::create_routes());
+
     let options_route = warp::options().map(|| "");
 
-    let filterlists_route = warp::path("filterlists").and(filterlists::create_routes());
+    let filterlists_route =
+        warp::path("filterlists").and(filterlists::create_routes(filterlists_client));
 
     let not_found = warp::path::tail()
         .map(move |tail: Tail| {
@@ -166,9 +206,11 @@ fn create_api_routes(
                 .or(custom_filters_route)
                 .or(exclusions_route)
                 .or(blocking_enabled_route)
+                .or(blocking_observe_route)
                 .or(settings_route)
                 .or(options_route)
                 .or(filterlists_route)
+                .or(export_route)
                 .or(not_found),
         )
         .with(def_headers)
@@ -206,6 +248,24 @@ pub(self) fn with_http_client(
     warp::any().map(move || http_client.clone())
 }
 
+fn with_filterlists_client(
+    filterlists_client: FilterListsClient,
+) -> impl Filter<Extract = (FilterListsClient,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || filterlists_client.clone())
+}
+
+fn with_filter_updates(
+    filter_updates: FilterUpdateSender,
+) -> impl Filter<Extract = (FilterUpdateSender,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || filter_updates.clone())
+}
+
+fn with_enabled_rules(
+    enabled_rules: EnabledRulesStore,
+) -> impl Filter<Extract = (EnabledRulesStore,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || enabled_rules.clone())
+}
+
 pub(self) fn with_notify_reload(
     notify_reload: Arc<Notify>,
 ) -> impl Filter<Extract = (Arc<Notify>,), Error = std::convert::Infallible> + Clone {
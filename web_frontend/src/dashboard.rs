@@ -14,6 +14,7 @@ pub struct Message {
     proxied_requests: Option<u64>,
     blocked_requests: Option<u64>,
     modified_responses: Option<u64>,
+    procedural_filters_applied: Option<u64>,
     #[serde(with = "tuple_vec_map")]
     top_blocked_paths: Vec<(String, u64)>,
     #[serde(with = "tuple_vec_map")]
@@ -102,6 +103,7 @@ impl Component for Dashboard {
                 proxied_requests: None,
                 blocked_requests: None,
                 modified_responses: None,
+                procedural_filters_applied: None,
                 top_blocked_paths: Vec::new(),
                 top_clients: Vec::new(),
             },
@@ -161,7 +163,7 @@ impl Component for Dashboard {
                 </div>
 
                 <dl
-                    class="mt-5 grid grid-cols-1 rounded-lg bg-white overflow-hidden shadow divide-y divide-gray-200 md:grid-cols-3 md:divide-y-0 md:divide-x">
+                    class="mt-5 grid grid-cols-1 rounded-lg bg-white overflow-hidden shadow divide-y divide-gray-200 md:grid-cols-4 md:divide-y-0 md:divide-x">
                     <div class="px-4 py-5 sm:p-6">
                         <dt class="text-base font-normal text-gray-900">
                             {"Proxied requests"}
@@ -194,6 +196,17 @@ impl Component for Dashboard {
                             </div>
                         </dd>
                     </div>
+
+                    <div class="px-4 py-5 sm:p-6">
+                        <dt class="text-base font-normal text-gray-900">
+                            {"Procedural filters applied"}
+                        </dt>
+                        <dd class="mt-1 flex justify-between items-baseline md:block lg:flex">
+                            <div class="flex items-baseline text-2xl font-semibold text-blue-600">
+                                { some_or_loading(self.message.procedural_filters_applied) }
+                            </div>
+                        </dd>
+                    </div>
                 </dl>
                 <div class="mt-4 lg:grid lg:gap-y-4 lg:gap-x-8 lg:grid-cols-2">
                     <div class="mt-4 bg-white overflow-hidden shadow rounded-lg divide-y divide-gray-200">
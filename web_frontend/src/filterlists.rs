@@ -1,10 +1,14 @@
 use crate::button;
 use crate::button::{ButtonColor, ButtonState, PrivaxyButton};
-use crate::filters::{AddFilterRequest, Filter, FilterConfiguration, FilterGroup};
+use crate::filters::{
+    AddFilterRequest, AddFilterResponse, Filter, FilterConfiguration, FilterGroup,
+};
 use crate::save_button::BASE_BUTTON_CSS;
 use crate::{save_button, submit_banner};
 use filterlists_api;
+use gloo_timers::callback::Timeout;
 use reqwasm::http::Request;
+use serde::Deserialize;
 use url::Url;
 use wasm_bindgen_futures::spawn_local;
 use web_sys::HtmlInputElement;
@@ -18,7 +22,9 @@ pub enum SearchFilterMessage {
     AddFilter(filterlists_api::Filter),
     RemoveFilter(filterlists_api::Filter),
     LoadFilters,
-    FiltersLoaded(Vec<filterlists_api::Filter>),
+    ToggleByLanguage,
+    Search,
+    FiltersLoaded(u32, FilterSearchResponse),
     Error(String),
     NextPage,
     PreviousPage,
@@ -32,7 +38,13 @@ pub struct SearchFilterList {
     is_open: bool,
     filters: Vec<filterlists_api::Filter>,
     filter_query: String,
-    loading: bool,
+    /// Ranks lists by the languages of the browser.
+    by_language: bool,
+    /// Number of lists matching the search, across all pages.
+    total: usize,
+    search_timeout: Option<Timeout>,
+    /// Identifies the latest search, so that results of earlier ones are discarded.
+    search_generation: u32,
     languages: Vec<filterlists_api::FilterLanguage>,
     licenses: Vec<filterlists_api::FilterLicense>,
     tags: Vec<filterlists_api::FilterTag>,
@@ -41,6 +53,71 @@ pub struct SearchFilterList {
     active_filters: FilterConfiguration,
 }
 
+/// A page of the lists matching a search, as returned by `/api/filterlists/search`.
+#[derive(Deserialize)]
+pub struct FilterSearchResponse {
+    filters: Vec<filterlists_api::Filter>,
+    total: usize,
+}
+
+/// Offers to add the lists an added filter depends upon.
+async fn add_dependencies(title: &str, group: FilterGroup, add_filter_response: AddFilterResponse) {
+    if !add_filter_response.included_filters.is_empty() {
+        log::info!(
+            "{} includes {}, they can be disabled",
+            title,
+            add_filter_response.included_filters.join(", ")
+        );
+    }
+
+    if add_filter_response.missing_dependencies.is_empty() {
+        return;
+    }
+
+    let message = format!(
+        "{} depends on {}. Add them as well?",
+        title,
+        add_filter_response
+            .missing_dependencies
+            .iter()
+            .map(|dependency| dependency.title.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    if !gloo_utils::window()
+        .confirm_with_message(&message)
+        .unwrap_or(false)
+    {
+        return;
+    }
+
+    for dependency in add_filter_response.missing_dependencies {
+        let url = match Url::parse(&dependency.url) {
+            Ok(url) => url,
+            Err(err) => {
+                log::error!("Failed to parse URL: {}", err);
+                continue;
+            }
+        };
+
+        let request_body = AddFilterRequest::new(dependency.title, group, url)
+            .with_filterlists_id(dependency.filterlists_id);
+        let request = Request::post("/api/filters")
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&request_body).unwrap());
+
+        match request.send().await {
+            Ok(response) if response.ok() => log::info!("Dependency added successfully"),
+            Ok(response) => log::error!("Failed to add dependency: {:?}", response.status()),
+            Err(err) => log::error!("Request error: {:?}", err),
+        }
+    }
+}
+
+/// Delay after the last keystroke before the lists get searched.
+const SEARCH_DELAY_MS: u32 = 300;
+
 const FILTER_TAG_GROUPS: [&'static str; 4] = ["ads", "privacy", "malware", "social"];
 
 #[derive(Properties, PartialEq)]
@@ -61,7 +138,10 @@ impl Component for SearchFilterList {
             licenses: Vec::<filterlists_api::FilterLicense>::new(),
             tags: Vec::<filterlists_api::FilterTag>::new(),
             filter_query: String::new(),
-            loading: true,
+            by_language: false,
+            total: 0,
+            search_timeout: None,
+            search_generation: 0,
             current_page: 1,
             results_per_page: 10,
             active_filters: _ctx.props().filter_configuration.clone(),
@@ -75,7 +155,61 @@ impl Component for SearchFilterList {
                 self.link.send_message(SearchFilterMessage::LoadFilters);
             }
             SearchFilterMessage::Close => self.is_open = false,
-            SearchFilterMessage::FilterChanged(query) => self.filter_query = query,
+            SearchFilterMessage::FilterChanged(query) => {
+                self.filter_query = query;
+                self.current_page = 1;
+
+                let link = self.link.clone();
+                self.search_timeout = Some(Timeout::new(SEARCH_DELAY_MS, move || {
+                    link.send_message(SearchFilterMessage::Search)
+                }));
+            }
+            SearchFilterMessage::ToggleByLanguage => {
+                self.by_language = !self.by_language;
+                self.current_page = 1;
+                self.link.send_message(SearchFilterMessage::Search);
+            }
+            SearchFilterMessage::Search => {
+                // Searching right away supersedes any pending search.
+                self.search_timeout = None;
+                self.search_generation += 1;
+
+                let search_generation = self.search_generation;
+                let mut query = url::form_urlencoded::Serializer::new(String::new());
+                query
+                    .append_pair("query", &self.filter_query)
+                    .append_pair("page", &self.current_page.to_string())
+                    .append_pair("per_page", &self.results_per_page.to_string());
+                if self.by_language {
+                    query.append_pair("by_language", "true");
+                }
+
+                let url = format!("/api/filterlists/search?{}", query.finish());
+                let link = self.link.clone();
+                spawn_local(async move {
+                    match Request::get(&url).send().await {
+                        Ok(response) => {
+                            if response.ok() {
+                                if let Ok(search_response) =
+                                    response.json::<FilterSearchResponse>().await
+                                {
+                                    link.send_message(SearchFilterMessage::FiltersLoaded(
+                                        search_generation,
+                                        search_response,
+                                    ))
+                                }
+                            } else {
+                                log::error!("Failed to search filters: {:?}", response.status());
+                                link.send_message(SearchFilterMessage::Error(
+                                    response.status().to_string(),
+                                ))
+                            }
+                        }
+                        Err(err) => link.send_message(SearchFilterMessage::Error(err.to_string())),
+                    }
+                });
+                return false;
+            }
             SearchFilterMessage::AddFilter(filter) => {
                 let parsed_url =
                     match Url::parse(&filter.primary_view_url.clone().unwrap_or_default()) {
@@ -104,7 +238,8 @@ impl Component for SearchFilterList {
                     .unwrap_or(FilterGroup::Regional);
 
                 let request_body: AddFilterRequest =
-                    AddFilterRequest::new(filter.name.clone(), group, parsed_url);
+                    AddFilterRequest::new(filter.name.clone(), group, parsed_url)
+                        .with_filterlists_id(filter.id);
                 let request = Request::post("/api/filters")
                     .header("Content-Type", "application/json")
                     .body(serde_json::to_string(&request_body).unwrap());
@@ -113,11 +248,19 @@ impl Component for SearchFilterList {
                     FilterGroup::Malware,
                     "".to_string(),
                 ));
+                let filter_name = filter.name.clone();
                 spawn_local(async move {
                     match request.send().await {
                         Ok(response) => {
                             if response.ok() {
                                 log::info!("Filter added successfully");
+
+                                if let Ok(add_filter_response) =
+                                    response.json::<AddFilterResponse>().await
+                                {
+                                    add_dependencies(&filter_name, group, add_filter_response)
+                                        .await;
+                                }
                             } else {
                                 log::error!("Failed to add filter: {:?}", response.status());
                             }
@@ -157,31 +300,11 @@ impl Component for SearchFilterList {
                 })
             }
             SearchFilterMessage::LoadFilters => {
-                if self.loading {
+                self.link.send_message(SearchFilterMessage::Search);
+
+                if self.languages.is_empty() {
                     let link = self.link.clone();
                     spawn_local(async move {
-                        let request = Request::get("/api/filterlists/list");
-                        match request.send().await {
-                            Ok(response) => {
-                                if response.ok() {
-                                    if let Ok(filters) =
-                                        response.json::<Vec<filterlists_api::Filter>>().await
-                                    {
-                                        link.send_message(SearchFilterMessage::FiltersLoaded(
-                                            filters,
-                                        ))
-                                    }
-                                } else {
-                                    log::error!("Failed to load filters: {:?}", response.status());
-                                    link.send_message(SearchFilterMessage::Error(
-                                        response.status().to_string(),
-                                    ))
-                                }
-                            }
-                            Err(err) => {
-                                link.send_message(SearchFilterMessage::Error(err.to_string()))
-                            }
-                        }
                         let request = Request::get("/api/filterlists/languages");
                         match request.send().await {
                             Ok(response) => {
@@ -253,10 +376,13 @@ impl Component for SearchFilterList {
                     });
                 }
             }
-            SearchFilterMessage::FiltersLoaded(filters) => {
-                log::info!("Filters loaded successfully");
-                self.filters = filters.clone();
-                self.loading = false;
+            SearchFilterMessage::FiltersLoaded(search_generation, search_response) => {
+                if search_generation != self.search_generation {
+                    return false;
+                }
+
+                self.filters = search_response.filters;
+                self.total = search_response.total;
             }
             SearchFilterMessage::LanguagesLoaded(langs) => {
                 log::info!("Languages loaded successfully");
@@ -272,18 +398,19 @@ impl Component for SearchFilterList {
             }
             SearchFilterMessage::Error(error) => {
                 log::error!("Error loading filters: {}", error.to_string());
-                self.loading = false;
             }
             SearchFilterMessage::NextPage => {
                 if self.current_page
-                    < (self.filters.len() as f64 / self.results_per_page as f64).ceil() as usize
+                    < (self.total as f64 / self.results_per_page as f64).ceil() as usize
                 {
                     self.current_page += 1;
+                    self.link.send_message(SearchFilterMessage::Search);
                 }
             }
             SearchFilterMessage::PreviousPage => {
                 if self.current_page > 1 {
                     self.current_page -= 1;
+                    self.link.send_message(SearchFilterMessage::Search);
                 }
             }
         }
@@ -291,23 +418,7 @@ impl Component for SearchFilterList {
     }
 
     fn view(&self, _ctx: &Context<Self>) -> Html {
-        let filtered_filters: Vec<&filterlists_api::Filter> = self
-            .filters
-            .iter()
-            .filter(|filter| {
-                filter
-                    .name
-                    .to_lowercase()
-                    .contains(&self.filter_query.to_lowercase())
-            })
-            .collect();
-        let total_pages =
-            (filtered_filters.len() as f64 / self.results_per_page as f64).ceil() as usize;
-        let start_index = (self.current_page - 1) * self.results_per_page;
-        let paginated_filters = filtered_filters
-            .into_iter()
-            .skip(start_index)
-            .take(self.results_per_page);
+        let total_pages = (self.total as f64 / self.results_per_page as f64).ceil() as usize;
 
         let prev_button = html! {
         <PrivaxyButton
@@ -320,7 +431,7 @@ impl Component for SearchFilterList {
         let next_button = html! {
         <PrivaxyButton
             color={ButtonColor::Gray}
-            state={if self.current_page == total_pages {ButtonState::Disabled} else {ButtonState::Enabled}}
+            state={if self.current_page >= total_pages {ButtonState::Disabled} else {ButtonState::Enabled}}
             onclick={self.link.callback(|_| SearchFilterMessage::NextPage)}
             button_text={"Next"}
         />
@@ -374,6 +485,14 @@ impl Component for SearchFilterList {
                                             SearchFilterMessage::FilterChanged(input.value())
                                         })}
                                     />
+                                    <label class="flex items-center space-x-2 text-sm text-gray-700"
+                                        title="Lists for your languages come first, then the ones listed on FilterLists the longest">
+                                        <input type="checkbox" class="h-4 w-4 rounded border-gray-300"
+                                            checked={self.by_language}
+                                            onchange={self.link.callback(|_| SearchFilterMessage::ToggleByLanguage)}
+                                        />
+                                        <span>{"My languages first"}</span>
+                                    </label>
                                     <div style="flex-grow: 1; overflow: auto;">
                                         <table class="table-fixed bg-white">
                                             <thead>
@@ -386,7 +505,7 @@ impl Component for SearchFilterList {
                                                 </tr>
                                             </thead>
                                             <tbody>
-                                                { for paginated_filters.map(|filter| self.view_filter_row(filter, _ctx)) }
+                                                { for self.filters.iter().map(|filter| self.view_filter_row(filter, _ctx)) }
                                             </tbody>
                                         </table>
                                     </div>
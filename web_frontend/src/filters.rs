@@ -1,11 +1,15 @@
 use crate::button::ButtonState;
 use crate::filterlists::SearchFilterList;
 use crate::{save_button, submit_banner};
+use futures::future::{AbortHandle, Abortable};
+use futures::StreamExt;
 use reqwasm::http::Request;
+use reqwasm::websocket::futures::WebSocket;
 use serde::{Deserialize, Serialize};
 use serde_json::de::IoRead;
 use serde_json::StreamDeserializer;
 use serde_with::{serde_as, DisplayFromStr};
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::io::Cursor;
 use url::Url;
@@ -71,6 +75,8 @@ pub struct AddFilterRequest {
     group: FilterGroup,
     #[serde_as(as = "DisplayFromStr")]
     url: Url,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filterlists_id: Option<u32>,
 }
 
 impl AddFilterRequest {
@@ -80,8 +86,31 @@ impl AddFilterRequest {
             title,
             group,
             url,
+            filterlists_id: None,
         }
     }
+
+    /// Lets the server add the mirrors and segments FilterLists knows of.
+    pub fn with_filterlists_id(mut self, filterlists_id: u32) -> Self {
+        self.filterlists_id = Some(filterlists_id);
+        self
+    }
+}
+
+/// A FilterLists list an added filter depends upon.
+#[derive(Deserialize)]
+pub struct FilterDependency {
+    pub filterlists_id: u32,
+    pub title: String,
+    pub url: String,
+}
+
+#[derive(Deserialize)]
+pub struct AddFilterResponse {
+    #[serde(default)]
+    pub missing_dependencies: Vec<FilterDependency>,
+    #[serde(default)]
+    pub included_filters: Vec<String>,
 }
 
 pub struct AddFilterComponent {
@@ -123,6 +152,7 @@ impl Component for AddFilterComponent {
                         },
                         group: category,
                         url: parsed_url,
+                        filterlists_id: None,
                     };
 
                     let request = Request::post("/api/filters")
@@ -261,6 +291,7 @@ impl Component for AddFilterComponent {
                                         <input
                                             type="text"
                                             class="flex-1 bg-white border border-gray-300 text-gray-700 py-2 px-4 rounded leading-tight focus:outline-none focus:bg-white focus:border-gray-500"
+                                            placeholder="https://example.com/list.txt"
                                             value={self.url.clone()}
                                             oninput={_ctx.link().callback(|e: InputEvent| {
                                                 let input = e.target_dyn_into::<HtmlInputElement>().expect("event target should be an input element");
@@ -290,6 +321,12 @@ pub struct Filter {
     pub title: String,
     group: FilterGroup,
     file_name: String,
+    #[serde(default)]
+    observe: bool,
+    #[serde(default)]
+    update_error: Option<String>,
+    #[serde(default)]
+    url: String,
 }
 
 impl Filter {
@@ -299,8 +336,15 @@ impl Filter {
             title,
             group,
             file_name,
+            observe: false,
+            update_error: None,
+            url: String::new(),
         }
     }
+
+    fn is_local(&self) -> bool {
+        self.url.starts_with("file:")
+    }
 }
 
 impl std::fmt::Display for Filter {
@@ -317,24 +361,42 @@ impl std::fmt::Display for Filter {
 #[derive(Serialize)]
 pub struct FilterStatusChangeRequest {
     enabled: bool,
+    observe: bool,
     file_name: String,
 }
 
 pub type FilterConfiguration = Vec<Filter>;
 
+/// Progress of a filter update, as streamed by the server.
+#[derive(Deserialize)]
+pub struct FilterUpdateEvent {
+    #[serde(default)]
+    file_name: Option<String>,
+    status: String,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
 pub enum Message {
     Load,
     Display(FilterConfiguration),
     UpdateFilterSelection((String, bool)),
+    UpdateFilterObserve((String, bool)),
     Save,
     ChangesSaved,
     AckChanges,
+    UpdateNow,
+    FilterUpdate(FilterUpdateEvent),
 }
 
 pub struct Filters {
     filter_configuration: Option<FilterConfiguration>,
     filter_configuration_before_changes: Option<FilterConfiguration>,
     changes_saved: bool,
+    is_updating: bool,
+    /// Progress of the ongoing update, by filter file name.
+    update_statuses: HashMap<String, String>,
+    updates_ws_abort_handle: AbortHandle,
 }
 
 impl Filters {
@@ -361,10 +423,39 @@ impl Component for Filters {
     fn create(ctx: &Context<Self>) -> Self {
         ctx.link().send_message(Message::Load);
 
+        let message_callback = ctx
+            .link()
+            .callback(|event: FilterUpdateEvent| Message::FilterUpdate(event));
+
+        let ws = WebSocket::open("/api/filters/updates").unwrap();
+        let (_write, mut read) = ws.split();
+
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        let future = Abortable::new(
+            async move {
+                while let Some(Ok(msg)) = read.next().await {
+                    if let reqwasm::websocket::Message::Text(s) = msg {
+                        match serde_json::from_str::<FilterUpdateEvent>(&s) {
+                            Ok(event) => message_callback.emit(event),
+                            Err(err) => log::error!("Failed to parse filter update: {:?}", err),
+                        }
+                    }
+                }
+            },
+            abort_registration,
+        );
+
+        spawn_local(async {
+            let _result = future.await;
+        });
+
         Self {
             filter_configuration: None,
             filter_configuration_before_changes: None,
             changes_saved: false,
+            is_updating: false,
+            update_statuses: HashMap::new(),
+            updates_ws_abort_handle: abort_handle,
         }
     }
 
@@ -413,6 +504,7 @@ impl Component for Filters {
                     .iter()
                     .map(|filter| FilterStatusChangeRequest {
                         enabled: filter.enabled,
+                        observe: filter.observe,
                         file_name: filter.file_name.clone(),
                     })
                     .collect::<Vec<_>>();
@@ -452,11 +544,63 @@ impl Component for Filters {
                         Some(filter)
                     });
             }
+            Message::UpdateFilterObserve((filter_name, observe)) => {
+                self.changes_saved = false;
+
+                if let Some(filter) = self
+                    .filter_configuration
+                    .as_mut()
+                    .unwrap()
+                    .iter_mut()
+                    .find(|filter| filter.file_name == filter_name)
+                {
+                    filter.observe = observe;
+                }
+            }
             Message::ChangesSaved => {
                 self.changes_saved = true;
                 self.filter_configuration_before_changes = self.filter_configuration.clone();
             }
             Message::AckChanges => self.changes_saved = false,
+            Message::UpdateNow => {
+                self.is_updating = true;
+                self.update_statuses.clear();
+
+                let request = Request::post("/api/filters/update");
+
+                spawn_local(async move {
+                    match request.send().await {
+                        Ok(response) if !response.ok() => {
+                            log::error!("Failed to update filters: {:?}", response.status())
+                        }
+                        Ok(_) => {}
+                        Err(err) => log::error!("Request error: {:?}", err),
+                    }
+                });
+            }
+            Message::FilterUpdate(event) => match event.file_name {
+                Some(file_name) => {
+                    let status = match (event.status.as_str(), event.reason) {
+                        ("downloading", _) => "Updating...".to_string(),
+                        ("unchanged", _) => "Up to date".to_string(),
+                        ("updated", _) => "Updated".to_string(),
+                        ("failed", Some(reason)) => format!("Update failed: {}", reason),
+                        (status, _) => status.to_string(),
+                    };
+
+                    self.update_statuses.insert(file_name, status);
+                }
+                // The engine was rebuilt, concluding the update. Filters are reloaded for their
+                // update errors, unless that would discard unsaved changes.
+                None if self.is_updating => {
+                    self.is_updating = false;
+
+                    if !self.configuration_has_changed() {
+                        ctx.link().send_message(Message::Load);
+                    }
+                }
+                None => return false,
+            },
         };
 
         true
@@ -476,8 +620,20 @@ impl Component for Filters {
             .callback(|(filter_file_name, enabled): (String, bool)| {
                 Message::UpdateFilterSelection((filter_file_name, enabled))
             });
+        let observe_callback =
+            ctx.link()
+                .callback(|(filter_file_name, observe): (String, bool)| {
+                    Message::UpdateFilterObserve((filter_file_name, observe))
+                });
         log::debug!("Retrieved callback.");
         let save_callback = ctx.link().callback(|_| Message::Save);
+        let update_now_callback = ctx.link().callback(|_| Message::UpdateNow);
+        let mut update_now_classes = crate::button::get_css(crate::button::ButtonColor::Gray);
+        if self.is_updating {
+            update_now_classes.push("opacity-50");
+            update_now_classes.push("cursor-not-allowed");
+        }
+        let update_statuses = &self.update_statuses;
         let render_category_filter = |filter: &Filter| {
             let filter_file_name = filter.file_name.clone();
             let filter_enabled = filter.enabled;
@@ -486,11 +642,43 @@ impl Component for Filters {
             let checkbox_callback = Callback::from(move |_| {
                 callback_clone.emit((filter_file_name.to_string(), !filter_enabled))
             });
+
+            let filter_file_name = filter.file_name.clone();
+            let filter_observe = filter.observe;
+            let observe_callback_clone = observe_callback.clone();
+
+            let observe_checkbox_callback = Callback::from(move |_| {
+                observe_callback_clone.emit((filter_file_name.to_string(), !filter_observe))
+            });
             log::debug!("Returning category filter.");
             html! {
             <div class="relative flex items-start py-4">
                 <div class="min-w-0 flex-1 text-sm">
                     <label for={filter.file_name.clone()} class="select-none">{&filter.title}</label>
+                    { if filter.is_local() {
+                        html! {
+                            <span class="ml-2 inline-flex items-center px-2 py-0.5 rounded text-xs font-medium bg-gray-100 text-gray-800">{"local"}</span>
+                        }
+                    } else {
+                        html! {}
+                    }}
+                    { match update_statuses.get(&filter.file_name) {
+                        Some(status) => html! {
+                            <p class="text-gray-500">{status}</p>
+                        },
+                        None => html! {},
+                    }}
+                    { match &filter.update_error {
+                        Some(update_error) => html! {
+                            <p class="text-red-600">{format!("Last update failed: {}", update_error)}</p>
+                        },
+                        None => html! {},
+                    }}
+                </div>
+                <div class="ml-3 flex items-center h-5 text-sm text-gray-500">
+                    <label class="select-none mr-2">{"Observe only"}</label>
+                    <input checked={filter.observe} onchange={observe_checkbox_callback} type="checkbox"
+                        class="focus:ring-yellow-500 h-4 w-4 text-yellow-600 border-gray-300 rounded" />
                 </div>
                 <div class="ml-3 flex items-center h-5">
                     <input checked={filter.enabled} onchange={checkbox_callback} name={filter.file_name.clone()} type="checkbox"
@@ -561,6 +749,11 @@ impl Component for Filters {
                                 <AddFilterComponent state={save_button::SaveButtonState::Enabled}/>
                                 <SearchFilterList filter_configuration={filter_configuration.clone()}/>
                                 {save_button!(save_callback, save_button_state)}
+                                <div class="mt-5">
+                                    <button onclick={update_now_callback} disabled={self.is_updating} class={update_now_classes}>
+                                        { if self.is_updating { "Updating..." } else { "Update now" } }
+                                    </button>
+                                </div>
                             </div>
                             { render_category(FilterGroup::Default, filter_configuration) }
                             { render_category(FilterGroup::Ads, filter_configuration) }
@@ -576,4 +769,8 @@ impl Component for Filters {
             None => html! {{ title }},
         }
     }
+
+    fn destroy(&mut self, _ctx: &Context<Self>) {
+        self.updates_ws_abort_handle.abort()
+    }
 }
@@ -14,6 +14,7 @@ mod save_button;
 mod settings;
 mod settings_textarea;
 mod submit_banner;
+mod subscribe;
 
 #[derive(Debug, Deserialize, Clone)]
 pub(crate) struct ApiError {
@@ -28,6 +29,8 @@ enum Route {
     Requests,
     #[at("/settings/:s")]
     Settings,
+    #[at("/subscribe")]
+    Subscribe,
     #[not_found]
     #[at("/404")]
     NotFound,
@@ -111,6 +114,10 @@ fn switch(route: &Route) -> Html {
         Route::Settings => {
             html! {<>{navigation} <div class={"container mt-4 mb-10 mx-auto px-4 sm:px-6 lg:px-8"}> <Switch<settings::SettingsRoute> render={Switch::render(settings::switch_settings)} /> </div> </>}
         }
+        Route::Subscribe => {
+            set_title("Subscribe");
+            html! { <>{navigation} <div class={"container mt-4 mb-10 mx-auto px-4 sm:px-6 lg:px-8"}> <subscribe::Subscribe /> </div></> }
+        }
         Route::NotFound => {
             set_title("Not Found");
             html! { <>{navigation} <NotFound /></> }
@@ -13,6 +13,8 @@ pub struct Message {
     method: String,
     url: String,
     is_request_blocked: bool,
+    #[serde(default)]
+    would_be_blocked: bool,
 }
 
 pub struct Requests {
@@ -72,6 +74,8 @@ impl Component for Requests {
             let background = {
                 if element.is_request_blocked {
                     "bg-red-50"
+                } else if element.would_be_blocked {
+                    "bg-yellow-50"
                 } else {
                     ""
                 }
@@ -88,7 +88,9 @@ pub fn switch_settings(route: &SettingsRoute) -> Html {
 
             let textarea_description = "Insert one filter per line";
 
-            html! {<SettingsTextarea h1="Custom Filters" {description} input_name="custom_filters" {textarea_description} {resource_url} />}
+            let lint_url = Some("/api/custom-filters/lint".to_string());
+
+            html! {<SettingsTextarea h1="Custom Filters" {description} input_name="custom_filters" {textarea_description} {resource_url} {lint_url} />}
         }
     };
 
@@ -1,7 +1,9 @@
 use crate::save_button;
 use crate::submit_banner;
 use crate::success_banner;
+use gloo_timers::callback::Timeout;
 use reqwasm::http::Request;
+use serde::Deserialize;
 use wasm_bindgen_futures::spawn_local;
 use web_sys::HtmlInputElement;
 use yew::virtual_dom::VNode;
@@ -14,6 +16,21 @@ pub struct Props {
     pub input_name: String,
     pub textarea_description: String,
     pub resource_url: String,
+    /// Endpoint returning the diagnostics of the input, shown under the textarea.
+    #[prop_or_default]
+    pub lint_url: Option<String>,
+}
+
+/// Delay after the last keystroke before the input gets linted.
+const LINT_DELAY_MS: u32 = 500;
+
+#[derive(Deserialize, PartialEq)]
+pub struct FilterDiagnostic {
+    line: usize,
+    rule: String,
+    kind: Option<String>,
+    severity: String,
+    message: String,
 }
 
 pub struct SettingsTextarea {
@@ -21,6 +38,11 @@ pub struct SettingsTextarea {
     changes_saved: bool,
     input_data: String,
     previous_input_data: String,
+    diagnostics: Vec<FilterDiagnostic>,
+    lint_timeout: Option<Timeout>,
+    /// Identifies the latest request for diagnostics, so that responses to earlier ones are
+    /// discarded.
+    lint_generation: u32,
 }
 
 pub enum Message {
@@ -30,6 +52,8 @@ pub enum Message {
     Save,
     Saved,
     AckChanges,
+    Lint,
+    Linted(u32, Vec<FilterDiagnostic>),
 }
 
 impl Component for SettingsTextarea {
@@ -44,6 +68,9 @@ impl Component for SettingsTextarea {
             input_data: String::new(),
             previous_input_data: String::new(),
             changes_saved: false,
+            diagnostics: Vec::new(),
+            lint_timeout: None,
+            lint_generation: 0,
         }
     }
 
@@ -54,6 +81,13 @@ impl Component for SettingsTextarea {
                 self.is_save_button_enabled = true;
 
                 self.input_data = input_value;
+
+                if ctx.props().lint_url.is_some() {
+                    let link = ctx.link().clone();
+                    self.lint_timeout = Some(Timeout::new(LINT_DELAY_MS, move || {
+                        link.send_message(Message::Lint)
+                    }));
+                }
             }
             Message::Save => {
                 if !self.is_save_button_enabled {
@@ -64,10 +98,22 @@ impl Component for SettingsTextarea {
                     .header("Content-Type", "application/json")
                     .body(&serde_json::to_string(&self.input_data).unwrap());
 
+                self.lint_generation += 1;
+
+                let lint_generation = self.lint_generation;
+                let has_diagnostics = ctx.props().lint_url.is_some();
+                let message_callback = ctx.link().callback(|message: Message| message);
+
                 spawn_local(async move {
                     if let Ok(response) = request.send().await {
                         // Todo: Handle errors
-                        if response.ok() {}
+                        if response.ok() && has_diagnostics {
+                            if let Ok(diagnostics) = response.json::<Vec<FilterDiagnostic>>().await
+                            {
+                                message_callback
+                                    .emit(Message::Linted(lint_generation, diagnostics));
+                            }
+                        }
                     }
                 });
 
@@ -102,6 +148,44 @@ impl Component for SettingsTextarea {
             Message::UpdatePreviousInputData => {
                 self.previous_input_data = self.input_data.clone();
             }
+            Message::Lint => {
+                self.lint_timeout = None;
+
+                let lint_url = match &ctx.props().lint_url {
+                    Some(lint_url) => lint_url,
+                    None => return false,
+                };
+
+                self.lint_generation += 1;
+
+                let lint_generation = self.lint_generation;
+                let request = Request::post(lint_url)
+                    .header("Content-Type", "application/json")
+                    .body(serde_json::to_string(&self.input_data).unwrap());
+
+                let message_callback = ctx.link().callback(|message: Message| message);
+
+                spawn_local(async move {
+                    if let Ok(response) = request.send().await {
+                        if response.ok() {
+                            if let Ok(diagnostics) = response.json::<Vec<FilterDiagnostic>>().await
+                            {
+                                message_callback
+                                    .emit(Message::Linted(lint_generation, diagnostics));
+                            }
+                        }
+                    }
+                });
+
+                return false;
+            }
+            Message::Linted(lint_generation, diagnostics) => {
+                if lint_generation != self.lint_generation {
+                    return false;
+                }
+
+                self.diagnostics = diagnostics;
+            }
         }
         true
     }
@@ -151,6 +235,37 @@ impl Component for SettingsTextarea {
 
         let onclick = ctx.link().callback(|_| Message::Save);
 
+        let diagnostics = if self.diagnostics.is_empty() {
+            html! {}
+        } else {
+            let diagnostics = self
+                .diagnostics
+                .iter()
+                .map(|diagnostic| {
+                    let color = if diagnostic.severity == "error" {
+                        "text-red-700"
+                    } else {
+                        "text-yellow-700"
+                    };
+                    let kind = match &diagnostic.kind {
+                        Some(kind) => format!(" ({kind})"),
+                        None => String::new(),
+                    };
+
+                    html! {
+                        <li class={color}>
+                            <span class="font-medium">{format!("Line {}{}: {}", diagnostic.line, kind, diagnostic.message)}</span>
+                            <code class="ml-2 text-gray-600 break-all">{&diagnostic.rule}</code>
+                        </li>
+                    }
+                })
+                .collect::<Html>();
+
+            html! {
+                <ul class="mt-2 space-y-1 text-sm">{diagnostics}</ul>
+            }
+        };
+
         let props = ctx.props();
 
         html! {
@@ -167,6 +282,7 @@ impl Component for SettingsTextarea {
                 <div class="mt-1">
                     <textarea {oninput} value={self.input_data.clone()} rows="8" name={props.input_name.clone()} id={props.input_name.clone()} class="shadow-sm focus:ring-blue-500 focus:border-blue-500 block w-full sm:text-sm border-gray-300 rounded-md"></textarea>
                 </div>
+                {diagnostics}
             </div>
             <save_button::SaveButton state={button_state} {onclick} />
             </>
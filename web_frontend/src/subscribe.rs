@@ -0,0 +1,207 @@
+use crate::filters::{AddFilterRequest, FilterGroup};
+use crate::ApiError;
+use reqwasm::http::Request;
+use url::Url;
+use wasm_bindgen_futures::spawn_local;
+use web_sys::{HtmlInputElement, HtmlSelectElement};
+use yew::prelude::*;
+
+pub enum Message {
+    TitleChanged(String),
+    GroupChanged(FilterGroup),
+    Subscribe,
+    Subscribed,
+    Error(String),
+}
+
+enum SubscriptionState {
+    Pending,
+    Subscribing,
+    Subscribed,
+    Failed(String),
+}
+
+/// Confirmation of a subscription to a list, reached through `/subscribe?location=...&title=...`
+/// links such as the `abp:subscribe` links the proxy hands over.
+pub struct Subscribe {
+    location: Option<Url>,
+    title: String,
+    group: FilterGroup,
+    state: SubscriptionState,
+}
+
+/// Returns the list location and title of the subscription link the page was opened with.
+fn get_subscription() -> (Option<Url>, String) {
+    let search = gloo_utils::window().location().search().unwrap_or_default();
+
+    let mut location = None;
+    let mut title = String::new();
+
+    for (key, value) in url::form_urlencoded::parse(search.trim_start_matches('?').as_bytes()) {
+        match key.as_ref() {
+            "location" => location = Url::parse(&value).ok(),
+            "title" => title = value.into_owned(),
+            _ => {}
+        }
+    }
+
+    // Websites shouldn't be able to get local files read.
+    let location =
+        location.filter(|location| location.scheme() == "http" || location.scheme() == "https");
+
+    if title.is_empty() {
+        if let Some(location) = &location {
+            title = location.to_string();
+        }
+    }
+
+    (location, title)
+}
+
+impl Component for Subscribe {
+    type Message = Message;
+    type Properties = ();
+
+    fn create(_ctx: &Context<Self>) -> Self {
+        let (location, title) = get_subscription();
+
+        Self {
+            location,
+            title,
+            group: FilterGroup::Ads,
+            state: SubscriptionState::Pending,
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Message::TitleChanged(title) => self.title = title,
+            Message::GroupChanged(group) => self.group = group,
+            Message::Subscribe => {
+                let location = match &self.location {
+                    Some(location) => location.clone(),
+                    None => return false,
+                };
+
+                let request_body = AddFilterRequest::new(self.title.clone(), self.group, location);
+                let request = Request::post("/api/filters")
+                    .header("Content-Type", "application/json")
+                    .body(serde_json::to_string(&request_body).unwrap());
+
+                let link = ctx.link().clone();
+                spawn_local(async move {
+                    match request.send().await {
+                        Ok(response) if response.ok() => link.send_message(Message::Subscribed),
+                        Ok(response) => {
+                            let error = match response.json::<ApiError>().await {
+                                Ok(api_error) => api_error.error,
+                                Err(_) => format!("Failed to add filter: {}", response.status()),
+                            };
+                            link.send_message(Message::Error(error))
+                        }
+                        Err(err) => link.send_message(Message::Error(err.to_string())),
+                    }
+                });
+
+                self.state = SubscriptionState::Subscribing;
+            }
+            Message::Subscribed => self.state = SubscriptionState::Subscribed,
+            Message::Error(error) => {
+                log::error!("Failed to subscribe: {}", error);
+                self.state = SubscriptionState::Failed(error);
+            }
+        }
+        true
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let location = match &self.location {
+            Some(location) => location,
+            None => {
+                return html! {
+                    <div class="bg-white shadow sm:rounded-lg p-6">
+                        <h1 class="text-lg font-medium text-gray-900">{"Invalid subscription link"}</h1>
+                        <p class="mt-2 text-sm text-gray-500">{"The link doesn't point to a list that can be subscribed to."}</p>
+                    </div>
+                };
+            }
+        };
+
+        let options: Html = FilterGroup::values()
+            .into_iter()
+            .map(|group| {
+                html! {
+                    <option value={group.as_str()} selected={group == self.group}>{group.as_str()}</option>
+                }
+            })
+            .collect();
+
+        let status = match &self.state {
+            SubscriptionState::Pending => html! {},
+            SubscriptionState::Subscribing => html! {
+                <p class="text-sm text-gray-500">{"Adding the list..."}</p>
+            },
+            SubscriptionState::Subscribed => html! {
+                <p class="text-sm text-green-700">
+                    {"The list was added. "}
+                    <a class="underline" href="/settings/filters">{"Go to filters"}</a>
+                </p>
+            },
+            SubscriptionState::Failed(error) => html! {
+                <p class="text-sm text-red-700">{error}</p>
+            },
+        };
+
+        let is_disabled = matches!(
+            self.state,
+            SubscriptionState::Subscribing | SubscriptionState::Subscribed
+        );
+
+        html! {
+            <div class="bg-white shadow sm:rounded-lg p-6">
+                <h1 class="text-lg font-medium text-gray-900">{"Subscribe to a filter list"}</h1>
+                <p class="mt-2 text-sm text-gray-500 break-all">{location.to_string()}</p>
+                <div class="mt-5 flex flex-col space-y-4">
+                    <div class="flex items-center">
+                        <div class="w-32">
+                            <label class="font-bold">{"Title"}</label>
+                        </div>
+                        <input
+                            type="text"
+                            class="flex-1 bg-white border border-gray-300 text-gray-700 py-2 px-4 rounded leading-tight focus:outline-none focus:bg-white focus:border-gray-500"
+                            value={self.title.clone()}
+                            oninput={ctx.link().callback(|e: InputEvent| {
+                                let input = e.target_dyn_into::<HtmlInputElement>().expect("event target should be an input element");
+                                Message::TitleChanged(input.value())
+                            })}
+                        />
+                    </div>
+                    <div class="flex items-center">
+                        <div class="w-32">
+                            <label class="font-bold">{"Category"}</label>
+                        </div>
+                        <select class="flex-1 bg-white border border-gray-300 text-gray-700 py-2 px-4 pr-8 rounded leading-tight focus:outline-none focus:bg-white focus:border-gray-500"
+                            onchange={ctx.link().callback(|e: Event| {
+                                let select = e.target_dyn_into::<HtmlSelectElement>().expect("event target should be a select element");
+                                let value = select.value();
+                                Message::GroupChanged(FilterGroup::values().into_iter().find(|group| group.as_str() == value).expect("invalid category"))
+                            })}
+                        >
+                            { options }
+                        </select>
+                    </div>
+                    { status }
+                    <div class="flex space-x-4">
+                        <button
+                            onclick={ctx.link().callback(|_| Message::Subscribe)}
+                            disabled={is_disabled}
+                            class={classes!("bg-blue-500", "hover:bg-blue-700", "text-white", "font-bold", "py-2", "px-4", "rounded", is_disabled.then_some("opacity-50"))}
+                        >
+                            {"Subscribe"}
+                        </button>
+                    </div>
+                </div>
+            </div>
+        }
+    }
+}